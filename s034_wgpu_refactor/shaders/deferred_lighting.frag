@@ -0,0 +1,224 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+// must be same as renderer::deferred::DeferredRaw
+layout(set = 1, binding = 0) uniform DeferredParams {
+    float u_channel;
+    float u_zfar;
+};
+layout(set = 1, binding = 1) uniform texture2D t_albedo;
+layout(set = 1, binding = 2) uniform texture2D t_normal;
+layout(set = 1, binding = 3) uniform texture2D t_material;
+layout(set = 1, binding = 4) uniform texture2D t_depth;
+layout(set = 1, binding = 5) uniform sampler s_gbuffer;
+
+// must be same as renderer::deferred::GBufferChannel
+const float CHANNEL_LIT = 0.0;
+const float CHANNEL_ALBEDO = 1.0;
+const float CHANNEL_NORMAL = 2.0;
+const float CHANNEL_MATERIAL = 3.0;
+const float CHANNEL_DEPTH = 4.0;
+
+// must be same as renderer::light::LightKind
+const float LIGHT_DIRECTIONAL = 0.0;
+const float LIGHT_POINT = 1.0;
+const float LIGHT_SPOT = 2.0;
+
+struct Light {
+    vec4 position;  // xyz: position, w: light type
+    vec4 direction; // xyz: direction, w: attenuation radius
+    vec4 color;     // rgb: color, a: intensity
+    vec4 cone;      // x: cos(inner angle), y: cos(outer angle)
+};
+
+layout(set = 2, binding = 0) uniform LightInfo {
+    uint u_num_lights;
+};
+
+layout(set = 2, binding = 1) readonly buffer Lights {
+    Light s_lights[];
+};
+
+// must be same as renderer::shadow::ShadowRaw
+struct Shadow {
+    uvec4 layers; // x: first layer, y: layer count (0: no shadow), z: pcf radius
+    vec4 params;  // x: bias, y: slope bias, z: texel size
+    vec4 splits;  // far view distance of each cascade
+};
+
+layout(set = 2, binding = 2) readonly buffer Shadows {
+    Shadow s_shadows[];
+};
+
+layout(set = 2, binding = 3) readonly buffer ShadowLayers {
+    mat4 s_shadow_layers[];
+};
+
+layout(set = 2, binding = 4) uniform texture2DArray t_shadow;
+layout(set = 2, binding = 5) uniform samplerShadow s_shadow;
+
+// reconstructed from the depth
+vec3 world_position;
+
+// smoothly reaches zero at the radius. no falloff if radius <= 0
+float range_attenuation(float distance, float radius) {
+    if (radius <= 0.0) {
+        return 1.0;
+    }
+    float ratio = distance / radius;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
+float sample_shadow_map(uint layer, float bias, int pcf_radius, float texel_size) {
+    vec4 homogeneous_coords = s_shadow_layers[layer] * vec4(world_position, 1.0);
+    if (homogeneous_coords.w <= 0.0) {
+        return 1.0;
+    }
+    vec3 light_local = homogeneous_coords.xyz / homogeneous_coords.w;
+    // compensate for the Y-flip difference between the NDC and texture coordinates
+    vec2 uv = light_local.xy * vec2(0.5, -0.5) + 0.5;
+    if (light_local.z > 1.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+    // each lookup is already 2x2 filtered by the comparison sampler
+    float visibility = 0.0;
+    for (int y = -pcf_radius; y <= pcf_radius; ++y) {
+        for (int x = -pcf_radius; x <= pcf_radius; ++x) {
+            vec2 offset = vec2(x, y) * texel_size;
+            visibility += texture(
+                sampler2DArrayShadow(t_shadow, s_shadow),
+                vec4(uv + offset, float(layer), light_local.z - bias)
+            );
+        }
+    }
+    float kernel_size = float(2 * pcf_radius + 1);
+    return visibility / (kernel_size * kernel_size);
+}
+
+float fetch_shadow(uint light_id, float n_dot_l) {
+    Shadow shadow = s_shadows[light_id];
+    uint count = shadow.layers.y;
+    if (count == 0) {
+        return 1.0;
+    }
+    uint layer = shadow.layers.x;
+    float light_type = s_lights[light_id].position.w;
+    if (light_type == LIGHT_DIRECTIONAL) {
+        // pick the cascade by the view distance
+        float depth = -(u_view_matrix * vec4(world_position, 1.0)).z;
+        uint cascade = 0;
+        while (cascade < count && depth > shadow.splits[cascade]) {
+            ++cascade;
+        }
+        if (cascade == count) {
+            return 1.0;
+        }
+        layer += cascade;
+    } else if (light_type == LIGHT_POINT) {
+        // pick the cube face (+x, -x, +y, -y, +z, -z) by the major axis
+        vec3 d = world_position - s_lights[light_id].position.xyz;
+        vec3 a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            layer += d.x > 0.0 ? 0 : 1;
+        } else if (a.y >= a.z) {
+            layer += d.y > 0.0 ? 2 : 3;
+        } else {
+            layer += d.z > 0.0 ? 4 : 5;
+        }
+    }
+    float bias = max(shadow.params.y * (1.0 - n_dot_l), shadow.params.x);
+    return sample_shadow_map(layer, bias, int(shadow.layers.z), shadow.params.z);
+}
+
+// must be same as renderer::deferred::decode_normal
+vec3 decode_normal(vec2 e) {
+    vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+    float t = max(-n.z, 0.0);
+    n.x -= n.x >= 0.0 ? t : -t;
+    n.y -= n.y >= 0.0 ? t : -t;
+    return normalize(n);
+}
+
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(sampler2D(t_depth, s_gbuffer), coords, 0).r;
+    vec4 albedo = texelFetch(sampler2D(t_albedo, s_gbuffer), coords, 0);
+    vec4 material = texelFetch(sampler2D(t_material, s_gbuffer), coords, 0);
+    vec3 normal = decode_normal(texelFetch(sampler2D(t_normal, s_gbuffer), coords, 0).xy);
+
+    // nothing is drawn. the skybox is drawn here later
+    if (albedo.a == 0.0) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec2 ndc = vec2(v_tex_coords.x * 2.0 - 1.0, 1.0 - v_tex_coords.y * 2.0);
+    vec4 view_position = inverse(u_proj_matrix) * vec4(ndc, depth, 1.0);
+    vec3 position = view_position.xyz / view_position.w;
+    world_position = (inverse(u_view_matrix) * vec4(position, 1.0)).xyz;
+
+    if (u_channel == CHANNEL_ALBEDO) {
+        f_color = vec4(albedo.rgb, 1.0);
+        return;
+    } else if (u_channel == CHANNEL_NORMAL) {
+        f_color = vec4(normal * 0.5 + 0.5, 1.0);
+        return;
+    } else if (u_channel == CHANNEL_MATERIAL) {
+        f_color = vec4(material.rgb, 1.0);
+        return;
+    } else if (u_channel == CHANNEL_DEPTH) {
+        f_color = vec4(vec3(-position.z / u_zfar), 1.0);
+        return;
+    }
+
+    float specular = material.x;
+    float shininess = material.y * 256.0;
+    float ambient_strength = material.z;
+
+    // same as shader.frag but in view space
+    vec3 view_dir = normalize(-position);
+    vec3 ambient_color = vec3(0.0);
+    vec3 diffuse_color = vec3(0.0);
+    vec3 specular_color = vec3(0.0);
+
+    for (uint i = 0; i < u_num_lights; ++i) {
+        Light light = s_lights[i];
+        vec3 light_color = light.color.rgb * light.color.a;
+        ambient_color += light_color * ambient_strength;
+
+        vec3 light_dir;
+        float attenuation = 1.0;
+        if (light.position.w == LIGHT_DIRECTIONAL) {
+            light_dir = -normalize(mat3(u_view_matrix) * light.direction.xyz);
+        } else {
+            vec3 to_light = (u_view_matrix * vec4(light.position.xyz, 1.0)).xyz - position;
+            float distance = length(to_light);
+            light_dir = to_light / max(distance, 0.0001);
+            attenuation = range_attenuation(distance, light.direction.w);
+            if (light.position.w == LIGHT_SPOT) {
+                float cos_theta = dot(-light_dir, normalize(mat3(u_view_matrix) * light.direction.xyz));
+                attenuation *= smoothstep(light.cone.y, light.cone.x, cos_theta);
+            }
+        }
+
+        float diffuse_strength = max(dot(normal, light_dir), 0.0);
+        attenuation *= fetch_shadow(i, diffuse_strength);
+        diffuse_color += light_color * diffuse_strength * attenuation;
+
+        vec3 half_dir = normalize(view_dir + light_dir);
+        float specular_strength = pow(max(dot(normal, half_dir), 0.0), shininess) * specular;
+        specular_color += light_color * specular_strength * attenuation;
+    }
+
+    f_color = vec4((ambient_color + diffuse_color + specular_color) * albedo.rgb, 1.0);
+}
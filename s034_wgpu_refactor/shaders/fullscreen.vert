@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) out vec2 v_tex_coords;
+
+void main() {
+    // fullscreen triangle. texture coordinates start at the top left
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    v_tex_coords = position * vec2(0.5, -0.5) + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,47 @@
+#version 450
+
+#define USE_NORMAL_MAP
+
+layout(location = 0) in vec2 v_tex_coords;
+#ifdef USE_NORMAL_MAP
+layout(location = 1) in mat3 v_tangent_matrix;
+#else
+layout(location = 1) in vec3 v_normal;
+#endif
+
+// must be same as renderer::deferred::GBufferFormats
+layout(location = 0) out vec4 f_albedo;
+layout(location = 1) out vec4 f_normal;
+layout(location = 2) out vec4 f_material;
+
+layout(set = 3, binding = 0) uniform texture2D t_diffuse;
+layout(set = 3, binding = 1) uniform sampler s_diffuse;
+layout(set = 3, binding = 2) uniform texture2D t_normal;
+layout(set = 3, binding = 3) uniform sampler s_normal;
+
+// must be same as renderer::deferred::encode_normal
+vec2 encode_normal(vec3 n) {
+    n /= abs(n.x) + abs(n.y) + abs(n.z);
+    if (n.z >= 0.0) {
+        return n.xy;
+    }
+    vec2 sign_not_zero = vec2(n.x >= 0.0 ? 1.0 : -1.0, n.y >= 0.0 ? 1.0 : -1.0);
+    return (1.0 - abs(n.yx)) * sign_not_zero;
+}
+
+void main() {
+    vec4 object_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+
+    // same as shader.frag
+#ifdef USE_NORMAL_MAP
+    vec3 tangent_normal = normalize(texture(sampler2D(t_normal, s_normal), v_tex_coords).rgb);
+    vec3 normal = normalize(v_tangent_matrix * tangent_normal);
+#else
+    vec3 normal = normalize(v_normal);
+#endif
+
+    f_albedo = vec4(object_color.rgb, 1.0);
+    f_normal = vec4(encode_normal(normal), 0.0, 0.0);
+    // Blinn-Phong materials have no parameters yet, so these are the constants of shader.frag
+    f_material = vec4(1.0, 32.0 / 256.0, 0.1, 0.0);
+}
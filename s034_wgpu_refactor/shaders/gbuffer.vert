@@ -0,0 +1,44 @@
+#version 450
+
+#define USE_NORMAL_MAP
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec2 a_tex_coords;
+layout(location = 2) in vec3 a_normal;
+layout(location = 3) in vec3 a_tangent;
+layout(location = 4) in vec3 a_bitangent;
+
+layout(location = 0) out vec2 v_tex_coords;
+#ifdef USE_NORMAL_MAP
+layout(location = 1) out mat3 v_tangent_matrix; // tangent space -> view space
+#else
+layout(location = 1) out vec3 v_normal; // view space
+#endif
+
+layout(set = 0, binding = 0) uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+layout(set = 1, binding = 0) buffer Instances {
+    mat4 s_models[];
+};
+
+void main() {
+    v_tex_coords = a_tex_coords;
+
+    mat4 model_view_matrix = u_view_matrix * s_models[gl_InstanceIndex];
+    mat3 normal_matrix = mat3(transpose(inverse(model_view_matrix)));
+#ifdef USE_NORMAL_MAP
+    v_tangent_matrix = mat3(
+        normalize(normal_matrix * a_tangent),
+        normalize(normal_matrix * a_bitangent),
+        normalize(normal_matrix * a_normal)
+    );
+#else
+    v_normal = normal_matrix * a_normal;
+#endif
+
+    gl_Position = u_proj_matrix * model_view_matrix * vec4(a_position, 1.0);
+}
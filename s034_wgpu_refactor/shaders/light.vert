@@ -11,18 +11,38 @@ layout(set = 0, binding = 0)
     mat4 u_proj_matrix;
 };
 
-layout(set = 1, binding = 0)
-    uniform Light {
-    vec3 u_position;
-    vec3 u_color;
+// must be same as renderer::light::LightKind
+const float LIGHT_DIRECTIONAL = 0.0;
+
+struct Light {
+    vec4 position;  // xyz: position, w: light type
+    vec4 direction; // xyz: direction, w: attenuation radius
+    vec4 color;     // rgb: color, a: intensity
+    vec4 cone;      // x: cos(inner angle), y: cos(outer angle)
+};
+
+layout(set = 1, binding = 0) uniform LightInfo {
+    uint u_num_lights;
+};
+
+layout(set = 1, binding = 1) readonly buffer Lights {
+    Light s_lights[];
 };
 
 // Let's keep our light smaller than our other objects
 float scale = 0.25;
 
+// directional lights don't have position, so place them far away against its direction
+float directional_distance = 10.0;
+
 void main() {
-    vec3 v_position = a_position * scale + u_position;
+    Light light = s_lights[gl_InstanceIndex];
+    vec3 light_position = light.position.xyz;
+    if (light.position.w == LIGHT_DIRECTIONAL) {
+        light_position = -normalize(light.direction.xyz) * directional_distance;
+    }
+    vec3 v_position = a_position * scale + light_position;
     gl_Position = u_proj_matrix * u_view_matrix * vec4(v_position, 1);
 
-    v_color = u_color;
+    v_color = light.color.rgb;
 }
@@ -0,0 +1,16 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 1) in float v_life;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 1) uniform ParticleRenderParams {
+    vec4 u_params; // x: size, yzw: color
+};
+
+void main() {
+    // soft round sprite which fades out over the lifetime. added to the HDR target
+    float falloff = max(1.0 - dot(v_tex_coords, v_tex_coords), 0.0);
+    f_color = vec4(u_params.yzw * falloff * falloff * v_life, 1.0);
+}
@@ -0,0 +1,42 @@
+#version 450
+
+// renderer::particle::Particle per instance
+layout(location = 0) in vec4 a_position; // xyz: position, w: age
+layout(location = 1) in vec4 a_velocity; // xyz: velocity, w: lifetime
+
+layout(location = 0) out vec2 v_tex_coords;
+layout(location = 1) out float v_life;
+
+layout(set = 0, binding = 0) uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+layout(set = 0, binding = 1) uniform ParticleRenderParams {
+    vec4 u_params; // x: size, yzw: color
+};
+
+void main() {
+    // two triangles of a camera facing quad
+    const vec2 corners[6] = vec2[6](
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+    );
+    vec2 corner = corners[gl_VertexIndex];
+    v_tex_coords = corner;
+
+    float age = a_position.w;
+    float lifetime = a_velocity.w;
+    if (age < 0.0 || age >= lifetime) {
+        // unborn or dead. clipped
+        v_life = 0.0;
+        gl_Position = vec4(0.0, 0.0, -1.0, 1.0);
+        return;
+    }
+    v_life = 1.0 - age / lifetime;
+
+    vec4 view_position = u_view_matrix * vec4(a_position.xyz, 1.0);
+    view_position.xy += corner * u_params.x;
+    gl_Position = u_proj_matrix * view_position;
+}
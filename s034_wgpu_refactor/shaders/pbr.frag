@@ -0,0 +1,320 @@
+#version 450
+
+#define USE_NORMAL_MAP
+
+// all coordinates are view space
+#ifdef USE_NORMAL_MAP
+layout(location = 0) in vec3 v_position;
+layout(location = 1) in vec3 v_eye_position;
+layout(location = 2) in vec2 v_tex_coords;
+layout(location = 3) in vec3 v_world_position;
+layout(location = 4) in mat3 v_tangent_matrix;
+#else
+layout(location = 0) in vec3 v_position;
+layout(location = 1) in vec3 v_eye_position;
+layout(location = 2) in vec2 v_tex_coords;
+layout(location = 3) in vec3 v_world_position;
+layout(location = 4) in vec3 v_normal;
+#endif
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0)
+    uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+// must be same as renderer::environment::EnvironmentRaw
+layout(set = 0, binding = 1) uniform EnvironmentInfo {
+    float u_environment_intensity;
+    float u_specular_mips;
+};
+layout(set = 0, binding = 3) uniform textureCube t_irradiance;
+layout(set = 0, binding = 4) uniform textureCube t_specular;
+layout(set = 0, binding = 5) uniform texture2D t_brdf_lut;
+layout(set = 0, binding = 6) uniform sampler s_environment;
+
+// must be same as renderer::light::LightKind
+const float LIGHT_DIRECTIONAL = 0.0;
+const float LIGHT_POINT = 1.0;
+const float LIGHT_SPOT = 2.0;
+
+struct Light {
+    vec4 position;  // xyz: position, w: light type
+    vec4 direction; // xyz: direction, w: attenuation radius
+    vec4 color;     // rgb: color, a: intensity
+    vec4 cone;      // x: cos(inner angle), y: cos(outer angle)
+};
+
+layout(set = 2, binding = 0) uniform LightInfo {
+    uint u_num_lights;
+};
+
+layout(set = 2, binding = 1) readonly buffer Lights {
+    Light s_lights[];
+};
+
+// must be same as renderer::shadow::ShadowRaw
+struct Shadow {
+    uvec4 layers; // x: first layer, y: layer count (0: no shadow), z: pcf radius
+    vec4 params;  // x: bias, y: slope bias, z: texel size
+    vec4 splits;  // far view distance of each cascade
+};
+
+layout(set = 2, binding = 2) readonly buffer Shadows {
+    Shadow s_shadows[];
+};
+
+layout(set = 2, binding = 3) readonly buffer ShadowLayers {
+    mat4 s_shadow_layers[];
+};
+
+layout(set = 2, binding = 4) uniform texture2DArray t_shadow;
+layout(set = 2, binding = 5) uniform samplerShadow s_shadow;
+
+// must be same as renderer::material::PbrFactorsRaw
+layout(set = 3, binding = 0) uniform PbrFactors {
+    vec4 u_base_color;
+    vec4 u_emissive; // rgb: emissive, a: exposure
+    vec4 u_params;   // x: metallic, y: roughness, z: occlusion strength, w: tone mapping
+};
+layout(set = 3, binding = 1) uniform texture2D t_base_color;
+layout(set = 3, binding = 2) uniform sampler s_base_color;
+layout(set = 3, binding = 3) uniform texture2D t_normal;
+layout(set = 3, binding = 4) uniform sampler s_normal;
+layout(set = 3, binding = 5) uniform texture2D t_metallic;
+layout(set = 3, binding = 6) uniform sampler s_metallic;
+layout(set = 3, binding = 7) uniform texture2D t_roughness;
+layout(set = 3, binding = 8) uniform sampler s_roughness;
+layout(set = 3, binding = 9) uniform texture2D t_occlusion;
+layout(set = 3, binding = 10) uniform sampler s_occlusion;
+layout(set = 3, binding = 11) uniform texture2D t_emissive;
+layout(set = 3, binding = 12) uniform sampler s_emissive;
+
+// must be same as renderer::brdf
+const float PI = 3.14159265359;
+const float DIELECTRIC_F0 = 0.04;
+const float TONE_MAPPING_REINHARD = 1.0;
+const float TONE_MAPPING_ACES = 2.0;
+
+// world space -> shading space (tangent space if normal map is used, otherwise view space)
+vec3 to_shading_point(vec3 p) {
+    vec3 view_space = (u_view_matrix * vec4(p, 1.0)).xyz;
+#ifdef USE_NORMAL_MAP
+    return v_tangent_matrix * view_space;
+#else
+    return view_space;
+#endif
+}
+
+vec3 to_shading_dir(vec3 d) {
+    vec3 view_space = mat3(u_view_matrix) * d;
+#ifdef USE_NORMAL_MAP
+    return normalize(v_tangent_matrix * view_space);
+#else
+    return normalize(view_space);
+#endif
+}
+
+// smoothly reaches zero at the radius. no falloff if radius <= 0
+float range_attenuation(float distance, float radius) {
+    if (radius <= 0.0) {
+        return 1.0;
+    }
+    float ratio = distance / radius;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
+float sample_shadow_map(uint layer, float bias, int pcf_radius, float texel_size) {
+    vec4 homogeneous_coords = s_shadow_layers[layer] * vec4(v_world_position, 1.0);
+    if (homogeneous_coords.w <= 0.0) {
+        return 1.0;
+    }
+    vec3 light_local = homogeneous_coords.xyz / homogeneous_coords.w;
+    // compensate for the Y-flip difference between the NDC and texture coordinates
+    vec2 uv = light_local.xy * vec2(0.5, -0.5) + 0.5;
+    if (light_local.z > 1.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+    // each lookup is already 2x2 filtered by the comparison sampler
+    float visibility = 0.0;
+    for (int y = -pcf_radius; y <= pcf_radius; ++y) {
+        for (int x = -pcf_radius; x <= pcf_radius; ++x) {
+            vec2 offset = vec2(x, y) * texel_size;
+            visibility += texture(
+                sampler2DArrayShadow(t_shadow, s_shadow),
+                vec4(uv + offset, float(layer), light_local.z - bias)
+            );
+        }
+    }
+    float kernel_size = float(2 * pcf_radius + 1);
+    return visibility / (kernel_size * kernel_size);
+}
+
+float fetch_shadow(uint light_id, float n_dot_l) {
+    Shadow shadow = s_shadows[light_id];
+    uint count = shadow.layers.y;
+    if (count == 0) {
+        return 1.0;
+    }
+    uint layer = shadow.layers.x;
+    float light_type = s_lights[light_id].position.w;
+    if (light_type == LIGHT_DIRECTIONAL) {
+        // pick the cascade by the view distance
+        float depth = -(u_view_matrix * vec4(v_world_position, 1.0)).z;
+        uint cascade = 0;
+        while (cascade < count && depth > shadow.splits[cascade]) {
+            ++cascade;
+        }
+        if (cascade == count) {
+            return 1.0;
+        }
+        layer += cascade;
+    } else if (light_type == LIGHT_POINT) {
+        // pick the cube face (+x, -x, +y, -y, +z, -z) by the major axis
+        vec3 d = v_world_position - s_lights[light_id].position.xyz;
+        vec3 a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            layer += d.x > 0.0 ? 0 : 1;
+        } else if (a.y >= a.z) {
+            layer += d.y > 0.0 ? 2 : 3;
+        } else {
+            layer += d.z > 0.0 ? 4 : 5;
+        }
+    }
+    float bias = max(shadow.params.y * (1.0 - n_dot_l), shadow.params.x);
+    return sample_shadow_map(layer, bias, int(shadow.layers.z), shadow.params.z);
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    n_dot_h = max(n_dot_h, 0.0);
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 1e-7);
+}
+
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    return geometry_schlick_ggx(max(n_dot_v, 0.0), roughness)
+        * geometry_schlick_ggx(max(n_dot_l, 0.0), roughness);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// BRDF * cos(theta_l)
+vec3 cook_torrance(vec3 n, vec3 v, vec3 l, vec3 base_color, float metallic, float roughness) {
+    float n_dot_l = max(dot(n, l), 0.0);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 h = normalize(v + l);
+    float n_dot_h = max(dot(n, h), 0.0);
+    float v_dot_h = max(dot(v, h), 0.0);
+
+    vec3 f0 = mix(vec3(DIELECTRIC_F0), base_color, metallic);
+    vec3 f = fresnel_schlick(v_dot_h, f0);
+    float d = distribution_ggx(n_dot_h, roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    vec3 specular = f * (d * g / max(4.0 * n_dot_v * n_dot_l, 1e-4));
+
+    // energy which is not reflected is refracted, and metals absorb all of it
+    vec3 kd = (1.0 - f) * (1.0 - metallic);
+    vec3 diffuse = kd * base_color / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// split sum approximation with the maps prefiltered by renderer::environment
+vec3 image_based_lighting(vec3 n, vec3 v, vec3 base_color, float metallic, float roughness) {
+    // shading space -> world space
+    mat3 view_to_world = transpose(mat3(u_view_matrix));
+#ifdef USE_NORMAL_MAP
+    mat3 to_world = view_to_world * transpose(v_tangent_matrix);
+#else
+    mat3 to_world = view_to_world;
+#endif
+    vec3 n_world = normalize(to_world * n);
+    vec3 r_world = normalize(to_world * reflect(-v, n));
+    float n_dot_v = max(dot(n, v), 1e-4);
+
+    vec3 f0 = mix(vec3(DIELECTRIC_F0), base_color, metallic);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    vec3 kd = (1.0 - f) * (1.0 - metallic);
+
+    vec3 irradiance = texture(samplerCube(t_irradiance, s_environment), n_world).rgb;
+    float lod = roughness * (u_specular_mips - 1.0);
+    vec3 prefiltered = textureLod(samplerCube(t_specular, s_environment), r_world, lod).rgb;
+    vec2 brdf = texture(sampler2D(t_brdf_lut, s_environment), vec2(n_dot_v, roughness)).rg;
+
+    return (kd * irradiance * base_color + prefiltered * (f0 * brdf.x + brdf.y)) * u_environment_intensity;
+}
+
+vec3 tone_map(vec3 color, float exposure, float tone_mapping) {
+    color *= exposure;
+    if (tone_mapping == TONE_MAPPING_REINHARD) {
+        return color / (1.0 + color);
+    } else if (tone_mapping == TONE_MAPPING_ACES) {
+        // Narkowicz 2015, "ACES Filmic Tone Mapping Curve"
+        return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+    }
+    return color;
+}
+
+void main() {
+    vec4 base_color = texture(sampler2D(t_base_color, s_base_color), v_tex_coords) * u_base_color;
+    float metallic = clamp(texture(sampler2D(t_metallic, s_metallic), v_tex_coords).r * u_params.x, 0.0, 1.0);
+    // avoid the singularity of GGX at zero roughness
+    float roughness = clamp(texture(sampler2D(t_roughness, s_roughness), v_tex_coords).r * u_params.y, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(sampler2D(t_occlusion, s_occlusion), v_tex_coords).r, u_params.z);
+    vec3 emissive = texture(sampler2D(t_emissive, s_emissive), v_tex_coords).rgb * u_emissive.rgb;
+
+#ifdef USE_NORMAL_MAP
+    vec3 normal = normalize(texture(sampler2D(t_normal, s_normal), v_tex_coords).rgb * 2.0 - 1.0);
+#else
+    vec3 normal = normalize(v_normal);
+#endif
+    vec3 view_dir = normalize(v_eye_position - v_position);
+
+    vec3 color = vec3(0.0);
+    for (uint i = 0; i < u_num_lights; ++i) {
+        Light light = s_lights[i];
+        vec3 light_color = light.color.rgb * light.color.a;
+        vec3 light_dir;
+        float attenuation = 1.0;
+        if (light.position.w == LIGHT_DIRECTIONAL) {
+            light_dir = -to_shading_dir(light.direction.xyz);
+        } else {
+            vec3 to_light = to_shading_point(light.position.xyz) - v_position;
+            float distance = length(to_light);
+            light_dir = to_light / max(distance, 0.0001);
+            attenuation = range_attenuation(distance, light.direction.w);
+            if (light.position.w == LIGHT_SPOT) {
+                float cos_theta = dot(-light_dir, to_shading_dir(light.direction.xyz));
+                attenuation *= smoothstep(light.cone.y, light.cone.x, cos_theta);
+            }
+        }
+        attenuation *= fetch_shadow(i, max(dot(normal, light_dir), 0.0));
+
+        color += cook_torrance(normal, view_dir, light_dir, base_color.rgb, metallic, roughness)
+            * light_color * attenuation;
+    }
+    color += image_based_lighting(normal, view_dir, base_color.rgb, metallic, roughness) * occlusion;
+    color += emissive;
+
+    f_color = vec4(tone_map(color, u_emissive.a, u_params.w), base_color.a);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+layout(set = 0, binding = 3) uniform texture2D t_bloom;
+
+// z: intensity
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    vec3 bloom = texture(sampler2D(t_bloom, s_post), v_tex_coords).rgb;
+    f_color = vec4(color.rgb + bloom * u_params[0].z, color.a);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+// x: threshold, y: knee
+void main() {
+    // 4 taps box filter while downsampling to the half resolution
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_post), 0));
+    vec3 color = vec3(0.0);
+    color += texture(sampler2D(t_input, s_post), v_tex_coords + texel * vec2(-0.5, -0.5)).rgb;
+    color += texture(sampler2D(t_input, s_post), v_tex_coords + texel * vec2(0.5, -0.5)).rgb;
+    color += texture(sampler2D(t_input, s_post), v_tex_coords + texel * vec2(-0.5, 0.5)).rgb;
+    color += texture(sampler2D(t_input, s_post), v_tex_coords + texel * vec2(0.5, 0.5)).rgb;
+    color *= 0.25;
+
+    // soft threshold
+    float threshold = u_params[0].x;
+    float knee = max(u_params[0].y, 1e-4);
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    color *= max(soft, brightness - threshold) / max(brightness, 1e-4);
+
+    f_color = vec4(color, 1.0);
+}
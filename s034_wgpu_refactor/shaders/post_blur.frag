@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// xy: direction * radius
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_post), 0));
+    vec2 step = u_params[0].xy * texel;
+    vec3 color = texture(sampler2D(t_input, s_post), v_tex_coords).rgb * WEIGHTS[0];
+    for (int i = 1; i < 5; ++i) {
+        color += texture(sampler2D(t_input, s_post), v_tex_coords + step * float(i)).rgb * WEIGHTS[i];
+        color += texture(sampler2D(t_input, s_post), v_tex_coords - step * float(i)).rgb * WEIGHTS[i];
+    }
+    f_color = vec4(color, 1.0);
+}
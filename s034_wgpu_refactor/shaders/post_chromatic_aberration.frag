@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+// x: intensity
+void main() {
+    // channels are shifted more toward the edges of the screen
+    vec2 offset = (v_tex_coords - 0.5) * u_params[0].x * 0.02;
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    float r = texture(sampler2D(t_input, s_post), v_tex_coords - offset).r;
+    float b = texture(sampler2D(t_input, s_post), v_tex_coords + offset).b;
+    f_color = vec4(r, color.g, b, color.a);
+}
@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+layout(set = 0, binding = 3) uniform texture3D t_lut;
+
+// [0] x: strength, y: LUT size
+// [1] xyz: domain min, [2] xyz: domain max
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    float size = u_params[0].y;
+    vec3 coords = (color.rgb - u_params[1].xyz) / (u_params[2].xyz - u_params[1].xyz);
+    // sample the centers of the first and the last texels
+    coords = clamp(coords, 0.0, 1.0) * (size - 1.0) / size + 0.5 / size;
+    vec3 graded = texture(sampler3D(t_lut, s_post), coords).rgb;
+    f_color = vec4(mix(color.rgb, graded, u_params[0].x), color.a);
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+float random(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// [0] x: intensity, y: time, z: luminance response
+// [3] rendered part of the image, see renderer::post::tile_params
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    vec2 size = vec2(textureSize(sampler2D(t_input, s_post), 0));
+    // the pixel in the whole image, so that the tiles get the grain of their part of it
+    vec2 pixel = floor(v_tex_coords * size) + round(u_params[3].xy * size / u_params[3].zw);
+    float noise = random(pixel + fract(u_params[0].y) * 1000.0) - 0.5;
+    // less grain in the highlights
+    float luminance = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+    float amount = u_params[0].x * (1.0 - clamp(luminance, 0.0, 1.0) * u_params[0].z);
+    f_color = vec4(max(color.rgb + noise * amount, 0.0), color.a);
+}
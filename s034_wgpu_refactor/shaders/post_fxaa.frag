@@ -0,0 +1,47 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+vec3 fetch(vec2 uv) {
+    return texture(sampler2D(t_input, s_post), uv).rgb;
+}
+
+// x: span max, y: reduce mul, z: reduce min
+// Timothy Lottes, "FXAA" (the compact version without the end of edge search)
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_post), 0));
+    vec2 uv = v_tex_coords;
+    float luma_nw = dot(fetch(uv + vec2(-1.0, -1.0) * texel), LUMA);
+    float luma_ne = dot(fetch(uv + vec2(1.0, -1.0) * texel), LUMA);
+    float luma_sw = dot(fetch(uv + vec2(-1.0, 1.0) * texel), LUMA);
+    float luma_se = dot(fetch(uv + vec2(1.0, 1.0) * texel), LUMA);
+    vec4 center = texture(sampler2D(t_input, s_post), uv);
+    float luma_m = dot(center.rgb, LUMA);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float span_max = u_params[0].x;
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * u_params[0].y, u_params[0].z);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-span_max), vec2(span_max)) * texel;
+
+    vec3 rgb_a = 0.5 * (fetch(uv + dir * (1.0 / 3.0 - 0.5)) + fetch(uv + dir * (2.0 / 3.0 - 0.5)));
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (fetch(uv - dir * 0.5) + fetch(uv + dir * 0.5));
+    float luma_b = dot(rgb_b, LUMA);
+    f_color = vec4((luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b, center.a);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+// must be same as renderer::brdf::ToneMapping
+const float TONE_MAPPING_REINHARD = 1.0;
+const float TONE_MAPPING_ACES = 2.0;
+
+// must be same as renderer::brdf::tone_map
+vec3 tone_map(vec3 color, float exposure, float tone_mapping) {
+    color *= exposure;
+    if (tone_mapping == TONE_MAPPING_REINHARD) {
+        return color / (1.0 + color);
+    } else if (tone_mapping == TONE_MAPPING_ACES) {
+        // Narkowicz 2015, "ACES Filmic Tone Mapping Curve"
+        return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+    }
+    return color;
+}
+
+// x: exposure, y: tone mapping
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    f_color = vec4(tone_map(color.rgb, u_params[0].x, u_params[0].y), color.a);
+}
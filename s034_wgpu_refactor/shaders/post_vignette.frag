@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::post::FullscreenPass
+layout(set = 0, binding = 0) uniform PostParams {
+    vec4 u_params[4];
+};
+layout(set = 0, binding = 1) uniform sampler s_post;
+layout(set = 0, binding = 2) uniform texture2D t_input;
+
+// [0] x: intensity, y: smoothness, z: roundness, w: rounded (0 or 1)
+// [1] rgb: color
+// [3] rendered part of the image, see renderer::post::tile_params
+void main() {
+    vec4 color = texture(sampler2D(t_input, s_post), v_tex_coords);
+    vec2 size = vec2(textureSize(sampler2D(t_input, s_post), 0)) / u_params[3].zw;
+    vec2 screen = u_params[3].xy + v_tex_coords * u_params[3].zw;
+    vec2 d = abs(screen - 0.5) * u_params[0].x;
+    d.x *= mix(1.0, size.x / size.y, u_params[0].w);
+    d = pow(clamp(d, 0.0, 1.0), vec2(u_params[0].z));
+    float factor = pow(clamp(1.0 - dot(d, d), 0.0, 1.0), u_params[0].y * 5.0);
+    f_color = vec4(color.rgb * mix(u_params[1].rgb, vec3(1.0), factor), color.a);
+}
@@ -6,14 +6,14 @@
 #ifdef USE_NORMAL_MAP
 layout(location = 0) in vec3 v_position;
 layout(location = 1) in vec3 v_eye_position;
-layout(location = 2) in vec3 v_light_position;
-layout(location = 3) in vec2 v_tex_coords;
+layout(location = 2) in vec2 v_tex_coords;
+layout(location = 3) in vec3 v_world_position;
 layout(location = 4) in mat3 v_tangent_matrix;
 #else
 layout(location = 0) in vec3 v_position;
 layout(location = 1) in vec3 v_eye_position;
-layout(location = 2) in vec3 v_light_position;
-layout(location = 3) in vec2 v_tex_coords;
+layout(location = 2) in vec2 v_tex_coords;
+layout(location = 3) in vec3 v_world_position;
 layout(location = 4) in vec3 v_normal;
 #endif
 
@@ -26,37 +26,194 @@ layout(set = 0, binding = 0)
     mat4 u_proj_matrix;
 };
 
-layout(set = 2, binding = 0) uniform Light {
-    vec3 u_light_position;
-    vec3 u_light_color;
+// renderer::ssao::Ssao. white while SSAO is disabled
+layout(set = 0, binding = 1) uniform texture2D t_ambient_occlusion;
+layout(set = 0, binding = 2) uniform sampler s_ambient_occlusion;
+
+// must be same as renderer::light::LightKind
+const float LIGHT_DIRECTIONAL = 0.0;
+const float LIGHT_POINT = 1.0;
+const float LIGHT_SPOT = 2.0;
+
+struct Light {
+    vec4 position;  // xyz: position, w: light type
+    vec4 direction; // xyz: direction, w: attenuation radius
+    vec4 color;     // rgb: color, a: intensity
+    vec4 cone;      // x: cos(inner angle), y: cos(outer angle)
+};
+
+layout(set = 2, binding = 0) uniform LightInfo {
+    uint u_num_lights;
+};
+
+layout(set = 2, binding = 1) readonly buffer Lights {
+    Light s_lights[];
+};
+
+// must be same as renderer::shadow::ShadowRaw
+struct Shadow {
+    uvec4 layers; // x: first layer, y: layer count (0: no shadow), z: pcf radius
+    vec4 params;  // x: bias, y: slope bias, z: texel size
+    vec4 splits;  // far view distance of each cascade
+};
+
+layout(set = 2, binding = 2) readonly buffer Shadows {
+    Shadow s_shadows[];
+};
+
+layout(set = 2, binding = 3) readonly buffer ShadowLayers {
+    mat4 s_shadow_layers[];
 };
 
+layout(set = 2, binding = 4) uniform texture2DArray t_shadow;
+layout(set = 2, binding = 5) uniform samplerShadow s_shadow;
+
 layout(set = 3, binding = 0) uniform texture2D t_diffuse;
 layout(set = 3, binding = 1) uniform sampler s_diffuse;
 layout(set = 3, binding = 2) uniform texture2D t_normal;
 layout(set = 3, binding = 3) uniform sampler s_normal;
 
+// world space -> shading space (tangent space if normal map is used, otherwise view space)
+vec3 to_shading_point(vec3 p) {
+    vec3 view_space = (u_view_matrix * vec4(p, 1.0)).xyz;
+#ifdef USE_NORMAL_MAP
+    return v_tangent_matrix * view_space;
+#else
+    return view_space;
+#endif
+}
+
+vec3 to_shading_dir(vec3 d) {
+    vec3 view_space = mat3(u_view_matrix) * d;
+#ifdef USE_NORMAL_MAP
+    return normalize(v_tangent_matrix * view_space);
+#else
+    return normalize(view_space);
+#endif
+}
+
+// smoothly reaches zero at the radius. no falloff if radius <= 0
+float range_attenuation(float distance, float radius) {
+    if (radius <= 0.0) {
+        return 1.0;
+    }
+    float ratio = distance / radius;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
+float sample_shadow_map(uint layer, float bias, int pcf_radius, float texel_size) {
+    vec4 homogeneous_coords = s_shadow_layers[layer] * vec4(v_world_position, 1.0);
+    if (homogeneous_coords.w <= 0.0) {
+        return 1.0;
+    }
+    vec3 light_local = homogeneous_coords.xyz / homogeneous_coords.w;
+    // compensate for the Y-flip difference between the NDC and texture coordinates
+    vec2 uv = light_local.xy * vec2(0.5, -0.5) + 0.5;
+    if (light_local.z > 1.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+    // each lookup is already 2x2 filtered by the comparison sampler
+    float visibility = 0.0;
+    for (int y = -pcf_radius; y <= pcf_radius; ++y) {
+        for (int x = -pcf_radius; x <= pcf_radius; ++x) {
+            vec2 offset = vec2(x, y) * texel_size;
+            visibility += texture(
+                sampler2DArrayShadow(t_shadow, s_shadow),
+                vec4(uv + offset, float(layer), light_local.z - bias)
+            );
+        }
+    }
+    float kernel_size = float(2 * pcf_radius + 1);
+    return visibility / (kernel_size * kernel_size);
+}
+
+float fetch_shadow(uint light_id, float n_dot_l) {
+    Shadow shadow = s_shadows[light_id];
+    uint count = shadow.layers.y;
+    if (count == 0) {
+        return 1.0;
+    }
+    uint layer = shadow.layers.x;
+    float light_type = s_lights[light_id].position.w;
+    if (light_type == LIGHT_DIRECTIONAL) {
+        // pick the cascade by the view distance
+        float depth = -(u_view_matrix * vec4(v_world_position, 1.0)).z;
+        uint cascade = 0;
+        while (cascade < count && depth > shadow.splits[cascade]) {
+            ++cascade;
+        }
+        if (cascade == count) {
+            return 1.0;
+        }
+        layer += cascade;
+    } else if (light_type == LIGHT_POINT) {
+        // pick the cube face (+x, -x, +y, -y, +z, -z) by the major axis
+        vec3 d = v_world_position - s_lights[light_id].position.xyz;
+        vec3 a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            layer += d.x > 0.0 ? 0 : 1;
+        } else if (a.y >= a.z) {
+            layer += d.y > 0.0 ? 2 : 3;
+        } else {
+            layer += d.z > 0.0 ? 4 : 5;
+        }
+    }
+    float bias = max(shadow.params.y * (1.0 - n_dot_l), shadow.params.x);
+    return sample_shadow_map(layer, bias, int(shadow.layers.z), shadow.params.z);
+}
+
 void main() {
     vec4 object_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
     vec4 object_normal = texture(sampler2D(t_normal, s_normal), v_tex_coords); // NEW!
 
     // We don't need (or want) much ambient light, so 0.1 is fine
     float ambient_strength = 0.1;
-    vec3 ambient_color = u_light_color * ambient_strength;
+    ambient_strength *= texelFetch(
+        sampler2D(t_ambient_occlusion, s_ambient_occlusion),
+        ivec2(gl_FragCoord.xy),
+        0
+    ).r;
 
 #ifdef USE_NORMAL_MAP
     vec3 normal = normalize(object_normal.rgb);
 #else
     vec3 normal = normalize(v_normal);
 #endif
-    vec3 light_dir = normalize(v_light_position - v_position);
-    float diffuse_strength = max(dot(normal, light_dir), 0.0);
-    vec3 diffuse_color = u_light_color * diffuse_strength;
-
     vec3 view_dir = normalize(v_eye_position - v_position);
-    vec3 half_dir = normalize(view_dir + light_dir);
-    float specular_strength = pow(max(dot(normal, half_dir), 0.0), 32);
-    vec3 specular_color = specular_strength * u_light_color;
+
+    vec3 ambient_color = vec3(0.0);
+    vec3 diffuse_color = vec3(0.0);
+    vec3 specular_color = vec3(0.0);
+
+    for (uint i = 0; i < u_num_lights; ++i) {
+        Light light = s_lights[i];
+        vec3 light_color = light.color.rgb * light.color.a;
+        ambient_color += light_color * ambient_strength;
+
+        vec3 light_dir;
+        float attenuation = 1.0;
+        if (light.position.w == LIGHT_DIRECTIONAL) {
+            light_dir = -to_shading_dir(light.direction.xyz);
+        } else {
+            vec3 to_light = to_shading_point(light.position.xyz) - v_position;
+            float distance = length(to_light);
+            light_dir = to_light / max(distance, 0.0001);
+            attenuation = range_attenuation(distance, light.direction.w);
+            if (light.position.w == LIGHT_SPOT) {
+                float cos_theta = dot(-light_dir, to_shading_dir(light.direction.xyz));
+                attenuation *= smoothstep(light.cone.y, light.cone.x, cos_theta);
+            }
+        }
+
+        float diffuse_strength = max(dot(normal, light_dir), 0.0);
+        attenuation *= fetch_shadow(i, diffuse_strength);
+        diffuse_color += light_color * diffuse_strength * attenuation;
+
+        vec3 half_dir = normalize(view_dir + light_dir);
+        float specular_strength = pow(max(dot(normal, half_dir), 0.0), 32);
+        specular_color += light_color * specular_strength * attenuation;
+    }
 
     vec3 result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
 
@@ -12,14 +12,14 @@ layout(location = 4) in vec3 a_bitangent;
 #ifdef USE_NORMAL_MAP
 layout(location = 0) out vec3 v_position;
 layout(location = 1) out vec3 v_eye_position;
-layout(location = 2) out vec3 v_light_position;
-layout(location = 3) out vec2 v_tex_coords;
+layout(location = 2) out vec2 v_tex_coords;
+layout(location = 3) out vec3 v_world_position; // for shadow lookup
 layout(location = 4) out mat3 v_tangent_matrix;
 #else
 layout(location = 0) out vec3 v_position;
 layout(location = 1) out vec3 v_eye_position;
-layout(location = 2) out vec3 v_light_position;
-layout(location = 3) out vec2 v_tex_coords;
+layout(location = 2) out vec2 v_tex_coords;
+layout(location = 3) out vec3 v_world_position; // for shadow lookup
 layout(location = 4) out vec3 v_normal;
 #endif
 
@@ -33,17 +33,13 @@ layout(set = 1, binding = 0) buffer Instances {
     mat4 s_models[];
 };
 
-layout(set = 2, binding = 0) uniform Light {
-    vec3 u_light_position;
-    vec3 u_light_color;
-};
-
 void main() {
     v_tex_coords = a_tex_coords;
 
     // calcurate all position in view space
     // after that, convert them to tangent space
     mat4 model_matrix = s_models[gl_InstanceIndex];
+    v_world_position = (model_matrix * vec4(a_position, 1.0)).xyz;
     mat3 normal_matrix = mat3(transpose(inverse(u_view_matrix * model_matrix)));
 #ifdef USE_NORMAL_MAP
     vec3 normal = normalize(normal_matrix * a_normal);
@@ -57,13 +53,11 @@ void main() {
     v_tangent_matrix = tangent_matrix;
     vec4 view_space = u_view_matrix * model_matrix * vec4(a_position, 1.0);
     v_position = tangent_matrix * view_space.xyz;
-    v_light_position = tangent_matrix * (u_view_matrix * vec4(u_light_position, 1.)).xyz;
     v_eye_position = tangent_matrix * (u_view_matrix * vec4(u_view_position, 1.)).xyz;
 #else
     v_normal = normal_matrix * a_normal;
     vec4 view_space = u_view_matrix * model_matrix * vec4(a_position, 1.0);
     v_position = view_space.xyz;
-    v_light_position = (u_view_matrix * vec4(u_light_position, 1.)).xyz;
     v_eye_position = (u_view_matrix * vec4(u_view_position, 1.)).xyz;
 #endif
 
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+
+layout(set = 0, binding = 0) uniform ShadowPass {
+    mat4 u_light_view_proj;
+};
+
+layout(set = 1, binding = 0) buffer Instances {
+    mat4 s_models[];
+};
+
+void main() {
+    gl_Position = u_light_view_proj * s_models[gl_InstanceIndex] * vec4(a_position, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 v_direction;
+
+layout(location = 0) out vec4 f_color;
+
+// must be same as renderer::environment::EnvironmentRaw
+layout(set = 0, binding = 1) uniform EnvironmentInfo {
+    float u_environment_intensity;
+    float u_specular_mips;
+};
+layout(set = 0, binding = 2) uniform textureCube t_skybox;
+layout(set = 0, binding = 6) uniform sampler s_environment;
+
+void main() {
+    vec3 radiance = texture(samplerCube(t_skybox, s_environment), normalize(v_direction)).rgb;
+    f_color = vec4(radiance * u_environment_intensity, 1.0);
+}
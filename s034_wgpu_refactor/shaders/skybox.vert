@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) out vec3 v_direction;
+
+layout(set = 0, binding = 0)
+    uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+void main() {
+    // fullscreen triangle on the far plane
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    vec4 view = inverse(u_proj_matrix) * vec4(position, 1.0, 1.0);
+    v_direction = transpose(mat3(u_view_matrix)) * (view.xyz / view.w);
+    gl_Position = vec4(position, 1.0, 1.0);
+}
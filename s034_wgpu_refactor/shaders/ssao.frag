@@ -0,0 +1,86 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out float f_occlusion;
+
+layout(set = 0, binding = 0) uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+// must be same as renderer::ssao::SsaoRaw
+layout(set = 0, binding = 1) uniform SsaoParams {
+    vec4 u_kernel[64];
+    vec4 u_params; // x: radius, y: bias, z: intensity, w: kernel size
+    vec4 u_blur;   // x: noise size, y: blur radius, z: blur depth sigma
+};
+layout(set = 0, binding = 2) uniform texture2D t_depth;
+layout(set = 0, binding = 3) uniform texture2D t_noise;
+layout(set = 0, binding = 4) uniform sampler s_ssao;
+
+mat4 inverse_proj;
+
+vec3 view_position(ivec2 coords) {
+    ivec2 size = textureSize(sampler2D(t_depth, s_ssao), 0);
+    coords = clamp(coords, ivec2(0), size - 1);
+    float depth = texelFetch(sampler2D(t_depth, s_ssao), coords, 0).r;
+    vec2 uv = (vec2(coords) + 0.5) / vec2(size);
+    vec2 ndc = (uv - 0.5) * vec2(2.0, -2.0);
+    vec4 position = inverse_proj * vec4(ndc, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    vec2 size = vec2(textureSize(sampler2D(t_depth, s_ssao), 0));
+    if (texelFetch(sampler2D(t_depth, s_ssao), coords, 0).r >= 1.0) {
+        f_occlusion = 1.0; // background
+        return;
+    }
+    inverse_proj = inverse(u_proj_matrix);
+    vec3 position = view_position(coords);
+
+    // normal from the neighbors on the same surface, so that edges don't bend it
+    vec3 right = view_position(coords + ivec2(1, 0)) - position;
+    vec3 left = position - view_position(coords - ivec2(1, 0));
+    vec3 down = view_position(coords + ivec2(0, 1)) - position;
+    vec3 up = position - view_position(coords - ivec2(0, 1));
+    vec3 dx = abs(right.z) < abs(left.z) ? right : left;
+    vec3 dy = abs(down.z) < abs(up.z) ? down : up;
+    vec3 normal = normalize(cross(dx, dy));
+    if (dot(normal, position) > 0.0) {
+        normal = -normal;
+    }
+
+    // rotate the kernel around the normal with the tiled noise
+    int noise_size = int(u_blur.x);
+    vec3 random = vec3(texelFetch(sampler2D(t_noise, s_ssao), coords % ivec2(noise_size), 0).xy, 0.0);
+    vec3 tangent = random - normal * dot(random, normal);
+    if (dot(tangent, tangent) < 1e-6) {
+        tangent = abs(normal.x) < 0.9 ? vec3(1.0, 0.0, 0.0) : vec3(0.0, 1.0, 0.0);
+        tangent -= normal * dot(tangent, normal);
+    }
+    tangent = normalize(tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float radius = u_params.x;
+    float bias = u_params.y;
+    int kernel_size = int(u_params.w);
+    float occlusion = 0.0;
+    for (int i = 0; i < kernel_size; ++i) {
+        vec3 sample_position = position + tbn * u_kernel[i].xyz * radius;
+        vec4 clip = u_proj_matrix * vec4(sample_position, 1.0);
+        vec2 uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+            continue;
+        }
+        float scene_z = view_position(ivec2(uv * size)).z;
+        // ignore occluders far behind the sample, e.g. the background behind a silhouette
+        float range = smoothstep(0.0, 1.0, radius / abs(position.z - scene_z));
+        occlusion += (scene_z >= sample_position.z + bias ? 1.0 : 0.0) * range;
+    }
+    float visibility = 1.0 - occlusion / max(float(kernel_size), 1.0);
+    f_occlusion = pow(visibility, u_params.z);
+}
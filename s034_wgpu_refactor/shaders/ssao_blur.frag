@@ -0,0 +1,52 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(location = 0) out float f_occlusion;
+
+layout(set = 0, binding = 0) uniform Uniforms {
+    vec3 u_view_position; // unused
+    mat4 u_view_matrix;
+    mat4 u_proj_matrix;
+};
+
+// must be same as renderer::ssao::SsaoRaw
+layout(set = 0, binding = 1) uniform SsaoParams {
+    vec4 u_kernel[64];
+    vec4 u_params; // x: radius, y: bias, z: intensity, w: kernel size
+    vec4 u_blur;   // x: noise size, y: blur radius, z: blur depth sigma
+};
+layout(set = 0, binding = 2) uniform texture2D t_depth;
+layout(set = 0, binding = 3) uniform texture2D t_occlusion;
+layout(set = 0, binding = 4) uniform sampler s_ssao;
+
+// view distance from the depth. the projection maps -z to (a * z + b) / -z
+float linear_depth(ivec2 coords) {
+    float depth = texelFetch(sampler2D(t_depth, s_ssao), coords, 0).r;
+    return u_proj_matrix[3][2] / (depth + u_proj_matrix[2][2]);
+}
+
+// bilateral: gaussian in space, and taps on other surfaces are ignored
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(sampler2D(t_depth, s_ssao), 0);
+    float center = linear_depth(coords);
+    int radius = int(u_blur.y);
+    float sigma = max(u_blur.z, 1e-4);
+    float spatial_sigma = max(float(radius), 1.0) * 0.5;
+
+    float sum = 0.0;
+    float weight_sum = 0.0;
+    for (int y = -radius; y <= radius; ++y) {
+        for (int x = -radius; x <= radius; ++x) {
+            ivec2 tap = clamp(coords + ivec2(x, y), ivec2(0), size - 1);
+            float dz = linear_depth(tap) - center;
+            float d2 = float(x * x + y * y);
+            float weight = exp(-d2 / (2.0 * spatial_sigma * spatial_sigma))
+                * exp(-dz * dz / (2.0 * sigma * sigma));
+            sum += texelFetch(sampler2D(t_occlusion, s_ssao), tap, 0).r * weight;
+            weight_sum += weight;
+        }
+    }
+    f_occlusion = sum / max(weight_sum, 1e-6);
+}
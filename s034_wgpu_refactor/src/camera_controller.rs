@@ -1,10 +1,10 @@
 use nannou::math::cgmath::{self, Matrix4, Rad};
 use nannou::prelude::*;
-use nannou::winit::dpi::LogicalPosition;
 use std::clone::Clone;
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
 
+use crate::input_map::{Action, ActionEvent};
 use crate::renderer::camera::{Camera, CameraRotation};
 
 #[derive(Debug)]
@@ -39,50 +39,26 @@ impl CameraController {
         }
     }
 
-    pub fn process_key(&mut self, key: Key, is_pressed: bool) -> bool {
-        let amount = if is_pressed { 1.0 } else { 0.0 };
-        match key {
-            Key::W | Key::Up => {
-                self.amount_forward = amount;
-                true
+    pub fn process_action(&mut self, event: ActionEvent) {
+        match event {
+            ActionEvent::Button { action, amount } => match action {
+                Action::MoveForward => self.amount_forward = amount,
+                Action::MoveBackward => self.amount_backward = amount,
+                Action::MoveLeft => self.amount_left = amount,
+                Action::MoveRight => self.amount_right = amount,
+                Action::MoveUp => self.amount_up = amount,
+                Action::MoveDown => self.amount_down = amount,
+            },
+            ActionEvent::Look { dx, dy } => {
+                self.rotate_horizontal = dx;
+                self.rotate_vertical = dy;
             }
-            Key::S | Key::Down => {
-                self.amount_backward = amount;
-                true
+            ActionEvent::Zoom(scroll) => {
+                self.scroll = scroll;
             }
-            Key::A | Key::Left => {
-                self.amount_left = amount;
-                true
-            }
-            Key::D | Key::Right => {
-                self.amount_right = amount;
-                true
-            }
-            Key::Space => {
-                self.amount_up = amount;
-                true
-            }
-            Key::LShift => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
         }
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
-    }
-
-    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
-            // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
-            MouseScrollDelta::PixelDelta(LogicalPosition { y: scroll, .. }) => *scroll as f32,
-        };
-    }
-
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
         if let Some(rotation) = &mut camera.rotation {
@@ -125,7 +101,7 @@ impl CameraController {
                 pitch = Rad(FRAC_PI_2);
             }
 
-            // If process_mouse isn't called every frame, these values
+            // If process_action isn't called every frame, these values
             // will not get set to zero, and the camera will rotate
             // when moving in a non cardinal direction.
             self.rotate_horizontal = 0.0;
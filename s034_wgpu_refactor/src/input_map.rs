@@ -0,0 +1,573 @@
+//! maps raw keyboard / mouse input to abstract camera actions
+//!
+//! bindings are loaded from JSON or RON (selected by file extension), e.g.
+//!
+//! ```ron
+//! (
+//!     bindings: {
+//!         MoveForward: [Key("Z"), Key("Up")],
+//!         MoveLeft: [Key("Q"), Key("Left")],
+//!         MoveBackward: [Key("S"), Key("Down")],
+//!         MoveRight: [Key("D"), Key("Right")],
+//!         MoveUp: [Key("Space"), Mouse("Middle")],
+//!         MoveDown: [Key("LShift")],
+//!     },
+//!     look: [Mouse("Right")],
+//!     invert_y: true,
+//! )
+//! ```
+use anyhow::*;
+use nannou::prelude::*;
+use nannou::winit::dpi::LogicalPosition;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+}
+
+/// what `CameraController` consumes. any device (keyboard, mouse, pedals, gamepads...) can
+/// drive the camera by sending these
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ActionEvent {
+    /// digital inputs send 1.0 / 0.0, analog inputs anything in between
+    Button {
+        action: Action,
+        amount: f32,
+    },
+    Look {
+        dx: f32,
+        dy: f32,
+    },
+    Zoom(f32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(Key),
+    Mouse(MouseButton),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "InputMapRaw", into = "InputMapRaw")]
+pub struct InputMap {
+    pub bindings: Vec<(Input, Action)>,
+    /// mouse look is only active while one of these is held. always active if empty
+    pub look: Vec<Input>,
+    pub mouse_sensitivity: f32,
+    /// exponent applied to mouse deltas. 1.0 is linear
+    pub acceleration: f32,
+    pub invert_y: bool,
+    pub scroll_sensitivity: f32,
+    /// pixels per line for `MouseScrollDelta::LineDelta`
+    pub scroll_line_pixels: f32,
+    /// the inputs of `look` held down. a set, as held keys repeat their presses
+    held: HashSet<Input>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let bindings = vec![
+            (Input::Key(Key::W), Action::MoveForward),
+            (Input::Key(Key::Up), Action::MoveForward),
+            (Input::Key(Key::S), Action::MoveBackward),
+            (Input::Key(Key::Down), Action::MoveBackward),
+            (Input::Key(Key::A), Action::MoveLeft),
+            (Input::Key(Key::Left), Action::MoveLeft),
+            (Input::Key(Key::D), Action::MoveRight),
+            (Input::Key(Key::Right), Action::MoveRight),
+            (Input::Key(Key::Space), Action::MoveUp),
+            (Input::Key(Key::LShift), Action::MoveDown),
+        ];
+        Self {
+            bindings,
+            look: vec![],
+            mouse_sensitivity: 1.0,
+            acceleration: 1.0,
+            invert_y: false,
+            scroll_sensitivity: 1.0,
+            scroll_line_pixels: 100.0,
+            held: HashSet::new(),
+        }
+    }
+}
+
+impl InputMap {
+    /// an input bound to more than one action is an error
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let src = read_to_string(path)?;
+        let input_map = match Self::extension(path)? {
+            "json" => serde_json::from_str(&src).map_err(Error::from),
+            "ron" => ron::de::from_str(&src).map_err(Error::from),
+            _ => bail!("Unsupported input map: {}", path.display()),
+        };
+        input_map.with_context(|| format!("Invalid input map: {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let dst = match Self::extension(path)? {
+            "json" => serde_json::to_string_pretty(self)?,
+            "ron" => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+            _ => bail!("Unsupported input map: {}", path.display()),
+        };
+        write(path, dst)?;
+        Ok(())
+    }
+
+    pub fn bind(mut self, input: Input, action: Action) -> Self {
+        self.bindings.push((input, action));
+        self
+    }
+
+    pub fn unbind(mut self, input: Input) -> Self {
+        self.bindings.retain(|(i, _)| *i != input);
+        self
+    }
+
+    /// inputs bound to more than one action, with the actions in the order of the bindings
+    pub fn conflicts(&self) -> Vec<(Input, Vec<Action>)> {
+        let mut conflicts: Vec<(Input, Vec<Action>)> = vec![];
+        for (input, action) in &self.bindings {
+            match conflicts.iter_mut().find(|(i, _)| i == input) {
+                Some((_, actions)) if !actions.contains(action) => actions.push(*action),
+                Some(_) => {}
+                None => conflicts.push((*input, vec![*action])),
+            }
+        }
+        conflicts.retain(|(_, actions)| actions.len() > 1);
+        conflicts
+    }
+
+    pub fn input(&mut self, input: Input, pressed: bool) -> Vec<ActionEvent> {
+        if self.look.contains(&input) {
+            if pressed {
+                self.held.insert(input);
+            } else {
+                self.held.remove(&input);
+            }
+        }
+        let amount = if pressed { 1.0 } else { 0.0 };
+        self.bindings
+            .iter()
+            .filter(|(i, _)| *i == input)
+            .map(|(_, action)| ActionEvent::Button {
+                action: *action,
+                amount,
+            })
+            .collect()
+    }
+
+    pub fn key(&mut self, key: Key, pressed: bool) -> Vec<ActionEvent> {
+        self.input(Input::Key(key), pressed)
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, pressed: bool) -> Vec<ActionEvent> {
+        self.input(Input::Mouse(button), pressed)
+    }
+
+    pub fn mouse_moved(&self, dx: f32, dy: f32) -> Option<ActionEvent> {
+        if !self.look.is_empty() && self.held.is_empty() {
+            return None;
+        }
+        let curve = |d: f32| d.signum() * d.abs().powf(self.acceleration) * self.mouse_sensitivity;
+        let dy = if self.invert_y { -dy } else { dy };
+        Some(ActionEvent::Look {
+            dx: curve(dx),
+            dy: curve(dy),
+        })
+    }
+
+    pub fn mouse_wheel(&self, delta: &MouseScrollDelta) -> ActionEvent {
+        let scroll = match delta {
+            MouseScrollDelta::LineDelta(_, scroll) => scroll * self.scroll_line_pixels,
+            MouseScrollDelta::PixelDelta(LogicalPosition { y: scroll, .. }) => *scroll as f32,
+        };
+        ActionEvent::Zoom(-scroll * self.scroll_sensitivity)
+    }
+
+    fn extension(path: &Path) -> Result<&str> {
+        path.extension()
+            .context("File has no extension")?
+            .to_str()
+            .context("Extension cannot be converted to &str")
+    }
+}
+
+// file representation. keys and buttons are written with their variant names
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum InputRaw {
+    Key(String),
+    Mouse(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct InputMapRaw {
+    bindings: BTreeMap<Action, Vec<InputRaw>>,
+    look: Vec<InputRaw>,
+    mouse_sensitivity: f32,
+    acceleration: f32,
+    invert_y: bool,
+    scroll_sensitivity: f32,
+    scroll_line_pixels: f32,
+}
+
+impl Default for InputMapRaw {
+    fn default() -> Self {
+        InputMap::default().into()
+    }
+}
+
+impl TryFrom<InputRaw> for Input {
+    type Error = Error;
+
+    fn try_from(raw: InputRaw) -> Result<Self> {
+        match raw {
+            InputRaw::Key(name) => key_from_name(&name)
+                .map(Input::Key)
+                .with_context(|| format!("Unknown key: {}", name)),
+            InputRaw::Mouse(name) => mouse_button_from_name(&name)
+                .map(Input::Mouse)
+                .with_context(|| format!("Unknown mouse button: {}", name)),
+        }
+    }
+}
+
+impl From<Input> for InputRaw {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Key(key) => InputRaw::Key(key_name(key).to_string()),
+            Input::Mouse(MouseButton::Other(n)) => InputRaw::Mouse(n.to_string()),
+            Input::Mouse(button) => InputRaw::Mouse(format!("{:?}", button)),
+        }
+    }
+}
+
+impl TryFrom<InputMapRaw> for InputMap {
+    type Error = Error;
+
+    fn try_from(raw: InputMapRaw) -> Result<Self> {
+        let mut bindings = vec![];
+        for (action, inputs) in raw.bindings {
+            for input in inputs {
+                bindings.push((Input::try_from(input)?, action));
+            }
+        }
+        let look = raw
+            .look
+            .into_iter()
+            .map(Input::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let input_map = Self {
+            bindings,
+            look,
+            mouse_sensitivity: raw.mouse_sensitivity,
+            acceleration: raw.acceleration,
+            invert_y: raw.invert_y,
+            scroll_sensitivity: raw.scroll_sensitivity,
+            scroll_line_pixels: raw.scroll_line_pixels,
+            held: HashSet::new(),
+        };
+        if let Some((input, actions)) = input_map.conflicts().first() {
+            bail!("{:?} is bound to {:?}", input, actions);
+        }
+        Ok(input_map)
+    }
+}
+
+impl From<InputMap> for InputMapRaw {
+    fn from(map: InputMap) -> Self {
+        let mut bindings = BTreeMap::<Action, Vec<InputRaw>>::new();
+        for (input, action) in map.bindings {
+            bindings.entry(action).or_default().push(input.into());
+        }
+        Self {
+            bindings,
+            look: map.look.into_iter().map(Into::into).collect(),
+            mouse_sensitivity: map.mouse_sensitivity,
+            acceleration: map.acceleration,
+            invert_y: map.invert_y,
+            scroll_sensitivity: map.scroll_sensitivity,
+            scroll_line_pixels: map.scroll_line_pixels,
+        }
+    }
+}
+
+fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        _ => name.parse().ok().map(MouseButton::Other),
+    }
+}
+
+// an exhaustive match, so a `Key` added to winit fails to compile until it is listed here
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn key_name(key: Key) -> &'static str {
+            match key {
+                $(Key::$key => stringify!($key),)*
+            }
+        }
+
+        fn key_from_name(name: &str) -> Option<Key> {
+            match name {
+                $(stringify!($key) => Some(Key::$key),)*
+                _ => None,
+            }
+        }
+
+        #[cfg(test)]
+        const KEYS: &[Key] = &[$(Key::$key,)*];
+    };
+}
+
+key_names![
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    Snapshot,
+    Scroll,
+    Pause,
+    Insert,
+    Home,
+    Delete,
+    End,
+    PageDown,
+    PageUp,
+    Left,
+    Up,
+    Right,
+    Down,
+    Back,
+    Return,
+    Space,
+    Compose,
+    Caret,
+    Numlock,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    AbntC1,
+    AbntC2,
+    Add,
+    Apostrophe,
+    Apps,
+    At,
+    Ax,
+    Backslash,
+    Calculator,
+    Capital,
+    Colon,
+    Comma,
+    Convert,
+    Decimal,
+    Divide,
+    Equals,
+    Grave,
+    Kana,
+    Kanji,
+    LAlt,
+    LBracket,
+    LControl,
+    LShift,
+    LWin,
+    Mail,
+    MediaSelect,
+    MediaStop,
+    Minus,
+    Multiply,
+    Mute,
+    MyComputer,
+    NavigateForward,
+    NavigateBackward,
+    NextTrack,
+    NoConvert,
+    NumpadComma,
+    NumpadEnter,
+    NumpadEquals,
+    OEM102,
+    Period,
+    PlayPause,
+    Power,
+    PrevTrack,
+    RAlt,
+    RBracket,
+    RControl,
+    RShift,
+    RWin,
+    Semicolon,
+    Slash,
+    Sleep,
+    Stop,
+    Subtract,
+    Sysrq,
+    Tab,
+    Underline,
+    Unlabeled,
+    VolumeDown,
+    VolumeUp,
+    Wake,
+    WebBack,
+    WebFavorites,
+    WebForward,
+    WebHome,
+    WebRefresh,
+    WebSearch,
+    WebStop,
+    Yen,
+    Copy,
+    Paste,
+    Cut,
+];
+
+#[test]
+fn test_input_map() {
+    // every key survives a save and a load by its name
+    for &key in KEYS {
+        let raw = InputRaw::from(Input::Key(key));
+        assert_eq!(Input::try_from(raw).unwrap(), Input::Key(key));
+    }
+    assert_eq!(key_name(Key::Numlock), "Numlock");
+    assert_eq!(key_from_name("Capital"), Some(Key::Capital));
+
+    let map = InputMap::default()
+        .unbind(Input::Key(Key::W))
+        .bind(Input::Key(Key::Numlock), Action::MoveForward)
+        .bind(Input::Mouse(MouseButton::Other(4)), Action::MoveUp);
+    // saved grouped by action
+    let json = serde_json::to_string(&map).unwrap();
+    let loaded = serde_json::from_str::<InputMap>(&json).unwrap();
+    assert_eq!(loaded.bindings.len(), map.bindings.len());
+    assert!(map.bindings.iter().all(|b| loaded.bindings.contains(b)));
+
+    let dir = crate::test_util::TempDir::new("input_map");
+    for ext in &["json", "ron"] {
+        let path = dir.join(format!("input_map.{}", ext));
+        map.save(&path).unwrap();
+        let loaded = InputMap::load(&path).unwrap();
+        assert!(map.bindings.iter().all(|b| loaded.bindings.contains(b)));
+    }
+    assert!(map.save(dir.join("input_map.toml")).is_err());
+
+    let parse = |json: &str| serde_json::from_str::<InputMap>(json);
+    assert!(parse(r#"{"bindings": {"MoveUp": [{"Key": "Hyper"}]}}"#).is_err());
+    assert!(parse(r#"{"bindings": {"MoveUp": [{"Mouse": "Fourth"}]}}"#).is_err());
+    assert!(parse(r#"{"bindings": {"Jump": [{"Key": "Space"}]}}"#).is_err());
+    let pedal = parse(r#"{"bindings": {"MoveUp": [{"Mouse": "8"}]}, "invert_y": true}"#).unwrap();
+    assert_eq!(
+        pedal.bindings,
+        vec![(Input::Mouse(MouseButton::Other(8)), Action::MoveUp)]
+    );
+    assert_eq!(pedal.mouse_sensitivity, 1.0);
+
+    assert!(InputMap::default().conflicts().is_empty());
+    let conflicting = InputMap::default()
+        .bind(Input::Key(Key::W), Action::MoveUp)
+        .bind(Input::Key(Key::W), Action::MoveForward);
+    assert_eq!(
+        conflicting.conflicts(),
+        vec![(
+            Input::Key(Key::W),
+            vec![Action::MoveForward, Action::MoveUp]
+        )]
+    );
+    let path = dir.join(format!("input_map_{}.json", std::process::id()));
+    conflicting.save(&path).unwrap();
+    assert!(InputMap::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    let raw = r#"{"bindings": {"MoveUp": [{"Key": "W"}], "MoveForward": [{"Key": "W"}]}}"#;
+    assert!(parse(raw).is_err());
+
+    // repeated presses of a held key are released at once
+    let mut map = InputMap::default();
+    map.look = vec![Input::Key(Key::LAlt), Input::Mouse(MouseButton::Right)];
+    assert!(map.mouse_moved(1.0, 1.0).is_none());
+    map.key(Key::LAlt, true);
+    map.key(Key::LAlt, true);
+    map.mouse_button(MouseButton::Right, true);
+    map.key(Key::LAlt, false);
+    assert!(map.mouse_moved(1.0, 1.0).is_some());
+    map.mouse_button(MouseButton::Right, false);
+    assert!(map.mouse_moved(1.0, 1.0).is_none());
+    // a release without a press, e.g. outside the window, is ignored
+    map.mouse_button(MouseButton::Right, false);
+    assert!(map.mouse_moved(1.0, 1.0).is_none());
+}
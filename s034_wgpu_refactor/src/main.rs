@@ -3,23 +3,88 @@ use nannou::prelude::*;
 pub type BufferSize = std::num::NonZeroU64;
 
 mod camera_controller;
+mod input_map;
+mod offline;
 mod pass;
 mod pass_compute;
+mod pass_particles;
 mod pass_triangle;
+mod preview;
 mod renderer;
+#[cfg(test)]
+mod test_util;
 
+use crate::offline::{FixedClock, OfflineConfig, OfflineScene};
 use crate::pass::PassMain;
+use crate::pass_particles::PassParticles;
 use crate::pass_triangle::PassTriangle;
+use crate::preview::PreviewConfig;
+use crate::renderer::accumulation::{self, Accumulator};
+use crate::renderer::brdf::ToneMapping;
+use crate::renderer::camera::ProjectionTile;
+use crate::renderer::capture::{CaptureQueue, GifWriterBuilder};
+use crate::renderer::deferred::{self, GBufferChannel, RenderPath};
+use crate::renderer::graph::{RenderGraph, RenderGraphBuilder, TextureDesc, TextureSize};
+use crate::renderer::manifest::{CameraState, Manifest};
+use crate::renderer::pipeline::PipelineCache;
+use crate::renderer::post::{self, Effect, FullscreenPass, FullscreenPassDesc, PostStack};
+use crate::renderer::ssao::SsaoConfig;
+use crate::renderer::tiled::{StripWriter, TileGrid};
 // use bytemuck;
 // use futures;
 
 struct Model {
-    pass: pass::PassMain,
+    graph: RenderGraph,
+    post: PostStack,
     last_mouse_pos: Point2<f32>,
+    capture: Option<WindowCapture>,
+}
+
+impl Model {
+    fn pass(&mut self) -> &mut PassMain {
+        self.graph.pass_mut("main").unwrap()
+    }
+
+    fn particles(&mut self) -> Option<&mut PassParticles> {
+        self.graph.pass_mut("particles")
+    }
+
+    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: std::time::Duration) {
+        self.pass().update(device, queue, dt);
+        if let Some(particles) = self.particles() {
+            particles.update(queue, dt);
+        }
+        self.post.update(&mut self.graph, queue, dt);
+    }
+
+    /// reads back the last rendered frame if the window is captured
+    fn capture(&mut self, app: &App) -> anyhow::Result<()> {
+        let time = app.duration.since_start.as_secs_f64();
+        let camera = CameraState::from_camera(self.pass().camera());
+        match self.capture.as_mut() {
+            Some(capture) => capture.capture(&self.graph, time, camera),
+            None => Ok(()),
+        }
+    }
 }
 
 fn main() {
-    nannou::app(model).event(event).update(update).run();
+    if std::env::args().any(|arg| arg == "--help") {
+        print!("{}", offline::USAGE);
+        return;
+    }
+    if let Some(config) = PreviewConfig::from_args(std::env::args()).unwrap() {
+        preview::run(&config).unwrap();
+        return;
+    }
+    match OfflineConfig::from_args(std::env::args()).unwrap() {
+        Some(config) => futures::executor::block_on(render_offline(&config)).unwrap(),
+        None => nannou::app(model)
+            .event(event)
+            .update(update)
+            .exit(exit)
+            .run(),
+    }
 }
 
 fn model(app: &App) -> Model {
@@ -31,8 +96,11 @@ fn model(app: &App) -> Model {
         .key_pressed(key_pressed)
         .key_released(key_released)
         .mouse_moved(mouse_moved)
+        .mouse_pressed(mouse_pressed)
+        .mouse_released(mouse_released)
         .mouse_wheel(mouse_wheel)
         .resized(resized)
+        .msaa_samples(4)
         .raw_view(raw_view)
         .build()
         .unwrap();
@@ -41,26 +109,677 @@ fn model(app: &App) -> Model {
     let device = window.swap_chain_device();
     let queue = window.swap_chain_queue();
     let sc_desc = window.swap_chain_descriptor();
+    let sample_count = window.msaa_samples();
+
+    let capture = capture_dir().map(|dir| WindowCapture::new(&window, dir).unwrap());
+    let graph = RenderGraphBuilder::new().import("swap_chain");
+    let (graph, output) = match &capture {
+        Some(_) => (present(device, queue, sc_desc, graph), WINDOW_OUTPUT),
+        None => (graph, "swap_chain"),
+    };
+    let (graph, post) = build_scene(
+        device,
+        queue,
+        sc_desc,
+        sample_count,
+        0,
+        false,
+        graph,
+        output,
+    )
+    .unwrap();
+
+    let mut model = Model {
+        graph,
+        post,
+        last_mouse_pos: app.mouse.position(),
+        capture: None,
+    };
+    if let Some(mut capture) = capture {
+        scene_parameters(&mut model, &mut capture.manifest);
+        model.capture = Some(capture);
+    }
+    model
+}
+
+/// `--capture <dir>` saves every frame of the window into dir with a manifest.json, and
+/// `--png-text` embeds the record of each frame into its PNG
+fn capture_dir() -> Option<std::path::PathBuf> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--capture");
+    args.nth(1).map(std::path::PathBuf::from)
+}
+
+/// graph output of the scene when the window is captured, copied to the swap chain by `present`
+const WINDOW_OUTPUT: &str = "window";
+
+/// declares `WINDOW_OUTPUT` and draws it on the swap chain, which can only be rendered to, with
+/// a tone mapping pass without a curve
+fn present(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sc_desc: &wgpu::SwapChainDescriptor,
+    graph: RenderGraphBuilder,
+) -> RenderGraphBuilder {
+    let usage = wgpu::TextureUsage::OUTPUT_ATTACHMENT
+        | wgpu::TextureUsage::SAMPLED
+        | wgpu::TextureUsage::COPY_SRC;
+    let desc = FullscreenPassDesc::new(
+        "present",
+        "post_tone_mapping",
+        include_bytes!("../shaders/post_tone_mapping.frag.spv"),
+    )
+    .input(WINDOW_OUTPUT)
+    .output("swap_chain", sc_desc.format);
+    let mut pass = FullscreenPass::new(device, queue, &mut PipelineCache::new(), &desc);
+    let copy = Effect::ToneMapping(post::ToneMappingParams {
+        tone_mapping: ToneMapping::None,
+        exposure: 1.0,
+    });
+    pass.set_params(queue, copy.to_raw(0.0)[0]);
+    graph
+        .output(
+            WINDOW_OUTPUT,
+            TextureDesc::new(sc_desc.format, TextureSize::Surface, usage),
+        )
+        .pass(pass)
+}
+
+/// saves the frames rendered into `WINDOW_OUTPUT` without waiting for them
+struct WindowCapture {
+    device_queue: std::sync::Arc<wgpu::DeviceQueuePair>,
+    captures: CaptureQueue,
+    dir: std::path::PathBuf,
+    /// the size is the last one of the window
+    manifest: Manifest,
+    png_text: bool,
+    frame: u32,
+    /// set by `raw_view`, the frame is read back on the next `update`
+    rendered: std::cell::Cell<bool>,
+}
+
+impl WindowCapture {
+    fn new(window: &nannou::window::Window, dir: std::path::PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let device_queue = window.swap_chain_device_queue_pair().clone();
+        let sc_desc = window.swap_chain_descriptor();
+        let captures = Self::captures(device_queue.device(), [sc_desc.width, sc_desc.height]);
+        let manifest = Manifest::new(env!("CARGO_PKG_NAME"), sc_desc.width, sc_desc.height)
+            .args(std::env::args())
+            .parameter("msaa", window.msaa_samples());
+        Ok(Self {
+            device_queue,
+            captures,
+            dir,
+            manifest,
+            png_text: std::env::args().any(|arg| arg == "--png-text"),
+            frame: 0,
+            rendered: std::cell::Cell::new(false),
+        })
+    }
+
+    fn captures(device: &wgpu::Device, [width, height]: [u32; 2]) -> CaptureQueue {
+        CaptureQueue::new(device, width, height, 3, rayon::current_num_threads(), 8)
+    }
+
+    fn manifest_path(&self) -> std::path::PathBuf {
+        self.dir.join("manifest.json")
+    }
+
+    /// reads back the frame of the last `raw_view`, if it wasn't already, and records it with
+    /// the app time in seconds and the camera it was rendered with
+    fn capture(
+        &mut self,
+        graph: &RenderGraph,
+        time: f64,
+        camera: CameraState,
+    ) -> anyhow::Result<()> {
+        if !self.rendered.replace(false) {
+            return Ok(());
+        }
+        let path = renderer::capture::sequence_path(&self.dir, self.frame);
+        self.manifest
+            .push_frame(self.frame, time, Some(path.clone()), Some(camera));
+        let text = if self.png_text {
+            self.manifest.text(self.manifest.frames.last().unwrap())
+        } else {
+            Vec::new()
+        };
+        let device = self.device_queue.device();
+        let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("window capture"),
+        });
+        self.captures.capture(
+            device,
+            self.device_queue.queue(),
+            encoder,
+            graph.texture(WINDOW_OUTPUT).unwrap(),
+            path,
+            text,
+        )?;
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// the frames of the old size and the manifest so far are saved before the readback
+    /// buffers are replaced
+    fn resized(&mut self, graph: &RenderGraph) -> anyhow::Result<()> {
+        let device = self.device_queue.device();
+        let captures = std::mem::replace(&mut self.captures, Self::captures(device, graph.size()));
+        captures.finish(device)?;
+        let [width, height] = graph.size();
+        self.manifest.width = width;
+        self.manifest.height = height;
+        self.manifest.save(self.manifest_path())?;
+        Ok(())
+    }
+
+    /// the last frame must have been captured already
+    fn finish(self) -> anyhow::Result<()> {
+        let saved = self.captures.finish(self.device_queue.device())?;
+        self.manifest.save(self.manifest_path())?;
+        println!("{} frames in {}", saved, self.dir.display());
+        Ok(())
+    }
+}
+
+/// the scene of `PassMain` and the passes drawn on it, post processed into `output`, which must
+/// be declared in `graph` with the format of `sc_desc`. `seed` seeds the particles and the SSAO
+/// kernel. `tiled` leaves out the post effects which would show seams between tiles
+#[allow(clippy::too_many_arguments)]
+fn build_scene(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sc_desc: &wgpu::SwapChainDescriptor,
+    sample_count: u32,
+    seed: u64,
+    tiled: bool,
+    graph: RenderGraphBuilder,
+    output: &str,
+) -> anyhow::Result<(RenderGraph, PostStack)> {
+    let mut pipeline_cache = PipelineCache::new();
+    let mut pass = PassMain::new(device, queue, sc_desc, &mut pipeline_cache, sample_count)?;
+    if std::env::args().any(|arg| arg == "--deferred") {
+        pass.set_render_path(RenderPath::Deferred);
+    }
+    pass.set_ssao_enabled(!std::env::args().any(|arg| arg == "--no-ssao"));
+    let ssao = SsaoConfig {
+        seed,
+        ..*pass.ssao_config()
+    };
+    pass.set_ssao_config(queue, ssao);
+    let camera_path = std::path::Path::new("camera_path.ron");
+    if camera_path.exists() {
+        let camera_path = renderer::camera_path::CameraPath::load(camera_path)?;
+        pass.set_camera_path(Some(camera_path));
+    }
+    let input_map = std::path::Path::new("input_map.ron");
+    if input_map.exists() {
+        pass.set_input_map(input_map::InputMap::load(input_map)?);
+    }
+    let environment = std::path::Path::new("environment.hdr");
+    if environment.exists() {
+        let image = renderer::environment::EquirectImage::load(environment)?;
+        let maps = renderer::environment::EnvironmentMaps::from_equirect(
+            &image,
+            &renderer::environment::EnvironmentConfig::default(),
+        );
+        pass.set_environment(device, queue, &maps);
+    }
+    let size = [sc_desc.width, sc_desc.height];
+
+    let mut post = PostStack::new()
+        .effect(Effect::Bloom(post::BloomParams::default()))
+        .effect(Effect::ToneMapping(post::ToneMappingParams::default()));
+    let grading = std::path::Path::new("grading.cube");
+    if grading.exists() {
+        let lut = renderer::lut::Lut3d::load(grading)?;
+        post = post.effect(Effect::ColorGrading(post::ColorGradingParams::new(lut)));
+    }
+    post = post
+        .effect(Effect::Fxaa(post::FxaaParams::default()))
+        .effect(Effect::ChromaticAberration(
+            post::ChromaticAberrationParams::default(),
+        ))
+        .effect(Effect::Vignette(post::VignetteParams::default()))
+        .effect(Effect::FilmGrain(post::FilmGrainParams::default()));
+    if tiled {
+        post = post.without_neighbourhood();
+    }
+
+    let mut graph = graph
+        .texture("hdr", PassMain::color_desc())
+        .texture(PassMain::MSAA_TARGET, PassMain::msaa_desc(sample_count))
+        .texture("depth", PassMain::depth_desc(sample_count));
+    for (name, desc) in pass.gbuffer_formats().descs() {
+        graph = graph.texture(name, desc);
+    }
+    for (name, desc) in SsaoConfig::descs() {
+        graph = graph.texture(name, desc);
+    }
+    // the particles are drawn on the scene with its depth
+    let particles = if std::env::args().any(|arg| arg == "--particles") {
+        let (sample_count, depth) = match pass.render_path() {
+            RenderPath::Forward => (pass.sample_count(), "depth"),
+            RenderPath::Deferred => (1, deferred::DEPTH),
+        };
+        Some(PassParticles::new(
+            device,
+            &mut pipeline_cache,
+            pass.camera(),
+            renderer::particle::ParticleConfig {
+                seed: seed as u32,
+                ..Default::default()
+            },
+            sample_count,
+            depth,
+        ))
+    } else {
+        None
+    };
+    let mut graph = graph.pass(pass);
+    if let Some(particles) = particles {
+        graph = graph.pass(particles);
+    }
+    let graph = post
+        .add_to_graph(
+            device,
+            queue,
+            &mut pipeline_cache,
+            graph,
+            "hdr",
+            output,
+            sc_desc.format,
+        )
+        .build(device, size)?;
+    Ok((graph, post))
+}
+
+/// format and graph output of the main scene when rendering offline
+const OFFLINE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const OFFLINE_OUTPUT: &str = "frame";
+
+/// the scene of the window, rendered to `OFFLINE_OUTPUT` with the size of `sc_desc`
+fn offline_model(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    sc_desc: &wgpu::SwapChainDescriptor,
+    sample_count: u32,
+    seed: u64,
+    tiled: bool,
+) -> anyhow::Result<Model> {
+    let graph = RenderGraphBuilder::new().output(
+        OFFLINE_OUTPUT,
+        TextureDesc::new(
+            OFFLINE_FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        ),
+    );
+    let (graph, post) = build_scene(
+        device,
+        queue,
+        sc_desc,
+        sample_count,
+        seed,
+        tiled,
+        graph,
+        OFFLINE_OUTPUT,
+    )?;
+    Ok(Model {
+        graph,
+        post,
+        last_mouse_pos: Point2::new(0.0, 0.0),
+        capture: None,
+    })
+}
+
+/// options of the main scene which aren't in `OfflineConfig`
+fn scene_parameters(model: &mut Model, manifest: &mut Manifest) {
+    let pass = model.pass();
+    manifest.set_parameter("render_path", format!("{:?}", pass.render_path()));
+    manifest.set_parameter("ssao", pass.ssao_enabled());
+    manifest.set_parameter("particles", model.particles().is_some());
+    let effects = model.post.effects().iter().map(Effect::name);
+    manifest.set_parameter("post", effects.collect::<Vec<_>>().join(","));
+    if !model.post.dropped().is_empty() {
+        manifest.set_parameter("dropped_post", dropped_effects(&model.post));
+    }
+}
+
+/// names of the effects `tiled` left out of `post`
+fn dropped_effects(post: &PostStack) -> String {
+    let names = post.dropped().iter().map(Effect::name);
+    names.collect::<Vec<_>>().join(",")
+}
+
+/// adds `frame` to `manifest`. returns the tEXt chunks of its PNG, empty unless they are embedded
+fn record_frame(
+    manifest: &mut Manifest,
+    config: &OfflineConfig,
+    frame: u32,
+    path: Option<std::path::PathBuf>,
+    camera: Option<CameraState>,
+) -> Vec<(String, String)> {
+    let time = FixedClock::new(config.fps).time(frame).as_secs_f64();
+    manifest.push_frame(frame, time, path, camera);
+    if config.png_text {
+        manifest.text(manifest.frames.last().unwrap())
+    } else {
+        Vec::new()
+    }
+}
+
+/// renders `config.frames` without a window. see `offline`
+async fn render_offline(config: &OfflineConfig) -> anyhow::Result<()> {
+    let (device, queue) = offline::request_device(config.adapter.as_deref()).await?;
+    if let Some(max_tile) = config.tile {
+        return render_tiled(config, &device, &queue, max_tile).await;
+    }
+    let sc_desc = config.swap_chain_descriptor(OFFLINE_FORMAT);
+    let size = [config.width, config.height];
+    let clock = FixedClock::new(config.fps);
+
+    enum Scene {
+        Main(Model),
+        Triangle(RenderGraph),
+    }
+    let mut scene = match config.scene {
+        OfflineScene::Main => Scene::Main(offline_model(
+            &device,
+            &queue,
+            &sc_desc,
+            config.sample_count,
+            config.seed,
+            false,
+        )?),
+        OfflineScene::Triangle => Scene::Triangle(
+            RenderGraphBuilder::new()
+                .output("triangle", PassTriangle::output_desc())
+                .texture(
+                    PassTriangle::MSAA_TARGET,
+                    PassTriangle::msaa_desc(config.sample_count),
+                )
+                .pass(PassTriangle::new(
+                    &device,
+                    &queue,
+                    &sc_desc,
+                    &mut PipelineCache::new(),
+                    config.sample_count,
+                ))
+                .build(&device, size)?,
+        ),
+    };
+    let mut manifest = config
+        .manifest(env!("CARGO_PKG_NAME"))
+        .args(std::env::args());
+    if let Scene::Main(model) = &mut scene {
+        scene_parameters(model, &mut manifest);
+    }
+
+    let mut gif = match &config.gif {
+        Some(path) => Some(
+            GifWriterBuilder::new(config.width, config.height)
+                .fps(config.fps)
+                .create(path)?,
+        ),
+        None => None,
+    };
+
+    let mut video = match config.video_writer() {
+        Some(builder) => Some(builder.build()?),
+        None => None,
+    };
+    if let Some(reason) = video.as_ref().and_then(|video| video.fallback_reason()) {
+        eprintln!("{}", reason);
+    }
+    let mut golden = config.golden_set();
+    // PNGs are saved in the background unless the frames are needed here
+    let mut captures = match (&gif, &video) {
+        (None, None) if !config.accumulates() && golden.is_none() => Some(CaptureQueue::new(
+            &device,
+            config.width,
+            config.height,
+            3,
+            rayon::current_num_threads(),
+            8,
+        )),
+        _ => None,
+    };
+
+    let mut accumulator = if config.accumulates() {
+        Some(Accumulator::new(config.width, config.height))
+    } else {
+        None
+    };
+
+    // the frames before the range are only simulated
+    for frame in 0..config.frames.end {
+        let rendered = frame >= config.frames.start;
+        let path = match &video {
+            Some(video) if !video.is_fallback() => None,
+            _ => Some(config.frame_path(frame)),
+        };
+        let (image, text) = match (&mut scene, accumulator.as_mut()) {
+            (Scene::Main(model), Some(accumulator)) if rendered => {
+                accumulator.clear();
+                render_accumulated(&device, &queue, model, config, frame, accumulator).await?;
+                let camera = CameraState::from_camera(model.pass().camera());
+                let text = record_frame(&mut manifest, config, frame, path, Some(camera));
+                (accumulator.resolve(), text)
+            }
+            (scene, _) => {
+                let (graph, output, camera) = match scene {
+                    Scene::Main(model) => {
+                        model.update(&device, &queue, clock.dt(frame));
+                        let camera = CameraState::from_camera(model.pass().camera());
+                        (&mut model.graph, OFFLINE_OUTPUT, Some(camera))
+                    }
+                    Scene::Triangle(graph) => {
+                        graph
+                            .pass_mut::<PassTriangle>("triangle")
+                            .unwrap()
+                            .set_frame(frame as i32);
+                        (graph, "triangle", None)
+                    }
+                };
+                if !rendered {
+                    continue;
+                }
+                let text = record_frame(&mut manifest, config, frame, path, camera);
+                let mut encoder =
+                    device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                graph.render(&mut encoder, &[]);
+                let texture = graph.texture(output).unwrap();
+                if let Some(captures) = captures.as_mut() {
+                    captures.capture(
+                        &device,
+                        &queue,
+                        encoder,
+                        texture,
+                        config.frame_path(frame),
+                        text,
+                    )?;
+                    continue;
+                }
+                queue.submit(std::iter::once(encoder.finish()));
+                let image = renderer::capture::read_texture(&device, &queue, texture).await?;
+                (image, text)
+            }
+        };
+
+        match video.as_mut() {
+            Some(video) => video.write_frame_with_text(&image, &text)?,
+            None => {
+                let path = config.frame_path(frame);
+                offline::save_frame(&image, &path, &text)?;
+                println!("{}", path.display());
+            }
+        }
+        if let Some(gif) = gif.as_mut() {
+            gif.write_frame(&image)?;
+        }
+        if let Some(golden) = golden.as_mut() {
+            golden.check(frame, &image)?;
+        }
+    }
+    if let Some(captures) = captures {
+        let saved = captures.finish(&device)?;
+        println!("{} frames in {}", saved, config.output.display());
+    }
+    if let (Some(video), Some(path)) = (video, &config.video) {
+        if !video.is_fallback() {
+            video.finish()?;
+            println!("{}", path.display());
+        }
+    }
+    if let (Some(gif), Some(path)) = (gif, &config.gif) {
+        gif.finish()?;
+        println!("{}", path.display());
+    }
+    manifest.save(config.manifest_path())?;
+    println!("{}", config.manifest_path().display());
+    if let Some(golden) = golden {
+        println!("{}", golden.report());
+        golden.finish()?;
+    }
+    Ok(())
+}
 
-    let pass = PassMain::new(device, queue, sc_desc);
-    let last_mouse_pos = app.mouse.position();
+/// adds `config.samples` sub-frames of `frame` to `accumulator`. they are spread over the
+/// shutter interval before the frame time, where the scene is left
+async fn render_accumulated(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    model: &mut Model,
+    config: &OfflineConfig,
+    frame: u32,
+    accumulator: &mut Accumulator,
+) -> anyhow::Result<()> {
+    let clock = FixedClock::new(config.fps);
+    let size = [config.width, config.height];
+    let end = clock.time(frame);
+    let open = clock.dt(frame).mul_f32(config.shutter);
+    let mut now = end - clock.dt(frame);
 
-    // offline render
-    let pass_triangle = PassTriangle::new(device, queue, sc_desc);
+    for subframe in accumulation::subframes(config.samples, config.jitter) {
+        let time = (end - open.mul_f32(1.0 - subframe.time)).max(now);
+        // the whole frustum for the culling and the shadow cascades
+        model.pass().set_projection_tile(device, queue, size, None);
+        model.update(device, queue, time - now);
+        now = time;
 
-    for i in 0..10 {
+        let [x, y] = subframe.offset;
+        let jitter = ProjectionTile {
+            x: x / config.width as f32,
+            y: y / config.height as f32,
+            width: 1.0,
+            height: 1.0,
+        };
+        model
+            .pass()
+            .set_projection_tile(device, queue, size, Some(jitter));
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        pass_triangle.render(&mut encoder, &i);
+        model.graph.render(&mut encoder, &[]);
+        queue.submit(std::iter::once(encoder.finish()));
 
-        let path = format!("image_{}.png", i);
-        renderer::capture_frame(device, queue, encoder, pass_triangle.output_texture(), path);
+        let texture = model.graph.texture(OFFLINE_OUTPUT).unwrap();
+        accumulator.add(&renderer::capture::read_texture(device, queue, texture).await?);
     }
+    model.pass().set_projection_tile(device, queue, size, None);
+    model.update(device, queue, end - now);
+    Ok(())
+}
+
+/// renders the main scene in tiles of a `TileGrid`, streaming each frame into a PNG
+async fn render_tiled(
+    config: &OfflineConfig,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    max_tile: u32,
+) -> anyhow::Result<()> {
+    if config.scene != OfflineScene::Main || config.gif.is_some() || config.video.is_some() {
+        anyhow::bail!("Tiled rendering only writes PNGs of the main scene");
+    }
+    let grid = TileGrid::new(config.width, config.height, max_tile);
+    let sc_desc = wgpu::SwapChainDescriptor {
+        width: grid.tile_width,
+        height: grid.tile_height,
+        ..config.swap_chain_descriptor(OFFLINE_FORMAT)
+    };
+    let mut model = offline_model(
+        device,
+        queue,
+        &sc_desc,
+        config.sample_count,
+        config.seed,
+        true,
+    )?;
+    let mut manifest = config
+        .manifest(env!("CARGO_PKG_NAME"))
+        .args(std::env::args());
+    scene_parameters(&mut model, &mut manifest);
+    if !model.post.dropped().is_empty() {
+        eprintln!(
+            "{} would show seams between tiles and are left out",
+            dropped_effects(&model.post)
+        );
+    }
+    let size = [config.width, config.height];
+    let clock = FixedClock::new(config.fps);
+    println!(
+        "{}x{} in {}x{} tiles of {}x{}",
+        grid.width,
+        grid.height,
+        grid.columns(),
+        grid.rows(),
+        grid.tile_width,
+        grid.tile_height
+    );
+
+    for frame in 0..config.frames.end {
+        // the whole frustum for the culling and the shadow cascades
+        model.pass().set_projection_tile(device, queue, size, None);
+        model.update(device, queue, clock.dt(frame));
+        if frame < config.frames.start {
+            continue;
+        }
+
+        let path = config.frame_path(frame);
+        let camera = CameraState::from_camera(model.pass().camera());
+        let text = record_frame(
+            &mut manifest,
+            config,
+            frame,
+            Some(path.clone()),
+            Some(camera),
+        );
+        std::fs::create_dir_all(&config.output)?;
+        let file = std::fs::File::create(&path)?;
+        let mut writer = StripWriter::new(std::io::BufWriter::new(file), grid, &text)?;
+        for tile in grid.tiles() {
+            let projection = tile.projection(&grid);
+            model
+                .pass()
+                .set_projection_tile(device, queue, size, Some(projection));
+            model
+                .post
+                .set_tile(&mut model.graph, queue, Some(projection));
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            model.graph.render(&mut encoder, &[]);
+            queue.submit(std::iter::once(encoder.finish()));
 
-    Model {
-        pass,
-        last_mouse_pos,
+            let texture = model.graph.texture(OFFLINE_OUTPUT).unwrap();
+            let pixels = renderer::capture::read_texture(device, queue, texture).await?;
+            writer.write_tile(&tile, &pixels)?;
+        }
+        writer.finish()?;
+        println!("{}", path.display());
     }
+    manifest.save(config.manifest_path())?;
+    println!("{}", config.manifest_path().display());
+    Ok(())
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
@@ -68,43 +787,83 @@ fn update(app: &App, model: &mut Model, _update: Update) {
     let device = window.swap_chain_device();
     let queue = window.swap_chain_queue();
 
-    model
-        .pass
-        .update(device, queue, app.duration.since_prev_update);
+    model.capture(app).unwrap();
+    model.update(device, queue, app.duration.since_prev_update);
 }
 
 fn event(_app: &App, _model: &mut Model, _event: Event) {}
 
+/// saves the last frame, the ones still in flight and the manifest
+fn exit(app: &App, mut model: Model) {
+    model.capture(app).unwrap();
+    if let Some(capture) = model.capture {
+        capture.finish().unwrap();
+    }
+}
+
 fn resized(app: &App, model: &mut Model, _: Vector2) {
     let window = app.main_window();
     let device = window.swap_chain_device();
     let sc_desc = window.swap_chain_descriptor();
 
-    model.pass.resized(device, sc_desc);
+    // the last frame is read back before its texture is recreated
+    model.capture(app).unwrap();
+    model.graph.resized(device, [sc_desc.width, sc_desc.height]);
+    if let Some(capture) = model.capture.as_mut() {
+        capture.resized(&model.graph).unwrap();
+    }
 }
 
-fn key_pressed(_app: &App, model: &mut Model, key: Key) {
-    model.pass.key_pressed(key);
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
+    // cycles the debug view of the G-buffer
+    if key == Key::G && model.pass().render_path() == RenderPath::Deferred {
+        let next = match model.pass().gbuffer_channel() {
+            GBufferChannel::Lit => GBufferChannel::Albedo,
+            GBufferChannel::Albedo => GBufferChannel::Normal,
+            GBufferChannel::Normal => GBufferChannel::Material,
+            GBufferChannel::Material => GBufferChannel::Depth,
+            GBufferChannel::Depth => GBufferChannel::Lit,
+        };
+        let window = app.main_window();
+        model
+            .pass()
+            .set_gbuffer_channel(window.swap_chain_queue(), next);
+        return;
+    }
+    model.pass().key_pressed(key);
 }
 
 fn key_released(_app: &App, model: &mut Model, key: Key) {
-    model.pass.key_released(key);
+    model.pass().key_released(key);
+}
+
+fn mouse_pressed(_app: &App, model: &mut Model, button: MouseButton) {
+    model.pass().mouse_pressed(button);
+}
+
+fn mouse_released(_app: &App, model: &mut Model, button: MouseButton) {
+    model.pass().mouse_released(button);
 }
 
 fn mouse_moved(_app: &App, model: &mut Model, pos: Point2) {
-    model.pass.mouse_moved(pos, model.last_mouse_pos);
+    let last_mouse_pos = model.last_mouse_pos;
+    model.pass().mouse_moved(pos, last_mouse_pos);
     model.last_mouse_pos = pos;
 }
 
 fn mouse_wheel(_app: &App, model: &mut Model, dt: MouseScrollDelta, _phase: TouchPhase) {
-    model.pass.mouse_wheel(&dt);
+    model.pass().mouse_wheel(&dt);
 }
 
 fn raw_view(app: &App, model: &Model, raw_frame: RawFrame) {
     let window = app.main_window();
     let device = window.swap_chain_device();
     let mut encoder = raw_frame.command_encoder();
-    model
-        .pass
-        .render(&mut encoder, raw_frame.swap_chain_texture());
+    model.graph.render(
+        &mut encoder,
+        &[("swap_chain", raw_frame.swap_chain_texture())],
+    );
+    if let Some(capture) = &model.capture {
+        capture.rendered.set(true);
+    }
 }
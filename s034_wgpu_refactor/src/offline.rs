@@ -0,0 +1,553 @@
+//! headless offline rendering
+//!
+//! `--offline` renders a range of frames at a fixed timestep without a window, so the same range
+//! always renders the same images whatever the machine. the options are listed in `USAGE`
+use anyhow::*;
+use nannou::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::renderer::accumulation::JitterSequence;
+use crate::renderer::capture::{self, VideoWriterBuilder};
+use crate::renderer::golden::{GoldenSet, Tolerance};
+use crate::renderer::manifest::{self, Manifest};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OfflineScene {
+    /// the scene of the window with the post stack
+    Main,
+    /// `PassTriangle`, whose color depends on the frame
+    Triangle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineConfig {
+    pub scene: OfflineScene,
+    pub frames: Range<u32>,
+    pub fps: f64,
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+    pub output: PathBuf,
+    /// GIF of the frames besides the PNGs
+    pub gif: Option<PathBuf>,
+    /// video instead of the PNGs
+    pub video: Option<PathBuf>,
+    /// program and arguments of the video encoder, see `VideoWriterBuilder::command`
+    pub encoder: Option<Vec<String>>,
+    /// sub-frames per frame. accumulation is off with 1
+    pub samples: u32,
+    /// part of the frame interval the sub-frames are spread over, in [0, 1]
+    pub shutter: f32,
+    pub jitter: JitterSequence,
+    /// maximum tile size of tiled rendering. see `renderer::tiled`
+    pub tile: Option<u32>,
+    pub seed: u64,
+    /// key fields of the manifest as tEXt chunks of the PNGs
+    pub png_text: bool,
+    /// directory of the reference frames
+    pub golden: Option<PathBuf>,
+    /// overwrites the references with the frames
+    pub bless: bool,
+    pub tolerance: Tolerance,
+    /// substring of the adapter name. the default adapter if `None`
+    pub adapter: Option<String>,
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        Self {
+            scene: OfflineScene::Main,
+            frames: 0..10,
+            fps: 30.0,
+            width: 800,
+            height: 600,
+            sample_count: 4,
+            output: PathBuf::from("capture"),
+            gif: None,
+            video: None,
+            encoder: None,
+            samples: 1,
+            shutter: 0.5,
+            jitter: JitterSequence::Halton,
+            tile: None,
+            seed: 0,
+            png_text: false,
+            golden: None,
+            bless: false,
+            tolerance: Tolerance::default(),
+            adapter: None,
+        }
+    }
+}
+
+/// printed by `--help`
+pub const USAGE: &str = "\
+usage: cargo run --release -- --offline [options]
+  e.g. --offline --frames 0..120 --fps 30 --size 1920x1080 --out capture
+
+frames before the range are simulated without rendering, and the frames are written as
+frame_00042.png into the output directory with a manifest.json of the session
+
+  --frames <start..end|n>  frames to render, 0..10 by default
+  --fps <fps>              fixed timestep, 30 by default
+  --size <width>x<height>  frame size, 800x600 by default
+  --msaa <1|4>             MSAA sample count, 4 by default
+  --out <dir>              output directory, capture by default
+  --scene <main|triangle>  the scene of the window or the MSAA test triangle
+  --deferred, --no-ssao, --particles
+                           options of the main scene, the same as in the window
+  --seed <n>               seeds the particles and the SSAO kernel
+  --gif <path>             also streams the frames into an animated GIF
+  --video <path>           pipes the frames to ffmpeg instead of writing PNGs, which are
+                           written if the encoder can't be started
+  --encoder <command>      encoder instead of ffmpeg, split on whitespace without quoting
+  --encoder-arg <arg>      appends one argument to the encoder as it is
+  --tile <max>             renders in tiles of at most max pixels a side, for sizes beyond
+                           the maximum texture size. the post effects which would show seams
+                           between tiles, bloom, FXAA and chromatic aberration, are left out
+  --samples <n>            accumulates n sub-frames per frame
+  --shutter <fraction>     part of the frame interval the sub-frames span, 0.5 by default
+  --jitter <halton|r2>     sub-pixel offsets of the sub-frames
+  --png-text               embeds the manifest of each frame into its PNG
+  --golden <dir>           compares every frame with the references in dir, writes diff
+                           images and golden.txt into the output and fails if one differs
+  --bless                  writes the frames as the references of --golden instead
+  --tolerance <n>          channel difference of a mismatched pixel
+  --max-mismatch <f>       fraction of mismatched pixels allowed
+  --min-psnr <dB>          minimum PSNR
+  --min-ssim <ssim>        minimum SSIM
+  --adapter <name>         first adapter whose name contains it, e.g. llvmpipe
+";
+
+/// slower than this a frame number doesn't fit the `Duration` of `FixedClock`
+pub const MIN_FPS: f64 = 1e-3;
+
+/// options of the scene, read by `build_scene` in the window too
+pub const SCENE_FLAGS: &[&str] = &["--deferred", "--no-ssao", "--particles"];
+
+/// `--msaa` sample counts, the ones WebGPU guarantees for render targets
+pub const SAMPLE_COUNTS: &[u32] = &[1, 4];
+
+impl OfflineConfig {
+    /// `None` unless `--offline` is given. other `--` options than `SCENE_FLAGS` are an error
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>> {
+        let mut args = args.into_iter();
+        let mut offline = false;
+        let mut config = Self::default();
+        let mut unknown = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--offline" => offline = true,
+                "--scene" => {
+                    config.scene = match value()?.as_str() {
+                        "main" => OfflineScene::Main,
+                        "triangle" => OfflineScene::Triangle,
+                        scene => bail!("Unknown scene {}", scene),
+                    }
+                }
+                "--frames" => config.frames = parse_range(&value()?)?,
+                "--fps" => config.fps = value()?.parse()?,
+                "--size" => {
+                    let size = value()?;
+                    let (width, height) = split_pair(&size, 'x')?;
+                    config.width = width;
+                    config.height = height;
+                }
+                "--msaa" => config.sample_count = value()?.parse()?,
+                "--out" => config.output = PathBuf::from(value()?),
+                "--gif" => config.gif = Some(PathBuf::from(value()?)),
+                "--video" => config.video = Some(PathBuf::from(value()?)),
+                "--encoder" => {
+                    let command = value()?;
+                    let command = command
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect::<Vec<_>>();
+                    if command.is_empty() {
+                        bail!("Empty encoder command");
+                    }
+                    config.encoder = Some(command);
+                }
+                "--encoder-arg" => {
+                    let arg = value()?;
+                    match &mut config.encoder {
+                        Some(encoder) => encoder.push(arg),
+                        None => bail!("--encoder-arg needs an --encoder before it"),
+                    }
+                }
+                "--samples" => config.samples = value()?.parse()?,
+                "--shutter" => config.shutter = value()?.parse()?,
+                "--jitter" => {
+                    config.jitter = match value()?.as_str() {
+                        "halton" => JitterSequence::Halton,
+                        "r2" => JitterSequence::R2,
+                        jitter => bail!("Unknown jitter sequence {}", jitter),
+                    }
+                }
+                "--tile" => config.tile = Some(value()?.parse()?),
+                "--seed" => config.seed = value()?.parse()?,
+                "--png-text" => config.png_text = true,
+                "--golden" => config.golden = Some(PathBuf::from(value()?)),
+                "--bless" => config.bless = true,
+                "--tolerance" => config.tolerance.channel = value()?.parse()?,
+                "--max-mismatch" => config.tolerance.mismatched = value()?.parse()?,
+                "--min-psnr" => config.tolerance.min_psnr = value()?.parse()?,
+                "--min-ssim" => config.tolerance.min_ssim = value()?.parse()?,
+                "--adapter" => config.adapter = Some(value()?),
+                flag if flag.starts_with("--") && !SCENE_FLAGS.contains(&flag) => {
+                    unknown.push(arg.clone())
+                }
+                _ => {}
+            }
+        }
+        if let (true, Some(flag)) = (offline, unknown.first()) {
+            bail!("Unknown offline option {}, see --help", flag);
+        }
+        if !SAMPLE_COUNTS.contains(&config.sample_count) {
+            bail!(
+                "MSAA supports {:?} samples but got {}",
+                SAMPLE_COUNTS,
+                config.sample_count
+            );
+        }
+        if !config.fps.is_finite() || config.fps < MIN_FPS {
+            bail!(
+                "fps must be finite and at least {} but got {}",
+                MIN_FPS,
+                config.fps
+            );
+        }
+        if config.samples == 0 {
+            bail!("At least one sample is needed");
+        }
+        if !(0.0..=1.0).contains(&config.shutter) {
+            bail!("Shutter must be in [0, 1] but got {}", config.shutter);
+        }
+        if config.accumulates() && (config.tile.is_some() || config.scene != OfflineScene::Main) {
+            bail!("Accumulation only supports the main scene without tiles");
+        }
+        if config.golden.is_some() && config.tile.is_some() {
+            bail!("Tiled frames can't be compared with references");
+        }
+        if config.bless && config.golden.is_none() {
+            bail!("--bless needs the references of --golden");
+        }
+        if config.tile == Some(0) {
+            bail!("Tiles must not be empty");
+        }
+        if config.width == 0 || config.height == 0 {
+            bail!("Empty frame size {}x{}", config.width, config.height);
+        }
+        Ok(if offline { Some(config) } else { None })
+    }
+
+    /// `frame_00042.png` in `output`
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        capture::sequence_path(&self.output, frame)
+    }
+
+    /// `manifest.json` in `output`
+    pub fn manifest_path(&self) -> PathBuf {
+        self.output.join("manifest.json")
+    }
+
+    /// with the render options of this config as parameters
+    pub fn manifest(&self, sketch: &str) -> Manifest {
+        let mut manifest = Manifest::new(sketch, self.width, self.height)
+            .seed(self.seed)
+            .parameter("scene", format!("{:?}", self.scene))
+            .parameter("frames", format!("{:?}", self.frames))
+            .parameter("fps", self.fps)
+            .parameter("msaa", self.sample_count)
+            .parameter("samples", self.samples);
+        if self.accumulates() {
+            manifest.set_parameter("shutter", self.shutter);
+            manifest.set_parameter("jitter", format!("{:?}", self.jitter));
+        }
+        if let Some(tile) = self.tile {
+            manifest.set_parameter("tile", tile);
+        }
+        if let Some(video) = &self.video {
+            manifest.set_parameter("video", video.display());
+        }
+        if let Some(gif) = &self.gif {
+            manifest.set_parameter("gif", gif.display());
+        }
+        manifest
+    }
+
+    /// checks the frames against the references of `golden`
+    pub fn golden_set(&self) -> Option<GoldenSet> {
+        let references = self.golden.as_ref()?;
+        let set = GoldenSet::new(references, &self.output)
+            .tolerance(self.tolerance)
+            .bless(self.bless);
+        Some(set)
+    }
+
+    /// whether sub-frames are accumulated
+    pub fn accumulates(&self) -> bool {
+        self.samples > 1
+    }
+
+    /// falls back to the PNGs of `frame_path`
+    pub fn video_writer(&self) -> Option<VideoWriterBuilder> {
+        let video = self.video.as_ref()?;
+        let mut builder = VideoWriterBuilder::new(self.width, self.height, video)
+            .fps(self.fps)
+            .fallback(&self.output)
+            .first_frame(self.frames.start);
+        if let Some(command) = &self.encoder {
+            builder = builder.command(&command[0], &command[1..]);
+        }
+        Some(builder)
+    }
+
+    pub fn swap_chain_descriptor(&self, format: wgpu::TextureFormat) -> wgpu::SwapChainDescriptor {
+        wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format,
+            width: self.width,
+            height: self.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        }
+    }
+}
+
+/// "start..end" or a single frame
+fn parse_range(value: &str) -> Result<Range<u32>> {
+    if let Some(index) = value.find("..") {
+        let start = value[..index].parse()?;
+        let end = value[index + 2..].parse()?;
+        if start > end {
+            bail!("Reversed frame range {}", value);
+        }
+        Ok(start..end)
+    } else {
+        let frame = value.parse()?;
+        Ok(frame..frame + 1)
+    }
+}
+
+pub fn split_pair(value: &str, separator: char) -> Result<(u32, u32)> {
+    let mut parts = value.splitn(2, separator);
+    let first = parts.next().unwrap_or_default().parse()?;
+    let second = parts
+        .next()
+        .with_context(|| format!("Expected <a>{}<b> but got {}", separator, value))?
+        .parse()?;
+    Ok((first, second))
+}
+
+/// time of each frame at a fixed rate. `dt` is the difference of the frame times, so the steps
+/// sum up to `time(frame)` without drifting
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FixedClock {
+    fps: f64,
+}
+
+impl FixedClock {
+    pub fn new(fps: f64) -> Self {
+        Self { fps }
+    }
+
+    pub fn time(&self, frame: u32) -> Duration {
+        Duration::from_secs_f64(frame as f64 / self.fps)
+    }
+
+    /// from `frame - 1` to `frame`. zero for the first frame
+    pub fn dt(&self, frame: u32) -> Duration {
+        match frame {
+            0 => Duration::from_secs(0),
+            _ => self.time(frame) - self.time(frame - 1),
+        }
+    }
+}
+
+/// `adapter` is a substring of the adapter name, e.g. a software rasterizer for CI
+pub async fn request_device(adapter: Option<&str>) -> Result<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    let adapter = match adapter {
+        Some(name) => {
+            let adapters = instance
+                .enumerate_adapters(wgpu::BackendBit::PRIMARY)
+                .collect::<Vec<_>>();
+            let names = adapters
+                .iter()
+                .map(|adapter| adapter.get_info().name)
+                .collect::<Vec<_>>();
+            adapters
+                .into_iter()
+                .find(|adapter| adapter.get_info().name.contains(name))
+                .with_context(|| format!("No adapter matches {} in {:?}", name, names))?
+        }
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+            })
+            .await
+            .context("No adapter found")?,
+    };
+    let info = adapter.get_info();
+    println!("offline rendering on {} ({:?})", info.name, info.backend);
+
+    // same optional features as the window, see `ShadowMaps::new`
+    let features = adapter.features() & wgpu::Features::DEPTH_CLAMPING;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features,
+                limits: wgpu::Limits::default(),
+                shader_validation: true,
+            },
+            None,
+        )
+        .await?;
+    Ok((device, queue))
+}
+
+/// creates the output directory of `path`. `text` is embedded as tEXt chunks unless empty
+pub fn save_frame(image: &image::RgbaImage, path: &Path, text: &[(String, String)]) -> Result<()> {
+    if !text.is_empty() {
+        return manifest::save_png(image, path, text);
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    image
+        .save(path)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+#[test]
+fn test_offline_config() {
+    let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+
+    assert_eq!(
+        OfflineConfig::from_args(args("app --deferred")).unwrap(),
+        None
+    );
+    let config = OfflineConfig::from_args(args(
+        "app --offline --frames 24..48 --fps 24 --size 320x240 --out renders --deferred",
+    ))
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.frames, 24..48);
+    assert_eq!(config.fps, 24.0);
+    assert_eq!((config.width, config.height), (320, 240));
+    assert_eq!(config.frame_path(7), Path::new("renders/frame_00007.png"));
+    assert_eq!(config.scene, OfflineScene::Main);
+    assert_eq!(config.adapter, None);
+    assert_eq!(config.tile, None);
+    assert!(!config.accumulates());
+    assert_eq!(config.manifest_path(), Path::new("renders/manifest.json"));
+    let manifest = config.manifest("sketch");
+    assert_eq!((manifest.width, manifest.height), (320, 240));
+    assert_eq!(manifest.parameters["frames"], "24..48");
+    assert_eq!(manifest.parameters.get("shutter"), None);
+
+    let config = OfflineConfig::from_args(args(
+        "app --offline --frames 3 --scene triangle --gif renders/triangle.gif",
+    ))
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.frames, 3..4);
+    assert_eq!(config.gif, Some(PathBuf::from("renders/triangle.gif")));
+    assert_eq!(config.scene, OfflineScene::Triangle);
+    assert_eq!(config.video_writer(), None);
+
+    let config = OfflineConfig::from_args(vec![
+        "app".to_string(),
+        "--offline".to_string(),
+        "--video".to_string(),
+        "out.mp4".to_string(),
+        "--encoder".to_string(),
+        "sh -c  cat>{output}".to_string(),
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        config.encoder.as_ref().unwrap(),
+        &["sh", "-c", "cat>{output}"]
+    );
+    assert_eq!(
+        config.video_writer().unwrap().expanded_args(),
+        vec!["-c", "cat>out.mp4"]
+    );
+    assert!(OfflineConfig::from_args(args("app --offline --encoder")).is_err());
+
+    let config = OfflineConfig::from_args(vec![
+        "app".to_string(),
+        "--offline".to_string(),
+        "--encoder".to_string(),
+        "sh -c".to_string(),
+        "--encoder-arg".to_string(),
+        "cat > '{output}'".to_string(),
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        config.encoder.as_ref().unwrap(),
+        &["sh", "-c", "cat > '{output}'"]
+    );
+    assert!(OfflineConfig::from_args(args("app --offline --encoder-arg -y")).is_err());
+
+    assert!(OfflineConfig::from_args(args("app --offline --frames 5..2")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --size 320")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --fps 0")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --fps inf")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --fps NaN")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --fps 1e-300")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --tile 0")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --samples 0")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --shutter 1.5")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --samples 8 --tile 512")).is_err());
+    let config = OfflineConfig::from_args(args("app --offline --samples 16 --jitter r2"));
+    let config = config.unwrap().unwrap();
+    assert!(config.accumulates());
+    assert_eq!((config.samples, config.jitter), (16, JitterSequence::R2));
+    assert_eq!(config.manifest("sketch").parameters["jitter"], "R2");
+    let config = OfflineConfig::from_args(args("app --offline --seed 7 --png-text"));
+    let config = config.unwrap().unwrap();
+    assert!(config.png_text);
+    assert_eq!(config.manifest("sketch").seed, 7);
+    assert!(config.golden_set().is_none());
+
+    let config = OfflineConfig::from_args(args(
+        "app --offline --golden golden --tolerance 4 --min-ssim 0.9 --bless",
+    ))
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.golden, Some(PathBuf::from("golden")));
+    assert_eq!(config.tolerance.channel, 4);
+    assert_eq!(config.tolerance.min_ssim, 0.9);
+    assert_eq!(config.tolerance.min_psnr, Tolerance::default().min_psnr);
+    assert!(config.bless && config.golden_set().is_some());
+    assert!(OfflineConfig::from_args(args("app --offline --bless")).is_err());
+    assert!(OfflineConfig::from_args(args("app --offline --golden g --tile 256")).is_err());
+    let config = OfflineConfig::from_args(args("app --offline --size 16000x9000 --tile 4096"));
+    assert_eq!(config.unwrap().unwrap().tile, Some(4096));
+    assert!(OfflineConfig::from_args(args("app --offline --fps")).is_err());
+    // mistyped options aren't ignored, but the options of the window are
+    assert!(OfflineConfig::from_args(args("app --offline --frame 10")).is_err());
+    assert!(OfflineConfig::from_args(args("app --frame 10"))
+        .unwrap()
+        .is_none());
+    assert!(OfflineConfig::from_args(args("app --offline --particles --no-ssao")).is_ok());
+    assert!(OfflineConfig::from_args(args("app --offline --msaa 3")).is_err());
+    let config = OfflineConfig::from_args(args("app --offline --msaa 1"));
+    assert_eq!(config.unwrap().unwrap().sample_count, 1);
+
+    let clock = FixedClock::new(30.0);
+    assert_eq!(clock.dt(0), Duration::from_secs(0));
+    let total = (1..=90).map(|frame| clock.dt(frame)).sum::<Duration>();
+    assert_eq!(total, clock.time(90));
+    assert_eq!(clock.time(90), Duration::from_secs(3));
+}
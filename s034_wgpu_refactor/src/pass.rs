@@ -1,19 +1,34 @@
 // use buffer::{Bindable, UniformBindable};
+use anyhow::*;
 use nannou::math::cgmath;
 use nannou::prelude::*;
 use rayon::prelude::*;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use crate::camera_controller::CameraController;
+use crate::input_map::InputMap;
 use crate::pass_compute::PassCompute;
 
 use crate::renderer::{
-    self,
-    camera::Camera,
-    geom::{DrawGeom, Geom},
+    batch::{self, DrawCommand, DrawItem},
+    camera::{Camera, ProjectionTile},
+    camera_path::CameraPath,
+    culling::{DrawIndexedIndirect, InstanceCulling},
+    deferred::{
+        self, DeferredLighting, GBufferChannel, GBufferFormats, GBufferPrecision, RenderPath,
+    },
+    environment::{DrawSkybox, Environment, EnvironmentMaps},
+    geom::Geom,
+    graph::{GraphPass, GraphResources, PassDesc, TextureDesc, TextureSize},
     instance::{Instance, Instances},
-    light::{DrawLight, Light},
-    texture::TextureSet,
+    light::{Light, LightId, LightSet},
+    material::{Material, Shading},
+    mesh_draw::{BindGroupKind, DrawLayout, DrawLayoutBuilder, DrawMesh},
+    pipeline::{PipelineCache, PipelineDesc},
+    post::HDR_FORMAT,
+    shadow::{ShadowConfig, ShadowMaps},
+    ssao::{self, Ssao, SsaoConfig},
     vertex::{Vertex, VertexDescription},
 };
 
@@ -22,32 +37,78 @@ pub struct PassMain {
     instances: Instances,
     camera: Camera,
     camera_controller: CameraController,
-    light: Light,
-    light_render_pipeline: wgpu::RenderPipeline,
-    depth_texture: TextureSet,
-    render_pipeline: wgpu::RenderPipeline,
+    input_map: InputMap,
+    camera_path: Option<CameraPath>,
+    elapsed: Duration,
+    lights: LightSet,
+    point_light: LightId,
+    shadow_maps: ShadowMaps,
+    environment: Environment,
+    skybox_render_pipeline: Arc<wgpu::RenderPipeline>,
+    light_render_pipeline: Arc<wgpu::RenderPipeline>,
+    render_pipeline: Arc<wgpu::RenderPipeline>,
+    pbr_render_pipeline: Arc<wgpu::RenderPipeline>,
+    /// sets of the pipelines above, shared by the deferred ones with the same layouts
+    light_layout: DrawLayout,
+    render_layout: DrawLayout,
+    pbr_layout: DrawLayout,
+    sample_count: u32,
+    render_path: RenderPath,
+    deferred: DeferredPipelines,
+    ssao: Ssao,
+    /// meshes of the forward path sorted by pipeline and material
+    draw_plan: Vec<DrawCommand>,
+    culling: InstanceCulling,
+    culling_enabled: bool,
+}
+
+/// the G-buffer only has Blinn-Phong attributes, so PBR meshes, light models and the skybox are
+/// drawn forward after the lighting pass. without MSAA since the G-buffer isn't multisampled
+struct DeferredPipelines {
+    formats: GBufferFormats,
+    gbuffer: Arc<wgpu::RenderPipeline>,
+    gbuffer_layout: DrawLayout,
+    lighting: DeferredLighting,
+    pbr: Arc<wgpu::RenderPipeline>,
+    light: Arc<wgpu::RenderPipeline>,
+    skybox: Arc<wgpu::RenderPipeline>,
 }
 
 impl PassMain {
     const NUM_INSTANCES_PER_ROW: u32 = 10;
     const SPACE_BETWEEN: f32 = 3.0;
-    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// the scene is rendered to "hdr" and tone mapped by the post stack
+    pub const COLOR_FORMAT: wgpu::TextureFormat = HDR_FORMAT;
+    /// multisampled color target which is resolved to "hdr"
+    pub const MSAA_TARGET: &'static str = "hdr_msaa";
 
+    /// `sample_count` is for MSAA. 1 disables it
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         sc_desc: &wgpu::SwapChainDescriptor,
-    ) -> Self {
+        pipeline_cache: &mut PipelineCache,
+        sample_count: u32,
+    ) -> Result<Self> {
         let instances = Self::create_instances(device);
 
         let dir = std::path::Path::new("..").join("assets").join("learn_wgpu");
-        let obj_model = Geom::load(&device, &queue, dir.join("cube.obj")).unwrap();
+        let obj_model = Geom::load(&device, &queue, dir.join("cube.obj"))?;
 
+        // the cache is only locked while a pass is created, the passes run in parallel
+        let cache = Mutex::new(&mut *pipeline_cache);
         obj_model
             .meshes
             .par_iter()
             .map(|m| {
-                let compute_pass = PassCompute::new(device, &m.vertices, &m.indices, &m.binding);
+                let compute_pass = PassCompute::new(
+                    device,
+                    &mut cache.lock().unwrap(),
+                    &m.vertices,
+                    &m.indices,
+                    &m.binding,
+                );
                 compute_pass.render(device, queue)
             })
             .collect::<Vec<Result<(), ()>>>();
@@ -77,189 +138,402 @@ impl PassMain {
         );
         let camera_controller = CameraController::new(4.0, 0.4);
 
-        let light = Light::new(device, (2.0, 2.0, 2.0).into(), (1.0, 1.0, 1.0).into());
-
-        let vs_mod =
-            wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/shader.vert.spv"));
-        let fs_mod =
-            wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/shader.frag.spv"));
-        let render_pipeline_layout = renderer::PipelineLayoutBuilder::new()
-            .bind_group_layouts(&[
-                &camera.binding.bind_group_layout(),
-                &instances.binding.bind_group_layout(),
-                &light.binding.bind_group_layout(),
-                &obj_model.materials[0].binding.bind_group_layout(), // TODO:
-            ])
-            .build(device);
+        let mut lights = LightSet::new(device);
+        let point_light = lights
+            .add(
+                Light::point((2.0, 2.0, 2.0).into(), 20.0, (1.0, 1.0, 1.0).into())
+                    .cast_shadows(true),
+            )
+            .context("Too many lights")?;
+        lights
+            .add(
+                Light::directional((-1.0, -1.0, 0.5).into(), (0.2, 0.2, 0.3).into())
+                    .cast_shadows(true),
+            )
+            .context("Too many lights")?;
+        lights.update(queue);
 
+        let mut shadow_maps = ShadowMaps::new(
+            device,
+            pipeline_cache,
+            &lights,
+            &instances,
+            ShadowConfig::default(),
+        );
+        shadow_maps.update(queue, &lights, &camera);
+
+        // same as the clear color until an environment map is set
+        let environment = Environment::new(
+            device,
+            queue,
+            &camera,
+            &EnvironmentMaps::constant(cgmath::Vector3::new(0.1, 0.2, 0.3)),
+        );
+
+        let ssao = Ssao::new(
+            device,
+            queue,
+            pipeline_cache,
+            &camera,
+            &instances,
+            SsaoConfig::default(),
+        );
+
+        let draw_plan = batch::plan(
+            &obj_model
+                .meshes
+                .iter()
+                .enumerate()
+                .map(|(i, mesh)| DrawItem {
+                    pipeline: Self::pipeline_index(obj_model.materials[mesh.material_id].shading),
+                    material: mesh.material_id,
+                    mesh: i,
+                })
+                .collect::<Vec<_>>(),
+        );
+        let culling = InstanceCulling::new(device, pipeline_cache, &obj_model, &instances)?;
+
+        let desc = PipelineDesc::new("main", include_bytes!("../shaders/shader.vert.spv"))
+            .fragment_shader(include_bytes!("../shaders/shader.frag.spv"))
+            .vertex_buffer(Vertex::desc())
+            .cull_mode(wgpu::CullMode::Back)
+            .color_format(Self::COLOR_FORMAT)
+            .depth_format(Self::DEPTH_FORMAT)
+            .sample_count(sample_count);
+        let render_layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Camera, ssao.frame_bind_group_layout())
+            .group(
+                BindGroupKind::Instances,
+                instances.binding.bind_group_layout(),
+            )
+            .group(BindGroupKind::Light, shadow_maps.bind_group_layout())
+            .group(
+                BindGroupKind::Material,
+                obj_model.materials[0].binding.bind_group_layout(), // TODO:
+            );
         let render_pipeline =
-            wgpu::RenderPipelineBuilder::from_layout(&render_pipeline_layout, &vs_mod)
-                // .label(Some("Render Pipeline")) // TODO:
-                .fragment_shader(&fs_mod)
-                // .front_face(wgpu::FrontFace::Ccw)
-                .cull_mode(wgpu::CullMode::Back)
-                // .depth_bias(0)
-                // .depth_bias_slope_scale(0.0)
-                // .depth_bias_clamp(0.0)
-                // .primitive_topology(wgpu::PrimitiveTopology::TriangleList)
-                .color_format(sc_desc.format)
-                .color_blend(wgpu::BlendDescriptor::REPLACE)
-                .alpha_blend(wgpu::BlendDescriptor::REPLACE)
-                // .write_mask(wgpu::ColorWrite::ALL)
+            pipeline_cache.render_pipeline(device, &desc, render_layout.bind_group_layouts());
+        let render_layout = render_layout.build();
+
+        // every PBR material has the same bindings
+        let pbr_material_layout = Material::pbr_layout(device);
+        let pbr_layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Environment, environment.bind_group_layout())
+            .group(
+                BindGroupKind::Instances,
+                instances.binding.bind_group_layout(),
+            )
+            .group(BindGroupKind::Light, shadow_maps.bind_group_layout())
+            .group(BindGroupKind::Material, &pbr_material_layout);
+        let pbr_render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &desc
+                .clone()
+                .layout("pbr")
+                .fragment_shader(include_bytes!("../shaders/pbr.frag.spv")),
+            pbr_layout.bind_group_layouts(),
+        );
+
+        // drawn at the far plane where the depth is still cleared
+        let skybox_render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new("skybox", include_bytes!("../shaders/skybox.vert.spv"))
+                .fragment_shader(include_bytes!("../shaders/skybox.frag.spv"))
+                .color_format(Self::COLOR_FORMAT)
                 .depth_format(Self::DEPTH_FORMAT)
-                // .depth_write_enabled(true)
-                .depth_compare(wgpu::CompareFunction::Less)
-                // .stencil_front(stencil)
-                // .stencil_back(stencil)
-                // .stencil_read_mask(mask)
-                // .stencil_write_mask(mask)
-                .sample_count(1)
-                // .sample_mask(!0)
-                // .index_format(wgpu::IndexFormat::Uint32)
-                .add_vertex_buffer_descriptor(Vertex::desc())
-                .build(device);
-
-        let vs_mod =
-            wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/light.vert.spv"));
-        let fs_mod =
-            wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/light.frag.spv"));
-        let light_pipeline_layout = renderer::PipelineLayoutBuilder::new()
-            .bind_group_layouts(&[
-                &camera.binding.bind_group_layout(),
-                &light.binding.bind_group_layout(),
-            ])
-            .build(device);
+                .sample_count(sample_count)
+                .depth_write_enabled(false)
+                .depth_compare(wgpu::CompareFunction::LessEqual),
+            &[environment.bind_group_layout()],
+        );
 
+        let light_desc = desc
+            .clone()
+            .layout("light")
+            .vertex_shader(include_bytes!("../shaders/light.vert.spv"))
+            .fragment_shader(include_bytes!("../shaders/light.frag.spv"));
+        let light_layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Camera, camera.binding.bind_group_layout())
+            .group(BindGroupKind::Light, lights.bind_group_layout());
         let light_render_pipeline =
-            wgpu::RenderPipelineBuilder::from_layout(&light_pipeline_layout, &vs_mod)
-                // .label(Some("Render Pipeline")) // TODO:
-                .fragment_shader(&fs_mod)
-                // .front_face(wgpu::FrontFace::Ccw)
-                .cull_mode(wgpu::CullMode::Back)
-                // .depth_bias(0)
-                // .depth_bias_slope_scale(0.0)
-                // .depth_bias_clamp(0.0)
-                // .primitive_topology(wgpu::PrimitiveTopology::TriangleList)
-                .color_format(sc_desc.format)
-                .color_blend(wgpu::BlendDescriptor::REPLACE)
-                .alpha_blend(wgpu::BlendDescriptor::REPLACE)
-                // .write_mask(wgpu::ColorWrite::ALL)
-                .depth_format(Self::DEPTH_FORMAT)
-                // .depth_write_enabled(true)
-                .depth_compare(wgpu::CompareFunction::Less)
-                // .stencil_front(stencil)
-                // .stencil_back(stencil)
-                // .stencil_read_mask(mask)
-                // .stencil_write_mask(mask)
-                .sample_count(1)
-                // .sample_mask(!0)
-                // .index_format(wgpu::IndexFormat::Uint32)
-                .add_vertex_buffer_descriptor(Vertex::desc())
-                .build(device);
-
-        let depth_texture = Self::create_depth_texture(device, sc_desc, "depth_texture");
-
-        Self {
+            pipeline_cache.render_pipeline(device, &light_desc, light_layout.bind_group_layouts());
+
+        let formats = GBufferFormats::select(GBufferPrecision::Low);
+        let gbuffer_layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Camera, camera.binding.bind_group_layout())
+            .group(
+                BindGroupKind::Instances,
+                instances.binding.bind_group_layout(),
+            )
+            .group(BindGroupKind::Light, shadow_maps.bind_group_layout())
+            .group(
+                BindGroupKind::Material,
+                obj_model.materials[0].binding.bind_group_layout(),
+            );
+        let deferred = DeferredPipelines {
+            formats,
+            gbuffer: pipeline_cache.render_pipeline(
+                device,
+                &desc
+                    .clone()
+                    .layout("gbuffer")
+                    .vertex_shader(include_bytes!("../shaders/gbuffer.vert.spv"))
+                    .fragment_shader(include_bytes!("../shaders/gbuffer.frag.spv"))
+                    .color_formats(&formats.colors())
+                    .depth_format(formats.depth)
+                    .sample_count(1),
+                gbuffer_layout.bind_group_layouts(),
+            ),
+            gbuffer_layout: gbuffer_layout.build(),
+            lighting: DeferredLighting::new(
+                device,
+                pipeline_cache,
+                &camera,
+                &shadow_maps,
+                Self::COLOR_FORMAT,
+            ),
+            pbr: pipeline_cache.render_pipeline(
+                device,
+                &desc
+                    .clone()
+                    .layout("pbr")
+                    .fragment_shader(include_bytes!("../shaders/pbr.frag.spv"))
+                    .depth_format(formats.depth)
+                    .sample_count(1),
+                pbr_layout.bind_group_layouts(),
+            ),
+            light: pipeline_cache.render_pipeline(
+                device,
+                &light_desc
+                    .clone()
+                    .depth_format(formats.depth)
+                    .sample_count(1),
+                light_layout.bind_group_layouts(),
+            ),
+            skybox: pipeline_cache.render_pipeline(
+                device,
+                &PipelineDesc::new("skybox", include_bytes!("../shaders/skybox.vert.spv"))
+                    .fragment_shader(include_bytes!("../shaders/skybox.frag.spv"))
+                    .color_format(Self::COLOR_FORMAT)
+                    .depth_format(formats.depth)
+                    .depth_write_enabled(false)
+                    .depth_compare(wgpu::CompareFunction::LessEqual),
+                &[environment.bind_group_layout()],
+            ),
+        };
+
+        Ok(Self {
             obj_model,
             instances,
             camera,
             camera_controller,
-            light,
+            input_map: InputMap::default(),
+            camera_path: None,
+            elapsed: Duration::from_secs(0),
+            lights,
+            point_light,
+            shadow_maps,
+            environment,
+            skybox_render_pipeline,
             light_render_pipeline,
-            depth_texture,
             render_pipeline,
-        }
+            pbr_render_pipeline,
+            light_layout: light_layout.build(),
+            render_layout,
+            pbr_layout: pbr_layout.build(),
+            sample_count,
+            render_path: RenderPath::Forward,
+            deferred,
+            ssao,
+            draw_plan,
+            culling,
+            culling_enabled: true,
+        })
     }
 
     pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.elapsed += dt;
+        if let Some(camera_path) = &self.camera_path {
+            camera_path.apply(&mut self.camera, self.elapsed.as_secs_f32());
+        } else {
+            self.camera_controller.update_camera(&mut self.camera, dt);
+        }
         self.camera.update(device, queue);
 
-        let old_position = self.light.position().to_owned();
-        let new_position = self.light.position_as_mut();
-        *new_position = cgmath::Quaternion::from_axis_angle(
-            (0.0, 1.0, 0.0).into(),
-            cgmath::Deg(60.0 * dt.as_secs_f32()),
-        ) * old_position;
-        self.light.update(queue, dt);
+        if let Some(position) = self
+            .lights
+            .get_mut(self.point_light)
+            .and_then(Light::position_as_mut)
+        {
+            *position = cgmath::Quaternion::from_axis_angle(
+                (0.0, 1.0, 0.0).into(),
+                cgmath::Deg(60.0 * dt.as_secs_f32()),
+            ) * *position;
+        }
+        self.lights.update(queue);
+        self.shadow_maps.update(queue, &self.lights, &self.camera);
+        self.culling
+            .update(queue, self.camera.view_projection_matrix());
     }
 
-    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, raw_frame: &wgpu::TextureViewHandle) {
-        let camera_bind_group = self.camera.binding.bind_group();
-        let mut render_pass = wgpu::RenderPassBuilder::new()
-            .color_attachment(raw_frame, |color| {
-                color
-                    // TODO:
-                    .resolve_target(None)
-                    // TODO:
-                    .load_op(wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }))
-                    // TODO:
-                    .store_op(true)
-            })
-            .depth_stencil_attachment(&self.depth_texture.view, |depth| {
-                depth
-                    .depth_load_op(wgpu::LoadOp::Clear(1.0))
-                    .depth_store_op(true)
-            })
-            .begin(encoder);
+    /// shared with the passes drawn on top of the scene
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
 
-        render_pass.set_pipeline(&self.light_render_pipeline);
-        render_pass.draw_light_model(
-            &self.obj_model,
-            &camera_bind_group,
-            &self.light.binding.bind_group(),
-        );
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.draw_geom_instanced_with_light_and_inner_material(
-            &self.obj_model,
-            &camera_bind_group,
-            &self.light.binding.bind_group(),
-            &self.instances.binding.bind_group(),
-            0..self.instances.instances.len() as u32,
-        );
+    /// renders only `tile` of an image of `size`, e.g. a tile of `renderer::tiled::TileGrid`.
+    /// the whole image if `None`
+    pub fn set_projection_tile(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: [u32; 2],
+        tile: Option<ProjectionTile>,
+    ) {
+        self.camera.resized(size[0], size[1]);
+        self.camera.projection.tile = tile;
+        self.camera.update(device, queue);
+    }
 
-        // TODO: make renderer based on nannou's way
-        // let device = window.swap_chain_device();
-        // let frame_dims: [u32; 2] = window.tracked_state.physical_size.into();
-        // let scale_factor = window.tracked_state.scale_factor as f32;
-        // let msaa_samples = window.msaa_samples();
-        // let target_format = crate::frame::Frame::TEXTURE_FORMAT;
-        // let renderer = draw::RendererBuilder::new().build(
-        //     device,
-        //     frame_dims,
-        //     scale_factor,
-        //     msaa_samples,
-        //     target_format,
-        // );
-        // draw.to_raw_frame(app, &renderer, &frame).unwrap();
+    /// camera follows the path instead of the controller while it's set
+    pub fn set_camera_path(&mut self, camera_path: Option<CameraPath>) {
+        self.camera_path = camera_path;
+        self.elapsed = Duration::from_secs(0);
+    }
+
+    /// must be called before the graph is built, since the graph textures written by the pass
+    /// depend on it
+    pub fn set_render_path(&mut self, render_path: RenderPath) {
+        self.render_path = render_path;
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.render_path
+    }
+
+    /// textures of the deferred path. declare them in the graph with `GBufferFormats::descs`
+    pub fn gbuffer_formats(&self) -> &GBufferFormats {
+        &self.deferred.formats
     }
 
-    pub fn resized(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
-        self.camera.resized(sc_desc.width, sc_desc.height);
-        self.depth_texture = Self::create_depth_texture(device, sc_desc, "depth_texture");
+    /// debug view of the deferred path
+    pub fn set_gbuffer_channel(&mut self, queue: &wgpu::Queue, channel: GBufferChannel) {
+        self.deferred.lighting.set_channel(queue, channel);
+    }
+
+    pub fn gbuffer_channel(&self) -> GBufferChannel {
+        self.deferred.lighting.channel()
+    }
+
+    /// ambient occlusion of the forward path. must be called before the graph is built like
+    /// `set_render_path`. declare the textures in the graph with `SsaoConfig::descs`
+    pub fn set_ssao_enabled(&mut self, enabled: bool) {
+        self.ssao.set_enabled(enabled);
+    }
+
+    pub fn ssao_enabled(&self) -> bool {
+        self.ssao.enabled()
+    }
+
+    pub fn set_ssao_config(&mut self, queue: &wgpu::Queue, config: SsaoConfig) {
+        self.ssao.set_config(queue, config);
+    }
+
+    pub fn ssao_config(&self) -> &SsaoConfig {
+        self.ssao.config()
+    }
+
+    /// frustum culling of the instances of the forward path. shadows and ambient occlusion
+    /// always draw all of them
+    pub fn set_culling_enabled(&mut self, enabled: bool) {
+        self.culling_enabled = enabled;
+    }
+
+    pub fn culling_enabled(&self) -> bool {
+        self.culling_enabled
+    }
+
+    /// resolved color target which is read by the post stack
+    pub fn color_desc() -> TextureDesc {
+        TextureDesc::new(
+            Self::COLOR_FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        )
+    }
+
+    /// `MSAA_TARGET`. only used when `sample_count` is more than 1
+    pub fn msaa_desc(sample_count: u32) -> TextureDesc {
+        TextureDesc::new(
+            Self::COLOR_FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        )
+        .sample_count(sample_count)
+    }
+
+    /// "depth". must have the same `sample_count` as the pass
+    pub fn depth_desc(sample_count: u32) -> TextureDesc {
+        TextureDesc::new(
+            Self::DEPTH_FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        )
+        .sample_count(sample_count)
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// replaces the image based lighting and the skybox
+    pub fn set_environment(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        maps: &EnvironmentMaps,
+    ) {
+        self.environment = Environment::new(device, queue, &self.camera, maps);
+    }
+
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.input_map = input_map;
     }
 
     pub fn key_pressed(&mut self, key: Key) {
-        self.camera_controller.process_key(key, true);
+        for event in self.input_map.key(key, true) {
+            self.camera_controller.process_action(event);
+        }
     }
 
     pub fn key_released(&mut self, key: Key) {
-        self.camera_controller.process_key(key, false);
+        for event in self.input_map.key(key, false) {
+            self.camera_controller.process_action(event);
+        }
+    }
+
+    pub fn mouse_pressed(&mut self, button: MouseButton) {
+        for event in self.input_map.mouse_button(button, true) {
+            self.camera_controller.process_action(event);
+        }
+    }
+
+    pub fn mouse_released(&mut self, button: MouseButton) {
+        for event in self.input_map.mouse_button(button, false) {
+            self.camera_controller.process_action(event);
+        }
     }
 
     pub fn mouse_moved(&mut self, curr_pos: Point2, prev_pos: Point2) {
         let diff: Vector2<f32> = curr_pos - prev_pos;
-        self.camera_controller
-            .process_mouse(diff.x as f64, diff.y as f64);
+        if let Some(event) = self.input_map.mouse_moved(diff.x, diff.y) {
+            self.camera_controller.process_action(event);
+        }
     }
 
     pub fn mouse_wheel(&mut self, delta: &MouseScrollDelta) {
-        self.camera_controller.process_scroll(delta);
+        let event = self.input_map.mouse_wheel(delta);
+        self.camera_controller.process_action(event);
     }
 
     fn create_instances(device: &wgpu::Device) -> Instances {
@@ -293,51 +567,272 @@ impl PassMain {
         Instances::from_vec(device, &instances)
     }
 
-    pub fn create_depth_texture(
-        device: &wgpu::Device,
-        sc_desc: &wgpu::SwapChainDescriptor,
-        _label: &str,
-    ) -> TextureSet {
-        let size = wgpu::Extent3d {
-            width: sc_desc.width,
-            height: sc_desc.height,
-            depth: 1,
+    /// index of the forward pipeline in `draw_plan`
+    fn pipeline_index(shading: Shading) -> usize {
+        match shading {
+            Shading::BlinnPhong => 0,
+            Shading::Pbr => 1,
+        }
+    }
+
+    fn render_forward(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        let camera_bind_group = self.camera.binding.bind_group();
+        self.ssao.render(
+            encoder,
+            resources,
+            &self.obj_model,
+            &self.instances,
+            camera_bind_group,
+        );
+        if self.culling_enabled {
+            self.culling.encode(encoder);
+        }
+        // multisampled color is resolved to "hdr" at the end of the pass
+        let (target, resolve_target) = if self.sample_count > 1 {
+            (
+                resources.view(Self::MSAA_TARGET),
+                Some(resources.view("hdr")),
+            )
+        } else {
+            (resources.view("hdr"), None)
+        };
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(target, |color| {
+                color
+                    .resolve_target(resolve_target)
+                    .load_op(wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }))
+                    .store_op(true)
+            })
+            .depth_stencil_attachment(resources.view("depth"), |depth| {
+                depth
+                    .depth_load_op(wgpu::LoadOp::Clear(1.0))
+                    .depth_store_op(true)
+            })
+            .begin(encoder);
+
+        render_pass.set_pipeline(&self.light_render_pipeline);
+        self.draw_lights(&mut render_pass);
+        // materials select the pipeline, sorted so that the bind groups are set once per batch
+        let instances = if self.culling_enabled {
+            self.culling.bind_group()
+        } else {
+            self.instances.binding.bind_group()
         };
-        let texture = wgpu::TextureBuilder::new()
-            // .label(label)
-            .extent(size)
-            .mip_level_count(1)
-            .sample_count(1)
-            .dimension(wgpu::TextureDimension::D2)
-            .format(Self::DEPTH_FORMAT)
-            .usage(wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
-            .build(device);
-        let view = texture.view().build();
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            ..Default::default()
-        });
-
-        TextureSet {
-            texture,
-            view,
-            sampler,
+        let num_instances = self.instances.instances.len() as u32;
+        let mut layout = &self.render_layout;
+        for command in &self.draw_plan {
+            match *command {
+                DrawCommand::SetPipeline(pipeline) => {
+                    let pipeline = if pipeline == Self::pipeline_index(Shading::Pbr) {
+                        layout = &self.pbr_layout;
+                        &self.pbr_render_pipeline
+                    } else {
+                        layout = &self.render_layout;
+                        &self.render_pipeline
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    // Blinn-Phong reads the camera with the ambient occlusion, PBR with the
+                    // environment maps
+                    render_pass
+                        .bind_groups()
+                        .layout(layout)
+                        .camera(self.ssao.frame_bind_group())
+                        .environment(self.environment.bind_group())
+                        .instances(instances, 0..num_instances)
+                        .light(self.shadow_maps.bind_group())
+                        .submit();
+                }
+                DrawCommand::SetMaterial(material) => {
+                    render_pass
+                        .bind_groups()
+                        .layout(layout)
+                        .material(&self.obj_model.materials[material])
+                        .submit();
+                }
+                DrawCommand::Draw(mesh) => {
+                    let offset = mesh as wgpu::BufferAddress * DrawIndexedIndirect::SIZE;
+                    let draw = render_pass
+                        .draw_mesh(&self.obj_model.meshes[mesh])
+                        .layout(layout);
+                    if self.culling_enabled {
+                        draw.indirect(self.culling.draw_args(), offset).submit();
+                    } else {
+                        draw.instance_range(0..num_instances).submit();
+                    }
+                }
+            }
+        }
+        render_pass.set_pipeline(&self.skybox_render_pipeline);
+        render_pass.draw_skybox(&self.environment);
+
+        // TODO: make renderer based on nannou's way
+        // let device = window.swap_chain_device();
+        // let frame_dims: [u32; 2] = window.tracked_state.physical_size.into();
+        // let scale_factor = window.tracked_state.scale_factor as f32;
+        // let msaa_samples = window.msaa_samples();
+        // let target_format = crate::frame::Frame::TEXTURE_FORMAT;
+        // let renderer = draw::RendererBuilder::new().build(
+        //     device,
+        //     frame_dims,
+        //     scale_factor,
+        //     msaa_samples,
+        //     target_format,
+        // );
+        // draw.to_raw_frame(app, &renderer, &frame).unwrap();
+    }
+
+    /// a marker for each light with a pipeline of `light_layout`
+    fn draw_lights<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass
+            .draw_geom(&self.obj_model)
+            .layout(&self.light_layout)
+            .camera(self.camera.binding.bind_group())
+            .light(self.lights.bind_group())
+            .instance_range(0..self.lights.len() as u32)
+            .submit();
+    }
+
+    /// every instance of the meshes with `shading`. `uniforms` is the camera or the environment
+    fn draw_meshes<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        shading: Shading,
+        layout: &DrawLayout,
+        uniforms: &'a wgpu::BindGroup,
+    ) {
+        for mesh in &self.obj_model.meshes {
+            let material = &self.obj_model.materials[mesh.material_id];
+            if material.shading == shading {
+                render_pass
+                    .draw_mesh(mesh)
+                    .layout(layout)
+                    .camera(uniforms)
+                    .environment(uniforms)
+                    .light(self.shadow_maps.bind_group())
+                    .material(material)
+                    .instances(
+                        self.instances.binding.bind_group(),
+                        0..self.instances.instances.len() as u32,
+                    )
+                    .submit();
+            }
         }
     }
 
+    fn render_deferred(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        let camera_bind_group = self.camera.binding.bind_group();
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT);
+        let mut gbuffer_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(resources.view(deferred::ALBEDO), |color| {
+                color.load_op(clear).store_op(true)
+            })
+            .color_attachment(resources.view(deferred::NORMAL), |color| {
+                color.load_op(clear).store_op(true)
+            })
+            .color_attachment(resources.view(deferred::MATERIAL), |color| {
+                color.load_op(clear).store_op(true)
+            })
+            .depth_stencil_attachment(resources.view(deferred::DEPTH), |depth| {
+                depth
+                    .depth_load_op(wgpu::LoadOp::Clear(1.0))
+                    .depth_store_op(true)
+            })
+            .begin(encoder);
+        gbuffer_pass.set_pipeline(&self.deferred.gbuffer);
+        self.draw_meshes(
+            &mut gbuffer_pass,
+            Shading::BlinnPhong,
+            &self.deferred.gbuffer_layout,
+            camera_bind_group,
+        );
+        drop(gbuffer_pass);
+
+        self.deferred.lighting.render(
+            encoder,
+            resources.view("hdr"),
+            camera_bind_group,
+            self.shadow_maps.bind_group(),
+        );
+
+        // forward on top of the lit G-buffer with its depth
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(resources.view("hdr"), |color| {
+                color.load_op(wgpu::LoadOp::Load).store_op(true)
+            })
+            .depth_stencil_attachment(resources.view(deferred::DEPTH), |depth| {
+                depth.depth_load_op(wgpu::LoadOp::Load).depth_store_op(true)
+            })
+            .begin(encoder);
+        render_pass.set_pipeline(&self.deferred.light);
+        self.draw_lights(&mut render_pass);
+        render_pass.set_pipeline(&self.deferred.pbr);
+        self.draw_meshes(
+            &mut render_pass,
+            Shading::Pbr,
+            &self.pbr_layout,
+            self.environment.bind_group(),
+        );
+        render_pass.set_pipeline(&self.deferred.skybox);
+        render_pass.draw_skybox(&self.environment);
+    }
+
     // pub fn output_texture(&self) -> &wgpu::Texuture {
     //     self.texture
     // }
 }
 
+impl GraphPass for PassMain {
+    fn desc(&self) -> PassDesc {
+        let desc = PassDesc::new("main").write("hdr");
+        match self.render_path {
+            RenderPath::Forward => {
+                let mut desc = desc.write("depth");
+                if self.sample_count > 1 {
+                    desc = desc.write(Self::MSAA_TARGET);
+                }
+                if self.ssao.enabled() {
+                    desc = desc
+                        .write(ssao::DEPTH)
+                        .write(ssao::OCCLUSION)
+                        .write(ssao::BLURRED);
+                }
+                desc
+            }
+            RenderPath::Deferred => desc
+                .write(deferred::ALBEDO)
+                .write(deferred::NORMAL)
+                .write(deferred::MATERIAL)
+                .write(deferred::DEPTH),
+        }
+    }
+
+    fn resized(&mut self, _device: &wgpu::Device, size: [u32; 2]) {
+        self.camera.resized(size[0], size[1]);
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, resources: &GraphResources) {
+        match self.render_path {
+            RenderPath::Forward => self.ssao.bind(device, resources, &self.camera),
+            RenderPath::Deferred => self.deferred.lighting.bind(device, resources),
+        }
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        self.shadow_maps
+            .render(encoder, &self.obj_model, &self.instances);
+
+        match self.render_path {
+            RenderPath::Forward => self.render_forward(encoder, resources),
+            RenderPath::Deferred => self.render_deferred(encoder, resources),
+        }
+    }
+}
+
 // // App
 // pub fn to_frame(&self, app: &App, frame: &Frame) -> Result<(), draw::renderer::DrawError> {
 //     let window_id = frame.window_id();
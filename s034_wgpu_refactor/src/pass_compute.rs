@@ -3,6 +3,8 @@ use std::sync::Arc;
 
 use crate::renderer::{
     binding::{Binding, BindingBuilder},
+    compute::ComputePass,
+    pipeline::PipelineCache,
     vertex::Vertex,
 };
 
@@ -15,12 +17,13 @@ struct ComputeInfo {
 
 pub struct PassCompute {
     compute_info: ComputeInfo,
-    binding: Binding,
+    compute: ComputePass,
 }
 
 impl PassCompute {
     pub fn new(
         device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
         vertices: &Vec<Vertex>,
         indices: &Vec<u32>,
         binding_ref: &Binding,
@@ -56,38 +59,25 @@ impl PassCompute {
             )
             .build(device);
 
+        // compiled once and shared by every mesh
+        let compute = ComputePass::new(
+            device,
+            pipeline_cache,
+            "model_load",
+            include_bytes!("../shaders/model_load.comp.spv"),
+            vec![binding],
+        )
+        .unwrap();
+
         Self {
             compute_info,
-            binding,
+            compute,
         }
     }
 
     pub fn render(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<(), ()> {
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Tangent and Bitangent Calc"),
-        });
-        {
-            let shader_src = wgpu::shader_from_spirv_bytes(
-                device,
-                include_bytes!("../shaders/model_load.comp.spv"),
-            );
-            let pipeline_layout = crate::renderer::PipelineLayoutBuilder::new()
-                .label("Compute Pipeline Layout")
-                .bind_group_layouts(&[self.binding.bind_group_layout()])
-                .build(device);
-            let pipeline =
-                crate::renderer::ComputePipelineBuilder::from_layout(&pipeline_layout, &shader_src)
-                    .label("ModelLoader ComputePipeline")
-                    .build(device);
-
-            let mut pass = encoder.begin_compute_pass();
-            pass.set_pipeline(&pipeline);
-            pass.set_bind_group(0, self.binding.bind_group(), &[]);
-            pass.dispatch(self.compute_info.num_vertices as u32, 1, 1);
-        }
-
-        queue.submit(std::iter::once(encoder.finish()));
-        device.poll(wgpu::Maintain::Wait);
+        self.compute
+            .dispatch(device, queue, [self.compute_info.num_vertices, 1, 1], 1);
 
         Ok(())
     }
@@ -0,0 +1,222 @@
+use nannou::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::pass::PassMain;
+use crate::renderer::{
+    binding::{Binding, BindingBuilder},
+    camera::Camera,
+    graph::{GraphPass, GraphResources, PassDesc},
+    particle::{Particle, ParticleConfig, WORKGROUP_SIZE},
+    pipeline::{ComputePipelineDesc, PipelineCache, PipelineDesc},
+    vertex::VertexDescription,
+};
+
+/// simulates the particles of `ParticleConfig` with `shaders/particle.comp` and draws them as
+/// additive billboards on top of the scene. the particle state is ping-ponged between two storage
+/// buffers: every frame reads the buffer written by the previous one
+pub struct PassParticles {
+    config: ParticleConfig,
+    frame: u32,
+    elapsed: Duration,
+    sample_count: u32,
+    depth: &'static str,
+    /// [0]: a -> b, [1]: b -> a
+    simulations: [Binding; 2],
+    render_binding: Binding,
+    compute_pipeline: Arc<wgpu::ComputePipeline>,
+    render_pipeline: Arc<wgpu::RenderPipeline>,
+}
+
+impl PassParticles {
+    /// `sample_count` and `depth` must match the pass which rendered the scene, e.g.
+    /// `PassMain::sample_count()` and "depth" for the forward path.
+    /// the depth is only tested, so the particles don't occlude each other
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        camera: &Camera,
+        config: ParticleConfig,
+        sample_count: u32,
+        depth: &'static str,
+    ) -> Self {
+        let particles = config.initial_particles();
+        let usage = wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::VERTEX
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST;
+        let a_to_b = BindingBuilder::new()
+            .storage_buffer_custom(
+                "particles_a",
+                &particles,
+                usage,
+                wgpu::ShaderStage::COMPUTE,
+                false,
+                false,
+            )
+            .storage_buffer_custom(
+                "particles_b",
+                &particles,
+                usage,
+                wgpu::ShaderStage::COMPUTE,
+                false,
+                false,
+            )
+            .uniform_buffer(
+                "sim_params",
+                &[config.to_raw(0.0, 0.0, 0)],
+                wgpu::ShaderStage::COMPUTE,
+                false,
+            )
+            .build(device);
+        // same buffers with the source and the destination swapped
+        let shared = |label: &str| {
+            let index = a_to_b.label_index[label];
+            (
+                Arc::clone(&a_to_b.bindings[index]),
+                Arc::clone(&a_to_b.buffers[index]),
+            )
+        };
+        let (a, a_buffer) = shared("particles_a");
+        let (b, b_buffer) = shared("particles_b");
+        let (params, params_buffer) = shared("sim_params");
+        let b_to_a = BindingBuilder::new()
+            .assign_storage_buffer("particles_b", b, b_buffer)
+            .assign_storage_buffer("particles_a", a, a_buffer)
+            .assign_uniform_buffer("sim_params", params, params_buffer)
+            .build(device);
+
+        let camera_index = camera.binding.label_index["camera_view_proj"];
+        let render_binding = BindingBuilder::new()
+            .assign_uniform_buffer(
+                "camera_view_proj",
+                Arc::clone(&camera.binding.bindings[camera_index]),
+                Arc::clone(&camera.binding.buffers[camera_index]),
+            )
+            .uniform_buffer(
+                "render_params",
+                &[config.to_render_raw()],
+                wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                false,
+            )
+            .build(device);
+
+        let compute_pipeline = pipeline_cache.compute_pipeline(
+            device,
+            &ComputePipelineDesc::new("particle", include_bytes!("../shaders/particle.comp.spv")),
+            &[a_to_b.bind_group_layout()],
+        );
+        let additive = wgpu::BlendDescriptor {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new("particle", include_bytes!("../shaders/particle.vert.spv"))
+                .fragment_shader(include_bytes!("../shaders/particle.frag.spv"))
+                .vertex_buffer(Particle::desc())
+                .color_format(PassMain::COLOR_FORMAT)
+                .color_blend(additive.clone())
+                .alpha_blend(additive)
+                .depth_format(PassMain::DEPTH_FORMAT)
+                .depth_write_enabled(false)
+                .sample_count(sample_count),
+            &[render_binding.bind_group_layout()],
+        );
+
+        Self {
+            config,
+            frame: 0,
+            elapsed: Duration::from_secs(0),
+            sample_count,
+            depth,
+            simulations: [a_to_b, b_to_a],
+            render_binding,
+            compute_pipeline,
+            render_pipeline,
+        }
+    }
+
+    pub fn config(&self) -> &ParticleConfig {
+        &self.config
+    }
+
+    /// forces and emitters take effect on the next update. `num_particles` is kept since the
+    /// buffers are allocated by `new`
+    pub fn set_config(&mut self, queue: &wgpu::Queue, config: ParticleConfig) {
+        self.config = ParticleConfig {
+            num_particles: self.config.num_particles,
+            ..config
+        };
+        self.render_binding.write_buffer_at_label(
+            queue,
+            "render_params",
+            0,
+            &[self.config.to_render_raw()],
+        );
+    }
+
+    /// advances the simulation by `dt` on the next render
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        self.elapsed += dt;
+        self.frame += 1;
+        let raw = self
+            .config
+            .to_raw(self.elapsed.as_secs_f32(), dt.as_secs_f32(), self.frame);
+        self.simulations[0].write_buffer_at_label(queue, "sim_params", 0, &[raw]);
+    }
+
+    /// buffer written by the simulation of the current frame
+    fn current(&self) -> (&Binding, &wgpu::Buffer) {
+        let simulation = &self.simulations[(self.frame % 2) as usize];
+        // source at 0, destination at 1
+        (simulation, &simulation.buffers[1])
+    }
+}
+
+impl GraphPass for PassParticles {
+    fn desc(&self) -> PassDesc {
+        let desc = PassDesc::new("particles").write("hdr").read(self.depth);
+        if self.sample_count > 1 {
+            desc.write(PassMain::MSAA_TARGET)
+        } else {
+            desc
+        }
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        let (simulation, particles) = self.current();
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, simulation.bind_group(), &[]);
+        let workgroups = (self.config.num_particles + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+        compute_pass.dispatch(workgroups, 1, 1);
+        drop(compute_pass);
+
+        // drawn into the multisampled target and resolved again, like `PassMain`
+        let (target, resolve_target) = if self.sample_count > 1 {
+            (
+                resources.view(PassMain::MSAA_TARGET),
+                Some(resources.view("hdr")),
+            )
+        } else {
+            (resources.view("hdr"), None)
+        };
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(target, |color| {
+                color
+                    .resolve_target(resolve_target)
+                    .load_op(wgpu::LoadOp::Load)
+                    .store_op(true)
+            })
+            .depth_stencil_attachment(resources.view(self.depth), |depth| {
+                depth.depth_load_op(wgpu::LoadOp::Load).depth_store_op(true)
+            })
+            .begin(encoder);
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, self.render_binding.bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, particles.slice(..));
+        render_pass.draw(0..6, 0..self.config.num_particles);
+    }
+}
@@ -1,83 +1,111 @@
 use nannou::prelude::*;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::renderer;
+use crate::renderer::{
+    graph::{GraphPass, GraphResources, PassDesc, TextureDesc, TextureSize},
+    pipeline::{PipelineCache, PipelineDesc},
+};
 
 pub struct PassTriangle {
-    texture: wgpu::Texture,
-    texture_view: wgpu::TextureView,
-    render_pipeline: wgpu::RenderPipeline,
+    frame: i32,
+    sample_count: u32,
+    render_pipeline: Arc<wgpu::RenderPipeline>,
 }
 
 impl PassTriangle {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// multisampled color target which is resolved to "triangle"
+    pub const MSAA_TARGET: &'static str = "triangle_msaa";
+
+    /// `sample_count` is for MSAA. 1 disables it
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         sc_desc: &wgpu::SwapChainDescriptor,
+        pipeline_cache: &mut PipelineCache,
+        sample_count: u32,
     ) -> Self {
-        let texture = wgpu::TextureBuilder::new()
-            .extent(wgpu::Extent3d {
-                width: sc_desc.width,
-                height: sc_desc.height,
-                depth: 1,
-            })
-            // .label(None)
-            // .array_layer_count(1)
-            .mip_level_count(1)
-            .sample_count(1)
-            .dimension(wgpu::TextureDimension::D2)
-            .format(wgpu::TextureFormat::Rgba8UnormSrgb)
-            .usage(wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::OUTPUT_ATTACHMENT)
-            .build(device);
-
-        let texture_view = texture.view().build();
-
-        let vs_module =
-            &wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/offline.vert.spv"));
-        let fs_module =
-            &wgpu::shader_from_spirv_bytes(device, include_bytes!("../shaders/offline.frag.spv"));
-
-        let render_pipeline_layout = renderer::PipelineLayoutBuilder::new()
-            .label("Render Pipeline Layout")
-            .build(device);
-
-        let render_pipeline =
-            wgpu::RenderPipelineBuilder::from_layout(&render_pipeline_layout, vs_module)
-                .fragment_shader(fs_module)
-                // .front_face(wgpu::FrontFace::Ccw)
+        let render_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new("offline", include_bytes!("../shaders/offline.vert.spv"))
+                .fragment_shader(include_bytes!("../shaders/offline.frag.spv"))
                 .cull_mode(wgpu::CullMode::Back)
-                // .depth_bias(0)
-                // .depth_bias_slope_scale(0.0)
-                // .depth_bias_clamp(0.0)
-                // .primitive_topology(wgpu::PrimitiveTopology::TriangleList)
-                .color_format(texture.format())
-                .color_blend(wgpu::BlendDescriptor::REPLACE)
-                .alpha_blend(wgpu::BlendDescriptor::REPLACE)
-                // .write_mask(wgpu::ColorWrite::ALL)
-                // .depth_stencil_state(None)
+                .color_format(Self::FORMAT)
                 .index_format(wgpu::IndexFormat::Uint16)
-                // .add_vertex_buffer(&[])
-                .sample_count(1)
-                // .sample_mask(!0)
-                .build(device);
+                .sample_count(sample_count),
+            &[],
+        );
 
         Self {
-            texture,
-            texture_view,
+            frame: 0,
+            sample_count,
             render_pipeline,
         }
     }
 
+    /// `output` texture of the graph. can be captured after rendering
+    pub fn output_desc() -> TextureDesc {
+        TextureDesc::new(
+            Self::FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::COPY_SRC | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        )
+    }
+
+    /// `MSAA_TARGET`. only used when `sample_count` is more than 1.
+    /// `SAMPLED` so that it can also be captured before the resolve with `capture_frame`
+    pub fn msaa_desc(sample_count: u32) -> TextureDesc {
+        TextureDesc::new(
+            Self::FORMAT,
+            TextureSize::Surface,
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        )
+        .sample_count(sample_count)
+    }
+
     pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {}
 
-    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, i: &i32) {
+    pub fn set_frame(&mut self, frame: i32) {
+        self.frame = frame;
+    }
+
+    pub fn key_pressed(&mut self, key: Key) {}
+
+    pub fn key_released(&mut self, key: Key) {}
+
+    pub fn mouse_moved(&mut self, curr_pos: Point2, prev_pos: Point2) {}
+
+    pub fn mouse_wheel(&mut self, delta: &MouseScrollDelta) {}
+}
+
+impl GraphPass for PassTriangle {
+    fn desc(&self) -> PassDesc {
+        let desc = PassDesc::new("triangle").write("triangle");
+        if self.sample_count > 1 {
+            desc.write(Self::MSAA_TARGET)
+        } else {
+            desc
+        }
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        let i = self.frame;
+        let (target, resolve_target) = if self.sample_count > 1 {
+            (
+                resources.view(Self::MSAA_TARGET),
+                Some(resources.view("triangle")),
+            )
+        } else {
+            (resources.view("triangle"), None)
+        };
         let mut render_pass = wgpu::RenderPassBuilder::new()
-            .color_attachment(&self.texture_view, |color| {
+            .color_attachment(target, |color| {
                 color
-                    .resolve_target(None)
+                    .resolve_target(resolve_target)
                     .load_op(wgpu::LoadOp::Clear(wgpu::Color {
-                        r: *i as f64 / 10.,
-                        g: *i as f64 / 20.,
+                        r: i as f64 / 10.,
+                        g: i as f64 / 20.,
                         b: 0.5,
                         a: 1.0,
                     }))
@@ -88,18 +116,4 @@ impl PassTriangle {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.draw(0..3, 0..1);
     }
-
-    pub fn resized(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {}
-
-    pub fn key_pressed(&mut self, key: Key) {}
-
-    pub fn key_released(&mut self, key: Key) {}
-
-    pub fn mouse_moved(&mut self, curr_pos: Point2, prev_pos: Point2) {}
-
-    pub fn mouse_wheel(&mut self, delta: &MouseScrollDelta) {}
-
-    pub fn output_texture(&self) -> &wgpu::Texture {
-        &self.texture
-    }
 }
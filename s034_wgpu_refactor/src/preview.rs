@@ -0,0 +1,246 @@
+//! previews of captured sequences for the READMEs
+//!
+//! `--preview <dir>` reads the `frame_00042.png` sequence in dir, e.g. of `--offline`, and writes
+//! a contact sheet, a looping GIF and a thumbnail without a GPU
+//!
+//! ```sh
+//! cargo run --release -- --preview capture --out img --thumbnail 320x180
+//! ```
+//!
+//! `contact_sheet.png` has `--cells` frames spread over the sequence in `--columns` columns of
+//! `--cell-width` pixels. `preview.gif` plays every `--step`th frame at `--fps` / step,
+//! `--gif-width` pixels wide. `thumbnail.png` is the middle frame, or `--thumbnail-frame`,
+//! cropped to `--thumbnail <w>x<h>`. they are written to `--out`, the sequence dir by default
+use anyhow::*;
+use std::path::{Path, PathBuf};
+
+use crate::offline::{split_pair, MIN_FPS};
+use crate::renderer::capture::GifWriterBuilder;
+use crate::renderer::contact_sheet::{self, ContactSheetBuilder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewConfig {
+    pub input: PathBuf,
+    /// `input` if `None`
+    pub output: Option<PathBuf>,
+    pub columns: u32,
+    pub cells: usize,
+    pub cell_width: u32,
+    pub gif_width: u32,
+    /// of the sequence, the GIF plays at `fps / step`
+    pub fps: f64,
+    pub step: usize,
+    pub thumbnail: (u32, u32),
+    /// the middle frame if `None`
+    pub thumbnail_frame: Option<u32>,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            input: PathBuf::from("capture"),
+            output: None,
+            columns: 4,
+            cells: 16,
+            cell_width: 240,
+            gif_width: 400,
+            fps: 30.0,
+            step: 1,
+            thumbnail: (320, 180),
+            thumbnail_frame: None,
+        }
+    }
+}
+
+impl PreviewConfig {
+    /// `None` unless `--preview` is given, the other options are only parsed then.
+    /// unknown `--` options are an error
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>> {
+        let args = args.into_iter().collect::<Vec<_>>();
+        if !args.iter().any(|arg| arg == "--preview") {
+            return Ok(None);
+        }
+        let mut args = args.into_iter();
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--preview" => config.input = PathBuf::from(value()?),
+                "--out" => config.output = Some(PathBuf::from(value()?)),
+                "--columns" => config.columns = value()?.parse()?,
+                "--cells" => config.cells = value()?.parse()?,
+                "--cell-width" => config.cell_width = value()?.parse()?,
+                "--gif-width" => config.gif_width = value()?.parse()?,
+                "--fps" => config.fps = value()?.parse()?,
+                "--step" => config.step = value()?.parse()?,
+                "--thumbnail" => config.thumbnail = split_pair(&value()?, 'x')?,
+                "--thumbnail-frame" => config.thumbnail_frame = Some(value()?.parse()?),
+                flag if flag.starts_with("--") => bail!("Unknown preview option {}", flag),
+                _ => {}
+            }
+        }
+        if !config.fps.is_finite() || config.fps < MIN_FPS {
+            bail!(
+                "fps must be finite and at least {} but got {}",
+                MIN_FPS,
+                config.fps
+            );
+        }
+        if config.columns == 0 || config.cells == 0 || config.step == 0 {
+            bail!("Columns, cells and step must not be 0");
+        }
+        if config.cell_width == 0 || config.gif_width == 0 {
+            bail!("Preview widths must not be 0");
+        }
+        if config.thumbnail.0 == 0 || config.thumbnail.1 == 0 {
+            bail!("Empty thumbnail size {:?}", config.thumbnail);
+        }
+        Ok(Some(config))
+    }
+
+    pub fn output_dir(&self) -> &Path {
+        self.output.as_deref().unwrap_or(&self.input)
+    }
+
+    pub fn contact_sheet_path(&self) -> PathBuf {
+        self.output_dir().join("contact_sheet.png")
+    }
+
+    pub fn gif_path(&self) -> PathBuf {
+        self.output_dir().join("preview.gif")
+    }
+
+    pub fn thumbnail_path(&self) -> PathBuf {
+        self.output_dir().join("thumbnail.png")
+    }
+}
+
+fn open(path: &Path) -> Result<image::RgbaImage> {
+    let image = image::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(image.to_rgba8())
+}
+
+/// writes the contact sheet, the GIF and the thumbnail of `config.input`
+pub fn run(config: &PreviewConfig) -> Result<()> {
+    let sequence = contact_sheet::list_sequence(&config.input)?;
+    if sequence.is_empty() {
+        bail!("No frame_*.png in {}", config.input.display());
+    }
+    std::fs::create_dir_all(config.output_dir())?;
+
+    let cells = contact_sheet::sample_indices(sequence.len(), config.cells)
+        .into_iter()
+        .map(|i| {
+            let (frame, path) = &sequence[i];
+            Ok((*frame, open(path)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let sheet = ContactSheetBuilder::new()
+        .columns(config.columns)
+        .cell_width(config.cell_width)
+        .build(&cells)?;
+    save(&sheet, &config.contact_sheet_path())?;
+
+    // frames are streamed into the GIF one at a time
+    let first = open(&sequence[0].1)?;
+    let gif_height = contact_sheet::scaled_height(first.dimensions(), config.gif_width);
+    let mut gif = GifWriterBuilder::new(config.gif_width, gif_height)
+        .fps(config.fps / config.step as f64)
+        .create(config.gif_path())?;
+    for (_, path) in sequence.iter().step_by(config.step) {
+        let frame = image::imageops::resize(
+            &open(path)?,
+            config.gif_width,
+            gif_height,
+            image::imageops::FilterType::Triangle,
+        );
+        gif.write_frame(&frame)?;
+    }
+    gif.finish()?;
+    println!("{}", config.gif_path().display());
+
+    let (_, path) = match config.thumbnail_frame {
+        Some(frame) => sequence
+            .iter()
+            .find(|(f, _)| *f == frame)
+            .with_context(|| format!("No frame {} in {}", frame, config.input.display()))?,
+        None => &sequence[sequence.len() / 2],
+    };
+    let (width, height) = config.thumbnail;
+    save(
+        &contact_sheet::thumbnail(&open(path)?, width, height),
+        &config.thumbnail_path(),
+    )?;
+    Ok(())
+}
+
+fn save(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    image
+        .save(path)
+        .with_context(|| format!("Failed to save {}", path.display()))?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+#[test]
+fn test_preview() {
+    let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+
+    // the options of other modes aren't parsed without --preview
+    assert_eq!(
+        PreviewConfig::from_args(args("app --offline --fps 0 --size 64x64")).unwrap(),
+        None
+    );
+    let config = PreviewConfig::from_args(args(
+        "app --preview renders --columns 6 --thumbnail 200x200 --step 2",
+    ))
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.input, PathBuf::from("renders"));
+    assert_eq!((config.columns, config.step), (6, 2));
+    assert_eq!(config.thumbnail, (200, 200));
+    assert_eq!(config.gif_path(), Path::new("renders/preview.gif"));
+    assert!(PreviewConfig::from_args(args("app --preview renders --cells 0")).is_err());
+    assert!(PreviewConfig::from_args(args("app --preview renders --thumbnail 200")).is_err());
+    assert!(PreviewConfig::from_args(args("app --preview renders --colums 6")).is_err());
+    assert!(PreviewConfig::from_args(args("app --preview renders --fps inf")).is_err());
+
+    let dir = crate::test_util::TempDir::new("preview");
+    for frame in 0..6 {
+        let color = image::Rgba([frame as u8 * 40, 0, 0, 255]);
+        let image = image::RgbaImage::from_pixel(64, 48, color);
+        image
+            .save(crate::renderer::capture::sequence_path(&dir, frame))
+            .unwrap();
+    }
+    let config = PreviewConfig {
+        input: dir.to_path_buf(),
+        output: Some(dir.join("img")),
+        cells: 4,
+        columns: 2,
+        cell_width: 32,
+        gif_width: 16,
+        step: 2,
+        thumbnail: (10, 10),
+        ..PreviewConfig::default()
+    };
+    run(&config).unwrap();
+    let sheet = image::open(config.contact_sheet_path()).unwrap().to_rgba8();
+    assert_eq!(sheet.dimensions(), (2 * 36 + 4, 2 * 28 + 4));
+    let thumbnail = image::open(config.thumbnail_path()).unwrap().to_rgba8();
+    assert_eq!(thumbnail.get_pixel(5, 5), &image::Rgba([120, 0, 0, 255]));
+    let gif = std::fs::File::open(config.gif_path()).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (16, 12));
+    let mut frames = 0;
+    while decoder.read_next_frame().unwrap().is_some() {
+        frames += 1;
+    }
+    assert_eq!(frames, 3);
+
+    drop(dir);
+    assert!(run(&config).is_err());
+}
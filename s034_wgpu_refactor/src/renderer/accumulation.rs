@@ -0,0 +1,198 @@
+//! accumulates sub-frames into motion blurred and supersampled frames
+//!
+//! a frame is rendered several times at times spread over the shutter interval and with the
+//! projection jittered within a pixel, then the sub-frames are averaged in linear color
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JitterSequence {
+    /// bases 2 and 3
+    Halton,
+    /// Roberts' R2, more even for a small number of samples
+    R2,
+}
+
+impl JitterSequence {
+    /// `index`-th point in [0, 1)^2
+    pub fn sample(&self, index: u32) -> [f32; 2] {
+        match self {
+            Self::Halton => [halton(index + 1, 2), halton(index + 1, 3)],
+            Self::R2 => r2(index),
+        }
+    }
+}
+
+/// radical inverse of `index` in `base`
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f64;
+        result += fraction * (index % base) as f64;
+        index /= base;
+    }
+    result as f32
+}
+
+/// http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+pub fn r2(index: u32) -> [f32; 2] {
+    // the plastic number, the real root of x^3 = x + 1
+    const G: f64 = 1.324_717_957_244_746;
+    let n = index as f64 + 0.5;
+    [(n / G).fract() as f32, (n / (G * G)).fract() as f32]
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SubFrame {
+    /// in [0, 1) of the shutter interval, increasing with the sub-frames
+    pub time: f32,
+    /// in pixels, in [-0.5, 0.5)
+    pub offset: [f32; 2],
+}
+
+/// one sub-frame in each of `samples` equal parts of the shutter interval
+pub fn subframes(samples: u32, sequence: JitterSequence) -> Vec<SubFrame> {
+    (0..samples)
+        .map(|i| {
+            let [x, y] = sequence.sample(i);
+            SubFrame {
+                time: (i as f32 + halton(i + 1, 5)) / samples as f32,
+                offset: [x - 0.5, y - 0.5],
+            }
+        })
+        .collect()
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// sums sRGB frames in linear color
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sum: Vec<f32>,
+    count: u32,
+    /// linear value of each 8 bit sRGB value
+    to_linear: Vec<f32>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            sum: vec![0.0; (width * height * 4) as usize],
+            count: 0,
+            to_linear: (0..256).map(|c| srgb_to_linear(c as f32 / 255.0)).collect(),
+        }
+    }
+
+    /// alpha is averaged as it is
+    pub fn add(&mut self, image: &image::RgbaImage) {
+        assert_eq!(
+            image.dimensions(),
+            (self.width, self.height),
+            "accumulated frames must have the same size"
+        );
+        for (i, (sum, &c)) in self.sum.iter_mut().zip(image.as_raw().iter()).enumerate() {
+            *sum += match i % 4 {
+                3 => c as f32 / 255.0,
+                _ => self.to_linear[c as usize],
+            };
+        }
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// the average of the frames so far
+    pub fn resolve(&self) -> image::RgbaImage {
+        let scale = 1.0 / self.count.max(1) as f32;
+        let data = self
+            .sum
+            .iter()
+            .enumerate()
+            .map(|(i, &sum)| {
+                let c = sum * scale;
+                let c = if i % 4 == 3 { c } else { linear_to_srgb(c) };
+                (c * 255.0).round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        image::RgbaImage::from_raw(self.width, self.height, data).unwrap()
+    }
+
+    pub fn clear(&mut self) {
+        self.sum.iter_mut().for_each(|sum| *sum = 0.0);
+        self.count = 0;
+    }
+}
+
+#[test]
+fn test_accumulation() {
+    assert_eq!(halton(1, 2), 0.5);
+    assert_eq!(halton(2, 2), 0.25);
+    assert_eq!(halton(3, 2), 0.75);
+    assert!((halton(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+    assert!((halton(5, 3) - 7.0 / 9.0).abs() < 1e-6);
+
+    // both sequences spread evenly over the quadrants of a pixel
+    for &sequence in &[JitterSequence::Halton, JitterSequence::R2] {
+        let mut quadrants = [0; 4];
+        for i in 0..64 {
+            let [x, y] = sequence.sample(i);
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            quadrants[(x >= 0.5) as usize + 2 * (y >= 0.5) as usize] += 1;
+        }
+        assert!(
+            quadrants.iter().all(|&n| (n as i32 - 16).abs() <= 2),
+            "{:?}",
+            quadrants
+        );
+    }
+
+    let frames = subframes(8, JitterSequence::R2);
+    assert_eq!(frames.len(), 8);
+    for (i, frame) in frames.iter().enumerate() {
+        assert!(frame.time >= i as f32 / 8.0 && frame.time < (i + 1) as f32 / 8.0);
+        assert!(frame.offset.iter().all(|o| (-0.5..0.5).contains(o)));
+    }
+
+    // every sRGB value survives a round trip
+    let mut accumulator = Accumulator::new(256, 1);
+    let ramp = image::RgbaImage::from_fn(256, 1, |x, _| image::Rgba([x as u8, x as u8, 0, 255]));
+    accumulator.add(&ramp);
+    accumulator.add(&ramp);
+    assert_eq!(accumulator.resolve(), ramp);
+
+    // black and white average to half the light, not half the sRGB value
+    let mut accumulator = Accumulator::new(1, 1);
+    accumulator.add(&image::RgbaImage::from_pixel(
+        1,
+        1,
+        image::Rgba([0, 0, 0, 255]),
+    ));
+    accumulator.add(&image::RgbaImage::from_pixel(
+        1,
+        1,
+        image::Rgba([255, 255, 255, 0]),
+    ));
+    assert_eq!(accumulator.count(), 2);
+    let pixel = *accumulator.resolve().get_pixel(0, 0);
+    assert_eq!(pixel, image::Rgba([188, 188, 188, 128]));
+    accumulator.clear();
+    assert_eq!(accumulator.count(), 0);
+}
@@ -0,0 +1,99 @@
+//! sorts draws by pipeline and material so that the state is only switched when it changes
+//!
+//! pipelines, materials and meshes are indices into whatever the caller draws from, e.g.
+//! `Geom::meshes` and `Geom::materials`
+
+/// one mesh drawn with a pipeline and a material
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DrawItem {
+    pub pipeline: usize,
+    pub material: usize,
+    pub mesh: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DrawCommand {
+    SetPipeline(usize),
+    /// always follows `SetPipeline`, since bind groups may be invalidated by a pipeline switch
+    SetMaterial(usize),
+    Draw(usize),
+}
+
+/// stable, so meshes with the same pipeline and material keep their order
+pub fn sort(items: &mut [DrawItem]) {
+    items.sort_by_key(|item| (item.pipeline, item.material));
+}
+
+/// sorts `items` and emits the commands to draw them
+pub fn plan(items: &[DrawItem]) -> Vec<DrawCommand> {
+    let mut items = items.to_vec();
+    sort(&mut items);
+
+    let mut commands = Vec::with_capacity(items.len() * 3);
+    let mut current: Option<(usize, usize)> = None;
+    for item in &items {
+        match current {
+            Some((pipeline, material)) if pipeline == item.pipeline => {
+                if material != item.material {
+                    commands.push(DrawCommand::SetMaterial(item.material));
+                }
+            }
+            _ => {
+                commands.push(DrawCommand::SetPipeline(item.pipeline));
+                commands.push(DrawCommand::SetMaterial(item.material));
+            }
+        }
+        current = Some((item.pipeline, item.material));
+        commands.push(DrawCommand::Draw(item.mesh));
+    }
+    commands
+}
+
+/// `SetPipeline` and `SetMaterial` in `commands`
+pub fn state_changes(commands: &[DrawCommand]) -> usize {
+    commands
+        .iter()
+        .filter(|command| !matches!(command, DrawCommand::Draw(_)))
+        .count()
+}
+
+#[test]
+fn test_batch_plan() {
+    use DrawCommand::*;
+    let item = |pipeline, material, mesh| DrawItem {
+        pipeline,
+        material,
+        mesh,
+    };
+    let items = vec![
+        item(1, 0, 0),
+        item(0, 1, 1),
+        item(1, 0, 2),
+        item(0, 0, 3),
+        item(0, 1, 4),
+        item(1, 2, 5),
+    ];
+    let commands = plan(&items);
+    assert_eq!(
+        commands,
+        vec![
+            SetPipeline(0),
+            SetMaterial(0),
+            Draw(3),
+            SetMaterial(1),
+            Draw(1),
+            Draw(4),
+            SetPipeline(1),
+            SetMaterial(0),
+            Draw(0),
+            Draw(2),
+            SetMaterial(2),
+            Draw(5),
+        ]
+    );
+    // one switch per distinct pipeline and (pipeline, material)
+    // instead of both for every item
+    assert_eq!(state_changes(&commands), 2 + 4);
+
+    assert!(plan(&[]).is_empty());
+}
@@ -0,0 +1,127 @@
+//! metallic-roughness Cook-Torrance BRDF (GGX + Smith + Schlick)
+//!
+//! must be same as `shaders/pbr.frag`. this is the CPU reference of the shader,
+//! so keep the formulas in sync when one of them changes
+use nannou::math::cgmath::{self, Vector3};
+use nannou::prelude::*;
+use std::f32::consts::PI;
+
+/// reflectance at normal incidence of dielectrics
+pub const DIELECTRIC_F0: f32 = 0.04;
+
+/// must be same as shaders
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneMapping {
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+/// GGX / Trowbridge-Reitz normal distribution. `roughness` is perceptual (alpha = roughness^2)
+pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let n_dot_h = n_dot_h.max(0.0);
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d).max(1e-7)
+}
+
+/// Schlick-GGX for one direction with k for direct lighting
+pub fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    n_dot_x / (n_dot_x * (1.0 - k) + k)
+}
+
+pub fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    geometry_schlick_ggx(n_dot_v.max(0.0), roughness)
+        * geometry_schlick_ggx(n_dot_l.max(0.0), roughness)
+}
+
+pub fn fresnel_schlick(cos_theta: f32, f0: Vector3<f32>) -> Vector3<f32> {
+    let t = (1.0 - cos_theta).max(0.0).min(1.0).powi(5);
+    f0 + (Vector3::new(1.0, 1.0, 1.0) - f0) * t
+}
+
+pub fn f0(base_color: Vector3<f32>, metallic: f32) -> Vector3<f32> {
+    let dielectric = Vector3::new(DIELECTRIC_F0, DIELECTRIC_F0, DIELECTRIC_F0);
+    dielectric.lerp(base_color, metallic)
+}
+
+/// BRDF * cos(theta_l). multiply by the incoming radiance to get the outgoing radiance
+pub fn cook_torrance(
+    n: Vector3<f32>,
+    v: Vector3<f32>,
+    l: Vector3<f32>,
+    base_color: Vector3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> Vector3<f32> {
+    let n_dot_l = n.dot(l).max(0.0);
+    let n_dot_v = n.dot(v).max(1e-4);
+    if n_dot_l <= 0.0 {
+        return Vector3::zero();
+    }
+    let h = (v + l).normalize();
+    let n_dot_h = n.dot(h).max(0.0);
+    let v_dot_h = v.dot(h).max(0.0);
+
+    let f = fresnel_schlick(v_dot_h, f0(base_color, metallic));
+    let d = distribution_ggx(n_dot_h, roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    let specular = f * (d * g / (4.0 * n_dot_v * n_dot_l).max(1e-4));
+
+    // energy which is not reflected is refracted, and metals absorb all of it
+    let kd = (Vector3::new(1.0, 1.0, 1.0) - f) * (1.0 - metallic);
+    let diffuse = kd.mul_element_wise(base_color) / PI;
+    (diffuse + specular) * n_dot_l
+}
+
+pub fn tone_map(color: Vector3<f32>, exposure: f32, tone_mapping: ToneMapping) -> Vector3<f32> {
+    let color = color * exposure;
+    match tone_mapping {
+        ToneMapping::None => color,
+        ToneMapping::Reinhard => color.map(|c| c / (1.0 + c)),
+        // Narkowicz 2015, "ACES Filmic Tone Mapping Curve"
+        ToneMapping::Aces => color.map(|c| {
+            let (a, b, c_, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+            ((c * (a * c + b)) / (c * (c_ * c + d) + e))
+                .max(0.0)
+                .min(1.0)
+        }),
+    }
+}
+
+#[test]
+fn test_brdf_reference() {
+    // GGX is normalized: the projected microfacet area integrates to 1 over the hemisphere
+    for &roughness in &[0.2, 0.5, 1.0] {
+        let steps = 2048;
+        let d_theta = (PI / 2.0) / steps as f32;
+        let integral = (0..steps)
+            .map(|i| {
+                let theta = (i as f32 + 0.5) * d_theta;
+                let cos = theta.cos();
+                distribution_ggx(cos, roughness) * cos * theta.sin() * d_theta * 2.0 * PI
+            })
+            .sum::<f32>();
+        assert!((integral - 1.0).abs() < 0.02, "{} {}", roughness, integral);
+    }
+
+    // Fresnel goes from F0 at normal incidence to white at grazing angles
+    let f0 = f0(Vector3::new(1.0, 0.5, 0.25), 1.0);
+    assert!((fresnel_schlick(1.0, f0) - f0).magnitude() < 1e-6);
+    assert!((fresnel_schlick(0.0, f0) - Vector3::new(1.0, 1.0, 1.0)).magnitude() < 1e-6);
+
+    // a rough white dielectric lit from the view direction stays below the Lambertian upper bound
+    let n = Vector3::unit_z();
+    let white = Vector3::new(1.0, 1.0, 1.0);
+    let c = cook_torrance(n, n, n, white, 0.0, 1.0);
+    assert!(c.x > 0.0 && c.x <= 1.0 / PI + 0.1, "{:?}", c);
+    // no light from below the surface
+    let below = cgmath::Vector3::new(0.0, 0.0, -1.0);
+    assert_eq!(cook_torrance(n, n, below, white, 0.0, 0.5), Vector3::zero());
+
+    let mapped = tone_map(Vector3::new(100.0, 1.0, 0.0), 1.0, ToneMapping::Aces);
+    assert!(mapped.x <= 1.0 && mapped.y < 1.0 && mapped.z == 0.0);
+}
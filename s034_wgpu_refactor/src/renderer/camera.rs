@@ -24,6 +24,17 @@ pub struct Projection {
     pub fovy: Rad<f32>,
     pub znear: f32,
     pub zfar: f32,
+    /// only this part of the image is projected to the viewport if set
+    pub tile: Option<ProjectionTile>,
+}
+
+/// a rectangle of the image in [0, 1] from the top left. may reach outside of it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProjectionTile {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
 }
 
 impl Projection {
@@ -46,6 +57,7 @@ impl Projection {
             fovy: fovy.into(),
             znear,
             zfar,
+            tile: None,
         }
     }
 
@@ -54,8 +66,27 @@ impl Projection {
     }
 
     pub fn projection_matrix(&self) -> Matrix4<f32> {
+        let tile = match self.tile {
+            Some(tile) => tile,
+            None => {
+                return Self::OPENGL_TO_WGPU_MATRIX
+                    * cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
+            }
+        };
+        // off-axis part of the near plane of the whole image
+        let top = self.znear * (self.fovy.0 * 0.5).tan();
+        let right = top * self.aspect;
+        let x = |u: f32| -right + 2.0 * right * u;
+        let y = |v: f32| top - 2.0 * top * v;
         Self::OPENGL_TO_WGPU_MATRIX
-            * cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
+            * cgmath::frustum(
+                x(tile.x),
+                x(tile.x + tile.width),
+                y(tile.y + tile.height),
+                y(tile.y),
+                self.znear,
+                self.zfar,
+            )
     }
 }
 
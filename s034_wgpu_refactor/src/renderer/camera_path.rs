@@ -0,0 +1,469 @@
+//! keyframed camera moves for deterministic fly-throughs
+//!
+//! positions (and fovy) are interpolated with the selected spline, orientations with slerp.
+//! paths can be saved / loaded as JSON or RON (selected by file extension)
+use anyhow::*;
+use nannou::math::cgmath::{
+    self, Deg, EuclideanSpace, InnerSpace, Matrix, Matrix3, Quaternion, Rad, Rotation, Vector3,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+use super::camera::{Camera, CameraRotation};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+    /// cubic bezier using `Keyframe::tangent` as handles (auto handles if `None`)
+    Bezier,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "KeyframeRaw", into = "KeyframeRaw")]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: cgmath::Point3<f32>,
+    /// rotates local +z to the viewing direction
+    pub orientation: Quaternion<f32>,
+    pub fovy: Deg<f32>,
+    /// bezier handle offset, mirrored on both sides of the keyframe
+    pub tangent: Option<Vector3<f32>>,
+}
+
+impl Keyframe {
+    pub fn look_at(
+        time: f32,
+        position: cgmath::Point3<f32>,
+        target: cgmath::Point3<f32>,
+        up: Vector3<f32>,
+        fovy: f32,
+    ) -> Self {
+        Self::look_dir(time, position, target - position, up, fovy)
+    }
+
+    pub fn look_dir(
+        time: f32,
+        position: cgmath::Point3<f32>,
+        dir: Vector3<f32>,
+        up: Vector3<f32>,
+        fovy: f32,
+    ) -> Self {
+        // Matrix3::look_at maps dir to +z, so its transpose (= inverse) maps +z to dir
+        let orientation = Quaternion::from(Matrix3::look_at(dir, up).transpose());
+        Self {
+            time,
+            position,
+            orientation,
+            fovy: Deg(fovy),
+            tangent: None,
+        }
+    }
+
+    pub fn tangent(mut self, tangent: Vector3<f32>) -> Self {
+        self.tangent = Some(tangent);
+        self
+    }
+}
+
+// file representation, plain arrays so that the format doesn't depend on cgmath's serde support
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyframeRaw {
+    time: f32,
+    position: [f32; 3],
+    /// [x, y, z, w]
+    orientation: [f32; 4],
+    /// degrees
+    fovy: f32,
+    #[serde(default)]
+    tangent: Option<[f32; 3]>,
+}
+
+impl From<KeyframeRaw> for Keyframe {
+    fn from(raw: KeyframeRaw) -> Self {
+        let [x, y, z, w] = raw.orientation;
+        Self {
+            time: raw.time,
+            position: raw.position.into(),
+            orientation: Quaternion::new(w, x, y, z).normalize(),
+            fovy: Deg(raw.fovy),
+            tangent: raw.tangent.map(Vector3::from),
+        }
+    }
+}
+
+impl From<Keyframe> for KeyframeRaw {
+    fn from(key: Keyframe) -> Self {
+        let q = key.orientation;
+        Self {
+            time: key.time,
+            position: key.position.into(),
+            orientation: [q.v.x, q.v.y, q.v.z, q.s],
+            fovy: key.fovy.0,
+            tangent: key.tangent.map(Into::into),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraSample {
+    pub position: cgmath::Point3<f32>,
+    pub orientation: Quaternion<f32>,
+    pub fovy: Deg<f32>,
+}
+
+impl CameraSample {
+    pub fn forward(&self) -> Vector3<f32> {
+        self.orientation.rotate_vector(Vector3::unit_z())
+    }
+
+    /// if the camera is target based, the target is moved along the viewing direction.
+    /// otherwise yaw / pitch are derived to match `Camera::view_matrix`
+    pub fn apply(&self, camera: &mut Camera) {
+        let forward = self.forward();
+        camera.position = self.position;
+        camera.projection.fovy = self.fovy.into();
+        if camera.target.is_some() {
+            camera.target = Some(self.position + forward);
+        } else {
+            // camera direction is normalize(cos(yaw), sin(pitch), sin(yaw))
+            let horizontal = (forward.x * forward.x + forward.z * forward.z).sqrt();
+            let yaw = Rad(forward.z.atan2(forward.x));
+            let pitch = if horizontal > std::f32::EPSILON {
+                Rad((forward.y / horizontal).max(-1.0).min(1.0).asin())
+            } else {
+                Rad(std::f32::consts::FRAC_PI_2.copysign(forward.y))
+            };
+            camera.rotation = Some(CameraRotation { yaw, pitch });
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub interpolation: Interpolation,
+    /// wrap time around the path. the last keyframe should be same as the first one
+    #[serde(default)]
+    pub looped: bool,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            interpolation,
+            looped: false,
+            keyframes: vec![],
+        }
+    }
+
+    pub fn looped(mut self, looped: bool) -> Self {
+        self.looped = looped;
+        self
+    }
+
+    pub fn keyframe(mut self, keyframe: Keyframe) -> Self {
+        self.push(keyframe);
+        self
+    }
+
+    /// keyframes are kept sorted by time
+    pub fn push(&mut self, keyframe: Keyframe) {
+        let index = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > keyframe.time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn start_time(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |k| k.time)
+    }
+
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// `progress` is normalized to [0.0, 1.0] over the whole path
+    pub fn sample_progress(&self, progress: f32) -> Option<CameraSample> {
+        self.sample(self.start_time() + progress * self.duration())
+    }
+
+    /// `None` unless the path has keyframes sorted by finite times, which `push` and `load` keep
+    pub fn sample(&self, time: f32) -> Option<CameraSample> {
+        let keys = &self.keyframes;
+        let sorted = keys.windows(2).all(|k| k[0].time <= k[1].time);
+        if keys.is_empty() || !sorted || keys.iter().any(|k| !k.time.is_finite()) {
+            return None;
+        }
+        let n = keys.len();
+        if n == 1 {
+            return Some(Self::sample_key(&keys[0]));
+        }
+
+        let start = self.start_time();
+        let duration = self.duration();
+        let time = if self.looped && duration > 0.0 {
+            start + (time - start).rem_euclid(duration)
+        } else {
+            time.max(start).min(start + duration)
+        };
+
+        // keys[i].time <= time <= keys[i + 1].time
+        let i = keys[1..n - 1]
+            .iter()
+            .position(|k| time < k.time)
+            .unwrap_or(n - 2);
+        let (k1, k2) = (&keys[i], &keys[i + 1]);
+        let span = k2.time - k1.time;
+        let u = if span > 0.0 {
+            (time - k1.time) / span
+        } else {
+            0.0
+        };
+
+        // neighbours for the cubic splines. a looped path skips the duplicated end keyframe
+        let k0 = if i > 0 {
+            &keys[i - 1]
+        } else if self.looped {
+            &keys[n - 2]
+        } else {
+            k1
+        };
+        let k3 = if i + 2 < n {
+            &keys[i + 2]
+        } else if self.looped {
+            &keys[1]
+        } else {
+            k2
+        };
+
+        let (position, fovy) = match self.interpolation {
+            Interpolation::Linear => (
+                k1.position + (k2.position - k1.position) * u,
+                lerp(k1.fovy.0, k2.fovy.0, u),
+            ),
+            Interpolation::CatmullRom => (
+                cgmath::Point3::from_vec(catmull_rom(
+                    k0.position.to_vec(),
+                    k1.position.to_vec(),
+                    k2.position.to_vec(),
+                    k3.position.to_vec(),
+                    u,
+                )),
+                catmull_rom(k0.fovy.0, k1.fovy.0, k2.fovy.0, k3.fovy.0, u),
+            ),
+            Interpolation::Bezier => {
+                let p1 = k1.position.to_vec();
+                let p2 = k2.position.to_vec();
+                let t1 = k1
+                    .tangent
+                    .unwrap_or_else(|| auto_handle(k0.position.to_vec(), k2.position.to_vec()));
+                let t2 = k2
+                    .tangent
+                    .unwrap_or_else(|| auto_handle(k1.position.to_vec(), k3.position.to_vec()));
+                let fovy_h1 = auto_handle(k0.fovy.0, k2.fovy.0);
+                let fovy_h2 = auto_handle(k1.fovy.0, k3.fovy.0);
+                (
+                    cgmath::Point3::from_vec(bezier(p1, p1 + t1, p2 - t2, p2, u)),
+                    bezier(
+                        k1.fovy.0,
+                        k1.fovy.0 + fovy_h1,
+                        k2.fovy.0 - fovy_h2,
+                        k2.fovy.0,
+                        u,
+                    ),
+                )
+            }
+        };
+
+        Some(CameraSample {
+            position,
+            orientation: slerp_shortest(k1.orientation, k2.orientation, u),
+            fovy: Deg(fovy),
+        })
+    }
+
+    /// leaves the camera as it is if the path can't be sampled, see `sample`
+    pub fn apply(&self, camera: &mut Camera, time: f32) {
+        if let Some(sample) = self.sample(time) {
+            sample.apply(camera);
+        }
+    }
+
+    /// keyframes are sorted by time, a path without keyframes or with a non-finite time is an
+    /// error
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let src = read_to_string(path)?;
+        let camera_path: Self = match Self::extension(path)? {
+            "json" => serde_json::from_str(&src)?,
+            "ron" => ron::de::from_str(&src)?,
+            _ => bail!("Unsupported camera path: {}", path.display()),
+        };
+        camera_path
+            .validated()
+            .with_context(|| format!("Invalid camera path: {}", path.display()))
+    }
+
+    fn validated(mut self) -> Result<Self> {
+        if self.keyframes.is_empty() {
+            bail!("Camera path has no keyframes");
+        }
+        if let Some(key) = self.keyframes.iter().find(|k| !k.time.is_finite()) {
+            bail!("Keyframe time {} is not finite", key.time);
+        }
+        // stable, keyframes at the same time keep their order
+        self.keyframes
+            .sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Ok(self)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let dst = match Self::extension(path)? {
+            "json" => serde_json::to_string_pretty(self)?,
+            "ron" => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+            _ => bail!("Unsupported camera path: {}", path.display()),
+        };
+        write(path, dst)?;
+        Ok(())
+    }
+
+    fn extension(path: &Path) -> Result<&str> {
+        path.extension()
+            .context("File has no extension")?
+            .to_str()
+            .context("Extension cannot be converted to &str")
+    }
+
+    fn sample_key(key: &Keyframe) -> CameraSample {
+        CameraSample {
+            position: key.position,
+            orientation: key.orientation,
+            fovy: key.fovy,
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, u: f32) -> f32 {
+    a + (b - a) * u
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, u: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let u2 = u * u;
+    let u3 = u2 * u;
+    (p1 * 2.0
+        + (p2 - p0) * u
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * u2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * u3)
+        * 0.5
+}
+
+fn bezier<T>(p0: T, c0: T, c1: T, p1: T, u: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let v = 1.0 - u;
+    p0 * (v * v * v) + c0 * (3.0 * v * v * u) + c1 * (3.0 * v * u * u) + p1 * (u * u * u)
+}
+
+// same curve as catmull-rom when used as bezier handles
+fn auto_handle<T>(prev: T, next: T) -> T
+where
+    T: Sub<Output = T> + Mul<f32, Output = T>,
+{
+    (next - prev) * (1.0 / 6.0)
+}
+
+fn slerp_shortest(a: Quaternion<f32>, b: Quaternion<f32>, u: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, u).normalize()
+}
+
+#[test]
+fn test_camera_path_passes_through_keyframes() {
+    let up = Vector3::unit_y();
+    let path = CameraPath::new(Interpolation::CatmullRom)
+        .keyframe(Keyframe::look_at(
+            0.0,
+            (0.0, 5.0, 10.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            up,
+            45.0,
+        ))
+        .keyframe(Keyframe::look_at(
+            1.0,
+            (10.0, 5.0, 0.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            up,
+            60.0,
+        ))
+        .keyframe(Keyframe::look_at(
+            3.0,
+            (0.0, 5.0, -10.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            up,
+            45.0,
+        ));
+
+    for key in &path.keyframes {
+        let sample = path.sample(key.time).unwrap();
+        assert!((sample.position - key.position).magnitude() < 1e-4);
+        assert!((sample.fovy.0 - key.fovy.0).abs() < 1e-4);
+        let dir = (cgmath::Point3::new(0.0, 0.0, 0.0) - key.position).normalize();
+        assert!((sample.forward() - dir).magnitude() < 1e-4);
+    }
+    assert_eq!(path.sample_progress(0.5), path.sample(1.5));
+    // clamped outside of the path
+    assert_eq!(path.sample(-1.0), path.sample(0.0));
+}
+
+#[test]
+fn test_camera_path_load_validates() {
+    let up = Vector3::unit_y();
+    let key = |time: f32, x: f32| {
+        Keyframe::look_at(
+            time,
+            (x, 0.0, 10.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            up,
+            45.0,
+        )
+    };
+    // unsorted times are sorted on load
+    let mut unsorted = CameraPath::new(Interpolation::Linear);
+    unsorted.keyframes = vec![key(2.0, 2.0), key(0.0, 0.0), key(1.0, 1.0)];
+    let sorted = unsorted.clone().validated().unwrap();
+    let times = sorted.keyframes.iter().map(|k| k.time).collect::<Vec<_>>();
+    assert_eq!(times, vec![0.0, 1.0, 2.0]);
+    assert!((sorted.sample(0.5).unwrap().position.x - 0.5).abs() < 1e-4);
+    assert!(CameraPath::new(Interpolation::Linear).validated().is_err());
+
+    let dir = crate::test_util::TempDir::new("camera_path");
+    for ext in &["json", "ron"] {
+        let path = dir.join(format!("camera_path.{}", ext));
+        unsorted.save(&path).unwrap();
+        let loaded = CameraPath::load(&path).unwrap();
+        assert_eq!(loaded.keyframes, sorted.keyframes);
+    }
+    let path = dir.join("empty.json");
+    CameraPath::new(Interpolation::Linear).save(&path).unwrap();
+    assert!(CameraPath::load(&path).is_err());
+
+    // paths built in code which can't be sampled leave the camera alone
+    assert_eq!(CameraPath::new(Interpolation::Linear).sample(0.0), None);
+    assert_eq!(unsorted.sample(0.5), None);
+    let mut nan = CameraPath::new(Interpolation::Bezier).keyframe(key(0.0, 0.0));
+    nan.keyframes.push(key(std::f32::NAN, 1.0));
+    assert_eq!(nan.sample_progress(0.5), None);
+}
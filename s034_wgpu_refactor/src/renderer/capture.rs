@@ -0,0 +1,1037 @@
+//! reads rendered frames back to the CPU and encodes them
+//!
+//! unlike `capture_frame`, which hands the snapshot to nannou's capturer thread, the frame is
+//! returned once the GPU is done with it. so it also works without a window, whose event loop
+//! would otherwise poll the device
+use anyhow::*;
+use futures::FutureExt;
+use nannou::prelude::*;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::manifest;
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// rows of a texture copy must be aligned to `COPY_BYTES_PER_ROW_ALIGNMENT`
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let unpadded = width * BYTES_PER_PIXEL;
+    let padding = (align - unpadded % align) % align;
+    unpadded + padding
+}
+
+/// tightly packed pixels of a copy with `padded_bytes_per_row`
+pub fn unpad_rows(data: &[u8], width: u32, height: u32, padded_bytes_per_row: u32) -> Vec<u8> {
+    let unpadded = (width * BYTES_PER_PIXEL) as usize;
+    data.chunks(padded_bytes_per_row as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[..unpadded])
+        .copied()
+        .collect()
+}
+
+/// copies `texture` to a mappable buffer and waits for it. the texture must be single sampled,
+/// `COPY_SRC` and one of the 8 bit RGBA or BGRA formats. sRGB formats are read as they are
+pub async fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<image::RgbaImage> {
+    let bgra = is_bgra(texture)?;
+    let [width, height] = texture.size();
+    let padded = padded_bytes_per_row(width);
+    let buffer = readback_buffer(device, width, height);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("readback"),
+    });
+    copy_to_buffer(&mut encoder, texture, &buffer);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    mapping.await?;
+    let data = unpad_rows(&slice.get_mapped_range(), width, height, padded);
+    buffer.unmap();
+    to_image(data, width, height, bgra)
+}
+
+/// whether the channels must be swapped, or an error if `texture` can't be read back
+fn is_bgra(texture: &wgpu::Texture) -> Result<bool> {
+    if texture.sample_count() > 1 {
+        bail!("Multisampled textures must be resolved before reading back");
+    }
+    match texture.format() {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => Ok(false),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => Ok(true),
+        format => bail!("Cannot read back {:?}", format),
+    }
+}
+
+fn readback_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: (padded_bytes_per_row(width) * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn copy_to_buffer(
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+    buffer: &wgpu::Buffer,
+) {
+    let [width, height] = texture.size();
+    encoder.copy_texture_to_buffer(
+        wgpu::TextureCopyView {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::BufferCopyView {
+            buffer,
+            layout: wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: padded_bytes_per_row(width),
+                rows_per_image: height,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+    );
+}
+
+fn to_image(mut data: Vec<u8>, width: u32, height: u32, bgra: bool) -> Result<image::RgbaImage> {
+    if bgra {
+        for pixel in data.chunks_mut(BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+    image::RgbaImage::from_raw(width, height, data).context("Unexpected readback size")
+}
+
+type Mapping = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// a readback buffer of `CaptureQueue`
+struct CaptureSlot {
+    buffer: wgpu::Buffer,
+    pending: Option<PendingFrame>,
+}
+
+/// a frame in flight
+struct PendingFrame {
+    mapping: Mapping,
+    bgra: bool,
+    path: PathBuf,
+    text: Vec<(String, String)>,
+}
+
+/// pixels of a mapped frame, converted and saved on a worker
+struct EncodeJob {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    bgra: bool,
+    path: PathBuf,
+    /// tEXt chunks of a PNG, see `manifest::save_png`
+    text: Vec<(String, String)>,
+}
+
+impl EncodeJob {
+    fn run(self) -> Result<PathBuf> {
+        let Self {
+            data,
+            width,
+            height,
+            bgra,
+            path,
+            text,
+        } = self;
+        let image = to_image(data, width, height, bgra)?;
+        if !text.is_empty() {
+            manifest::save_png(&image, &path, &text)?;
+            return Ok(path);
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        image
+            .save(&path)
+            .with_context(|| format!("Failed to save {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// threads encoding images by the extension of their path, e.g. PNG or JPEG
+struct CaptureWorkers {
+    jobs: Option<mpsc::SyncSender<EncodeJob>>,
+    results: mpsc::Receiver<Result<PathBuf>>,
+    threads: Vec<JoinHandle<()>>,
+    /// jobs whose result hasn't been received
+    encoding: usize,
+    saved: usize,
+}
+
+impl CaptureWorkers {
+    /// `submit` blocks while `max_pending` jobs are waiting for a worker
+    fn new(workers: usize, max_pending: usize) -> Self {
+        let (jobs, job_receiver) = mpsc::sync_channel::<EncodeJob>(max_pending);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (result_sender, results) = mpsc::channel();
+        let threads = (0..workers.max(1))
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                std::thread::spawn(move || loop {
+                    // the lock is released before encoding, so the workers run in parallel
+                    let job = job_receiver.lock().unwrap().recv();
+                    let job = match job.ok() {
+                        Some(job) => job,
+                        None => return,
+                    };
+                    if result_sender.send(job.run()).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            results,
+            threads,
+            encoding: 0,
+            saved: 0,
+        }
+    }
+
+    fn submit(&mut self, job: EncodeJob) -> Result<()> {
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .map_err(|_| anyhow!("Capture workers have stopped"))?;
+        self.encoding += 1;
+        Ok(())
+    }
+
+    /// collects the finished jobs, or all of them if `wait`. returns the first failure
+    fn receive(&mut self, wait: bool) -> Result<()> {
+        let mut first_error = None;
+        while self.encoding > 0 {
+            let result = if wait {
+                self.results.recv().ok()
+            } else {
+                self.results.try_recv().ok()
+            };
+            let result = match result {
+                Some(result) => result,
+                None => break,
+            };
+            self.encoding -= 1;
+            if let Err(err) = result {
+                first_error.get_or_insert(err);
+            } else {
+                self.saved += 1;
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CaptureWorkers {
+    fn drop(&mut self) {
+        // the workers finish the queued jobs and stop once the channel is closed
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// saves frames without waiting for the GPU or the encoder
+///
+/// every `capture` copies the frame into the next of a ring of readback buffers and maps it.
+/// mapped frames are unpadded on the render thread and handed to worker threads for encoding.
+/// when every buffer is in flight, or the workers are `max_pending` frames behind, `capture`
+/// blocks until one is free. `flush` waits for everything and `finish` also ends the queue.
+/// the device has to be polled to map the frames, so a queue dropped without `finish` only
+/// saves the frames already handed to the workers, and reports the ones it loses
+pub struct CaptureQueue {
+    slots: Vec<CaptureSlot>,
+    /// the oldest frame in flight, and the slot of the next capture
+    next: usize,
+    width: u32,
+    height: u32,
+    workers: CaptureWorkers,
+}
+
+impl CaptureQueue {
+    /// `slots` readback buffers for frames of `width` x `height`, encoded by `workers` threads
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        slots: usize,
+        workers: usize,
+        max_pending: usize,
+    ) -> Self {
+        let slots = (0..slots.max(1))
+            .map(|_| CaptureSlot {
+                buffer: readback_buffer(device, width, height),
+                pending: None,
+            })
+            .collect();
+        Self {
+            slots,
+            next: 0,
+            width,
+            height,
+            workers: CaptureWorkers::new(workers, max_pending),
+        }
+    }
+
+    /// submits `encoder` with a copy of `texture`, which is saved to `path` once it is mapped.
+    /// the texture has the same requirements as `read_texture`. `text` is embedded into a PNG
+    /// as tEXt chunks, or ignored if empty
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut encoder: wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        path: impl Into<PathBuf>,
+        text: Vec<(String, String)>,
+    ) -> Result<()> {
+        let bgra = is_bgra(texture)?;
+        if texture.size() != [self.width, self.height] {
+            bail!(
+                "Capture must be {}x{} but got {:?}",
+                self.width,
+                self.height,
+                texture.size()
+            );
+        }
+        self.poll(device)?;
+        let index = self.next;
+        if self.slots[index].pending.is_some() {
+            // back-pressure: the oldest frame must be mapped before its buffer is reused
+            device.poll(wgpu::Maintain::Wait);
+            self.complete(index, true)?;
+        }
+        self.next = (index + 1) % self.slots.len();
+
+        let slot = &mut self.slots[index];
+        copy_to_buffer(&mut encoder, texture, &slot.buffer);
+        queue.submit(std::iter::once(encoder.finish()));
+        let mapping = slot.buffer.slice(..).map_async(wgpu::MapMode::Read);
+        slot.pending = Some(PendingFrame {
+            mapping: Box::pin(mapping.map(|mapped| mapped.context("Failed to map a capture"))),
+            bgra,
+            path: path.into(),
+            text,
+        });
+        Ok(())
+    }
+
+    /// hands the mapped frames to the workers and reports the first failed save
+    pub fn poll(&mut self, device: &wgpu::Device) -> Result<()> {
+        device.poll(wgpu::Maintain::Poll);
+        self.complete_all(false)?;
+        self.workers.receive(false)
+    }
+
+    /// waits for every frame to be saved
+    pub fn flush(&mut self, device: &wgpu::Device) -> Result<()> {
+        device.poll(wgpu::Maintain::Wait);
+        self.complete_all(true)?;
+        self.workers.receive(true)
+    }
+
+    /// waits for every frame to be saved and stops the workers. returns the frames saved
+    pub fn finish(mut self, device: &wgpu::Device) -> Result<usize> {
+        self.flush(device)?;
+        Ok(self.saved())
+    }
+
+    /// frames written so far
+    pub fn saved(&self) -> usize {
+        self.workers.saved
+    }
+
+    /// frames captured but not saved yet
+    pub fn pending(&self) -> usize {
+        let mapping = self.slots.iter().filter(|slot| slot.pending.is_some());
+        mapping.count() + self.workers.encoding
+    }
+
+    /// in the order of the captures, so the workers get the oldest frames first
+    fn complete_all(&mut self, wait: bool) -> Result<()> {
+        for i in 0..self.slots.len() {
+            self.complete((self.next + i) % self.slots.len(), wait)?;
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, index: usize, wait: bool) -> Result<()> {
+        let slot = &mut self.slots[index];
+        let mapped = match slot.pending.as_mut() {
+            Some(frame) if wait => Some(futures::executor::block_on(&mut frame.mapping)),
+            Some(frame) => (&mut frame.mapping).now_or_never(),
+            None => None,
+        };
+        let mapped = match mapped {
+            Some(mapped) => mapped,
+            None => return Ok(()),
+        };
+        let frame = slot.pending.take().unwrap();
+        mapped?;
+
+        let data = unpad_rows(
+            &slot.buffer.slice(..).get_mapped_range(),
+            self.width,
+            self.height,
+            padded_bytes_per_row(self.width),
+        );
+        slot.buffer.unmap();
+        self.workers.submit(EncodeJob {
+            data,
+            width: self.width,
+            height: self.height,
+            bgra: frame.bgra,
+            path: frame.path,
+            text: frame.text,
+        })
+    }
+}
+
+impl Drop for CaptureQueue {
+    fn drop(&mut self) {
+        let lost = self.slots.iter().filter(|slot| slot.pending.is_some());
+        let lost = lost.count();
+        if lost > 0 {
+            eprintln!(
+                "{} captured frames were dropped before they were read back, \
+                 CaptureQueue::finish saves them",
+                lost
+            );
+        }
+    }
+}
+
+/// `GifWriter` options
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GifWriterBuilder {
+    width: u32,
+    height: u32,
+    delay: Duration,
+    repeat: Option<u16>,
+    speed: i32,
+    dither: bool,
+}
+
+impl GifWriterBuilder {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            delay: Duration::from_millis(100),
+            repeat: None,
+            speed: 10,
+            dither: false,
+        }
+    }
+
+    /// of each frame unless given to `write_frame_with_delay`
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// `1.0 / fps`
+    pub fn fps(self, fps: f64) -> Self {
+        self.delay(Duration::from_secs_f64(1.0 / fps))
+    }
+
+    /// times the animation is repeated after the first play. forever if `None`, the default
+    pub fn repeat(mut self, repeat: Option<u16>) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// palette quantization speed in [1, 30]. lower is better and slower. 10 by default
+    pub fn quality(mut self, speed: i32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Floyd-Steinberg dithering against the palette of each frame
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    pub fn build<W: Write>(self, writer: W) -> Result<GifWriter<W>> {
+        if !(1..=30).contains(&self.speed) {
+            bail!("GIF quality must be in [1, 30] but got {}", self.speed);
+        }
+        let width = u16::try_from(self.width).context("GIF width must fit in u16")?;
+        let height = u16::try_from(self.height).context("GIF height must fit in u16")?;
+        let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+        encoder.set_repeat(match self.repeat {
+            Some(count) => gif::Repeat::Finite(count),
+            None => gif::Repeat::Infinite,
+        })?;
+        Ok(GifWriter {
+            encoder,
+            config: self,
+            delay_error: 0.0,
+            frames: 0,
+        })
+    }
+
+    /// creates the directory of `path`
+    pub fn create(self, path: impl AsRef<Path>) -> Result<GifWriter<BufWriter<File>>> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        self.build(BufWriter::new(file))
+    }
+}
+
+/// encodes frames as they are written, so only the current frame is kept in memory.
+/// frames are opaque, alpha is ignored
+pub struct GifWriter<W: Write> {
+    encoder: gif::Encoder<W>,
+    config: GifWriterBuilder,
+    /// delays are in centiseconds. the rounding error is carried to the next frame, so 30 fps
+    /// alternates 3 and 4 instead of running at 33 fps
+    delay_error: f64,
+    frames: usize,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn write_frame(&mut self, image: &image::RgbaImage) -> Result<()> {
+        self.write_frame_with_delay(image, self.config.delay)
+    }
+
+    pub fn write_frame_with_delay(
+        &mut self,
+        image: &image::RgbaImage,
+        delay: Duration,
+    ) -> Result<()> {
+        if image.dimensions() != (self.config.width, self.config.height) {
+            bail!(
+                "GIF frame must be {}x{} but got {:?}",
+                self.config.width,
+                self.config.height,
+                image.dimensions()
+            );
+        }
+        let (width, height) = (self.config.width as u16, self.config.height as u16);
+        let mut pixels = image.as_raw().clone();
+        for pixel in pixels.chunks_mut(BYTES_PER_PIXEL as usize) {
+            pixel[3] = 0xff;
+        }
+        let mut frame = if self.config.dither {
+            let quant = color_quant::NeuQuant::new(self.config.speed, 256, &pixels);
+            let indices = floyd_steinberg(&pixels, width as usize, |rgb| {
+                let index = quant.index_of(&[rgb[0], rgb[1], rgb[2], 0xff]);
+                let color = quant.lookup(index).unwrap_or([0, 0, 0, 0xff]);
+                (index as u8, [color[0], color[1], color[2]])
+            });
+            gif::Frame::from_palette_pixels(width, height, &indices, &quant.color_map_rgb(), None)
+        } else {
+            gif::Frame::from_rgba_speed(width, height, &mut pixels, self.config.speed)
+        };
+        frame.delay = self.centiseconds(delay);
+        self.encoder.write_frame(&frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// writes the trailer and returns the writer. dropping the writer also ends the file
+    pub fn finish(self) -> Result<W> {
+        let mut writer = self.encoder.into_inner()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn centiseconds(&mut self, delay: Duration) -> u16 {
+        let exact = delay.as_secs_f64() * 100.0 + self.delay_error;
+        let centis = exact.round().max(0.0).min(u16::MAX as f64);
+        self.delay_error = exact - centis;
+        centis as u16
+    }
+}
+
+/// palette indices of `rgba` with the quantization error diffused to the neighbours.
+/// `nearest` returns the palette index and the color of an RGB value
+pub fn floyd_steinberg(
+    rgba: &[u8],
+    width: usize,
+    nearest: impl Fn([u8; 3]) -> (u8, [u8; 3]),
+) -> Vec<u8> {
+    let stride = BYTES_PER_PIXEL as usize;
+    let height = rgba.len() / stride / width.max(1);
+    let mut error = vec![[0.0f32; 3]; width * height];
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let mut rgb = [0u8; 3];
+            for c in 0..3 {
+                rgb[c] = (rgba[i * stride + c] as f32 + error[i][c])
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+            let (index, color) = nearest(rgb);
+            indices.push(index);
+
+            let mut diffuse = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx < 0 || nx >= width as isize || y + dy >= height {
+                    return;
+                }
+                let n = (y + dy) * width + nx as usize;
+                for c in 0..3 {
+                    error[n][c] += (rgb[c] as f32 - color[c] as f32) * weight;
+                }
+            };
+            diffuse(1, 0, 7.0 / 16.0);
+            diffuse(-1, 1, 3.0 / 16.0);
+            diffuse(0, 1, 5.0 / 16.0);
+            diffuse(1, 1, 1.0 / 16.0);
+        }
+    }
+    indices
+}
+
+/// `frame_00042.png` in `dir`
+pub fn sequence_path(dir: &Path, frame: u32) -> PathBuf {
+    dir.join(format!("frame_{:05}.png", frame))
+}
+
+/// ffmpeg reading raw RGBA frames from stdin. see `VideoWriterBuilder::command`
+pub const FFMPEG_ARGS: &[&str] = &[
+    "-y",
+    "-loglevel",
+    "error",
+    "-f",
+    "rawvideo",
+    "-pix_fmt",
+    "rgba",
+    "-s",
+    "{width}x{height}",
+    "-r",
+    "{fps}",
+    "-i",
+    "-",
+    "-pix_fmt",
+    "yuv420p",
+    "{output}",
+];
+
+/// `VideoWriter` options
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoWriterBuilder {
+    width: u32,
+    height: u32,
+    fps: f64,
+    output: PathBuf,
+    program: String,
+    args: Vec<String>,
+    fallback: Option<PathBuf>,
+    first_frame: u32,
+}
+
+impl VideoWriterBuilder {
+    /// ffmpeg at 30 fps by default
+    pub fn new(width: u32, height: u32, output: impl Into<PathBuf>) -> Self {
+        Self {
+            width,
+            height,
+            fps: 30.0,
+            output: output.into(),
+            program: "ffmpeg".to_string(),
+            args: FFMPEG_ARGS.iter().map(|arg| arg.to_string()).collect(),
+            fallback: None,
+            first_frame: 0,
+        }
+    }
+
+    pub fn fps(mut self, fps: f64) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// the encoder reads the frames from stdin, tightly packed RGBA8 in sRGB. `{width}`,
+    /// `{height}`, `{fps}` and `{output}` in `args` are replaced
+    pub fn command<S: AsRef<str>>(mut self, program: &str, args: &[S]) -> Self {
+        self.program = program.to_string();
+        self.args = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+        self
+    }
+
+    /// writes `sequence_path`s to `dir` instead if the encoder can't be started
+    pub fn fallback(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.fallback = Some(dir.into());
+        self
+    }
+
+    /// number of the first frame in the image sequence. 0 by default
+    pub fn first_frame(mut self, frame: u32) -> Self {
+        self.first_frame = frame;
+        self
+    }
+
+    /// `args` with the placeholders replaced
+    pub fn expanded_args(&self) -> Vec<String> {
+        let output = self.output.to_string_lossy();
+        self.args
+            .iter()
+            .map(|arg| {
+                arg.replace("{width}", &self.width.to_string())
+                    .replace("{height}", &self.height.to_string())
+                    .replace("{fps}", &self.fps.to_string())
+                    .replace("{output}", &output)
+            })
+            .collect()
+    }
+
+    pub fn build(self) -> Result<VideoWriter> {
+        if self.width == 0 || self.height == 0 {
+            bail!("Empty video size {}x{}", self.width, self.height);
+        }
+        if let Some(dir) = self.output.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let spawned = Command::new(&self.program)
+            .args(self.expanded_args())
+            .stdin(Stdio::piped())
+            .spawn();
+        let sink = match (spawned, &self.fallback) {
+            (Err(err), Some(dir)) => {
+                std::fs::create_dir_all(dir)?;
+                VideoSink::Sequence {
+                    dir: dir.clone(),
+                    reason: format!(
+                        "{} is not available ({}), writing an image sequence to {}",
+                        self.program,
+                        err,
+                        dir.display()
+                    ),
+                }
+            }
+            (Err(err), None) => {
+                return Err(err).with_context(|| format!("Failed to start {}", self.program))
+            }
+            (spawned, _) => {
+                let mut child = spawned?;
+                let stdin = child.stdin.take().context("Encoder has no stdin")?;
+                VideoSink::Encoder {
+                    child,
+                    stdin: BufWriter::new(stdin),
+                }
+            }
+        };
+        Ok(VideoWriter {
+            sink,
+            width: self.width,
+            height: self.height,
+            frame: self.first_frame,
+            program: self.program,
+        })
+    }
+}
+
+enum VideoSink {
+    Encoder {
+        child: Child,
+        stdin: BufWriter<ChildStdin>,
+    },
+    /// with why the encoder isn't used
+    Sequence { dir: PathBuf, reason: String },
+}
+
+/// streams frames to an encoder process, or to numbered images if it isn't installed
+pub struct VideoWriter {
+    sink: VideoSink,
+    width: u32,
+    height: u32,
+    /// number of the next frame
+    frame: u32,
+    program: String,
+}
+
+impl VideoWriter {
+    pub fn write_frame(&mut self, image: &image::RgbaImage) -> Result<()> {
+        self.write_frame_with_text(image, &[])
+    }
+
+    /// `text` is embedded as tEXt chunks into the images of the fallback sequence, the encoder
+    /// only gets the pixels
+    pub fn write_frame_with_text(
+        &mut self,
+        image: &image::RgbaImage,
+        text: &[(String, String)],
+    ) -> Result<()> {
+        if image.dimensions() != (self.width, self.height) {
+            bail!(
+                "Video frame must be {}x{} but got {:?}",
+                self.width,
+                self.height,
+                image.dimensions()
+            );
+        }
+        match &mut self.sink {
+            VideoSink::Encoder { stdin, .. } => stdin
+                .write_all(image.as_raw())
+                .with_context(|| format!("{} stopped reading frames", self.program))?,
+            VideoSink::Sequence { dir, .. } if !text.is_empty() => {
+                manifest::save_png(image, &sequence_path(dir, self.frame), text)?
+            }
+            VideoSink::Sequence { dir, .. } => {
+                let path = sequence_path(dir, self.frame);
+                image
+                    .save(&path)
+                    .with_context(|| format!("Failed to save {}", path.display()))?
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// whether the frames go to an image sequence
+    pub fn is_fallback(&self) -> bool {
+        self.fallback_reason().is_some()
+    }
+
+    /// why the frames go to an image sequence, for the caller to report
+    pub fn fallback_reason(&self) -> Option<&str> {
+        match &self.sink {
+            VideoSink::Encoder { .. } => None,
+            VideoSink::Sequence { reason, .. } => Some(reason),
+        }
+    }
+
+    /// closes stdin and waits for the encoder to finish the file
+    pub fn finish(self) -> Result<()> {
+        if let VideoSink::Encoder {
+            mut child,
+            mut stdin,
+        } = self.sink
+        {
+            stdin.flush()?;
+            drop(stdin);
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("{} failed with {}", self.program, status);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_capture_unpad_rows() {
+    assert_eq!(padded_bytes_per_row(1), 256);
+    assert_eq!(padded_bytes_per_row(64), 256);
+    assert_eq!(padded_bytes_per_row(65), 512);
+
+    // 2x2 pixels with rows padded to 12 bytes
+    let data = (0..24u8).collect::<Vec<_>>();
+    assert_eq!(
+        unpad_rows(&data, 2, 2, 12),
+        vec![0, 1, 2, 3, 4, 5, 6, 7, 12, 13, 14, 15, 16, 17, 18, 19]
+    );
+    // the last row may not be padded
+    assert_eq!(unpad_rows(&data[..20], 2, 2, 12).len(), 16);
+}
+
+#[test]
+fn test_capture_gif_writer() {
+    let (width, height) = (300, 2);
+    let frame = image::RgbaImage::from_fn(width, height, |x, _| {
+        let v = (x * 255 / (width - 1)) as u8;
+        image::Rgba([v, v, 255 - v, 255])
+    });
+
+    for &dither in &[false, true] {
+        let mut writer = GifWriterBuilder::new(width, height)
+            .fps(30.0)
+            .repeat(Some(2))
+            .dither(dither)
+            .build(Vec::new())
+            .unwrap();
+        for _ in 0..3 {
+            writer.write_frame(&frame).unwrap();
+        }
+        assert_eq!(writer.frames(), 3);
+        assert!(writer
+            .write_frame(&image::RgbaImage::new(width, 1))
+            .is_err());
+        let data = writer.finish().unwrap();
+        assert!(data.starts_with(b"GIF89a"));
+        assert_eq!(&data[6..10], &[44, 1, 2, 0]);
+    }
+    assert!(GifWriterBuilder::new(width, height)
+        .quality(0)
+        .build(Vec::new())
+        .is_err());
+    assert!(GifWriterBuilder::new(70000, 1).build(Vec::new()).is_err());
+
+    // 30 fps doesn't drift: 3 seconds are 300 centiseconds
+    let mut writer = GifWriterBuilder::new(1, 1).build(Vec::new()).unwrap();
+    let delay = Duration::from_secs_f64(1.0 / 30.0);
+    let delays = (0..90)
+        .map(|_| writer.centiseconds(delay))
+        .collect::<Vec<_>>();
+    assert_eq!(delays.iter().map(|&d| d as u32).sum::<u32>(), 300);
+    assert!(delays.iter().all(|&d| d == 3 || d == 4));
+
+    // two colors dithered to the average of a gray
+    let gray = vec![128u8; 16 * 16 * 4];
+    let indices = floyd_steinberg(&gray, 16, |rgb| {
+        if rgb[0] < 128 {
+            (0, [0; 3])
+        } else {
+            (1, [255; 3])
+        }
+    });
+    let white = indices.iter().filter(|&&i| i == 1).count();
+    assert!((white as i32 - 128).abs() <= 4, "{}", white);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_capture_video_writer() {
+    let dir = crate::test_util::TempDir::new("capture_video");
+    let frame = image::RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+
+    // a stand-in encoder which copies stdin to the output
+    let output = dir.join("video.raw");
+    let builder = VideoWriterBuilder::new(2, 2, &output)
+        .fps(24.0)
+        .command("sh", &["-c", "cat > {output}"]);
+    assert_eq!(
+        VideoWriterBuilder::new(2, 2, "out.mp4").expanded_args()[8..11],
+        ["2x2", "-r", "30"]
+    );
+    let mut writer = builder.build().unwrap();
+    assert!(!writer.is_fallback());
+    writer.write_frame(&frame).unwrap();
+    writer.write_frame(&frame).unwrap();
+    assert!(writer.write_frame(&image::RgbaImage::new(3, 2)).is_err());
+    writer.finish().unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), frame.as_raw().repeat(2));
+
+    // a failing encoder is reported on finish
+    let writer = VideoWriterBuilder::new(2, 2, &output)
+        .command("sh", &["-c", "exit 3"])
+        .build()
+        .unwrap();
+    assert!(writer.finish().is_err());
+
+    // no encoder
+    let missing = VideoWriterBuilder::new(2, 2, &output).command("no-such-encoder", &["-"]);
+    assert!(missing.clone().build().is_err());
+    let sequence = dir.join("sequence");
+    let mut writer = missing.fallback(&sequence).first_frame(10).build().unwrap();
+    assert!(writer.is_fallback());
+    assert!(writer
+        .fallback_reason()
+        .unwrap()
+        .contains("no-such-encoder"));
+    let text = vec![("Frame".to_string(), "11".to_string())];
+    writer.write_frame(&frame).unwrap();
+    writer.write_frame_with_text(&frame, &text).unwrap();
+    writer.finish().unwrap();
+    let saved = image::open(sequence_path(&sequence, 11))
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(saved, frame);
+    let png = std::fs::read(sequence_path(&sequence, 11)).unwrap();
+    assert_eq!(manifest::read_png_text(&png).unwrap(), text);
+}
+
+#[test]
+fn test_capture_workers() {
+    let dir = crate::test_util::TempDir::new("capture_workers");
+    let (width, height) = (3, 2);
+    let bgra = (0..width * height)
+        .flat_map(|i| vec![i as u8, 100, 200, 255])
+        .collect::<Vec<_>>();
+
+    let mut workers = CaptureWorkers::new(2, 1);
+    for frame in 0..5 {
+        workers
+            .submit(EncodeJob {
+                data: bgra.clone(),
+                width,
+                height,
+                bgra: true,
+                path: sequence_path(&dir, frame),
+                text: match frame {
+                    4 => vec![("Frame".to_string(), "4".to_string())],
+                    _ => Vec::new(),
+                },
+            })
+            .unwrap();
+    }
+    workers.receive(true).unwrap();
+    assert_eq!((workers.saved, workers.encoding), (5, 0));
+    let saved = image::open(sequence_path(&dir, 4)).unwrap().to_rgba8();
+    assert_eq!(saved.get_pixel(2, 1), &image::Rgba([200, 100, 5, 255]));
+    let png = std::fs::read(sequence_path(&dir, 4)).unwrap();
+    let text = manifest::read_png_text(&png).unwrap();
+    assert_eq!(text, vec![("Frame".to_string(), "4".to_string())]);
+
+    // failures are reported once every job is done
+    workers
+        .submit(EncodeJob {
+            data: bgra.clone(),
+            width,
+            height,
+            bgra: false,
+            path: dir.join("frame.unknown"),
+            text: Vec::new(),
+        })
+        .unwrap();
+    workers
+        .submit(EncodeJob {
+            data: bgra,
+            width,
+            height,
+            bgra: false,
+            path: dir.join("frame.jpg"),
+            text: Vec::new(),
+        })
+        .unwrap();
+    assert!(workers.receive(true).is_err());
+    assert_eq!((workers.saved, workers.encoding), (6, 0));
+
+    drop(workers);
+}
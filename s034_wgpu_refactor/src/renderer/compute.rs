@@ -0,0 +1,222 @@
+//! generic compute dispatch
+//!
+//! a `ComputePass` is a compute shader and the `Binding`s it's dispatched with (set 0, 1, ...).
+//! workgroup counts are derived from the number of elements and the `local_size` declared in the
+//! shader, which is read from the SPIR-V, so the shader should return early for the invocations
+//! past the element count
+use anyhow::*;
+use nannou::prelude::*;
+use std::sync::Arc;
+
+use super::binding::{Binding, BindingType};
+use super::pipeline::{ComputePipelineDesc, PipelineCache};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const OP_EXECUTION_MODE: u32 = 16;
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+/// `local_size_x/y/z` of the first entry point which declares it
+pub fn local_size(spirv: &[u8]) -> Option<[u32; 3]> {
+    if spirv.len() % 4 != 0 || spirv.len() < 20 {
+        return None;
+    }
+    let words = spirv
+        .chunks(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect::<Vec<_>>();
+    if words[0] != SPIRV_MAGIC {
+        return None;
+    }
+    // 5 words of header, then instructions of (word count << 16 | opcode) and operands
+    let mut i = 5;
+    while i < words.len() {
+        let count = (words[i] >> 16) as usize;
+        let opcode = words[i] & 0xffff;
+        if count == 0 || i + count > words.len() {
+            return None;
+        }
+        // OpExecutionMode %entry LocalSize x y z
+        if opcode == OP_EXECUTION_MODE && count == 6 && words[i + 2] == EXECUTION_MODE_LOCAL_SIZE {
+            return Some([words[i + 3], words[i + 4], words[i + 5]]);
+        }
+        i += count;
+    }
+    None
+}
+
+/// enough workgroups of `local_size` to cover `elements`
+pub fn workgroup_count(elements: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    let mut count = [0; 3];
+    for i in 0..3 {
+        let size = local_size[i].max(1);
+        count[i] = (elements[i] + size - 1) / size;
+    }
+    count
+}
+
+pub struct ComputePass {
+    local_size: [u32; 3],
+    bindings: Vec<Binding>,
+    pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl ComputePass {
+    /// `layout` names the bind group layouts of `bindings` in the pipeline cache
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        layout: &'static str,
+        spirv: &'static [u8],
+        bindings: Vec<Binding>,
+    ) -> Result<Self> {
+        let local_size =
+            local_size(spirv).with_context(|| format!("{} has no compute local size", layout))?;
+        let layouts = bindings
+            .iter()
+            .map(Binding::bind_group_layout)
+            .collect::<Vec<_>>();
+        let pipeline = pipeline_cache.compute_pipeline(
+            device,
+            &ComputePipelineDesc::new(layout, spirv),
+            &layouts,
+        );
+        Ok(Self {
+            local_size,
+            bindings,
+            pipeline,
+        })
+    }
+
+    pub fn local_size(&self) -> [u32; 3] {
+        self.local_size
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// e.g. to write uniforms between dispatches
+    pub fn bindings_mut(&mut self) -> &mut [Binding] {
+        &mut self.bindings
+    }
+
+    pub fn workgroups(&self, elements: [u32; 3]) -> [u32; 3] {
+        workgroup_count(elements, self.local_size)
+    }
+
+    /// records `iterations` dispatches over `elements`. each one is a separate compute pass, so
+    /// every dispatch sees the writes of the previous one
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, elements: [u32; 3], iterations: u32) {
+        let [x, y, z] = self.workgroups(elements);
+        for _ in 0..iterations {
+            let mut pass = encoder.begin_compute_pass();
+            pass.set_pipeline(&self.pipeline);
+            for (i, binding) in self.bindings.iter().enumerate() {
+                pass.set_bind_group(i as u32, binding.bind_group(), &[]);
+            }
+            pass.dispatch(x, y, z);
+        }
+    }
+
+    /// submits `encode` and waits for it
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        elements: [u32; 3],
+        iterations: u32,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("compute"),
+        });
+        self.encode(&mut encoder, elements, iterations);
+        queue.submit(std::iter::once(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+    }
+
+    /// `read_buffer` of the whole buffer `label` of `bindings()[binding]`
+    pub async fn read<T: bytemuck::Pod>(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        binding: usize,
+        label: &str,
+    ) -> Result<Vec<T>> {
+        let binding = self
+            .bindings
+            .get(binding)
+            .with_context(|| format!("No binding {}", binding))?;
+        let index = *binding
+            .label_index
+            .get(label)
+            .with_context(|| format!("No buffer {}", label))?;
+        let size = buffer_size(&binding.bindings[index])
+            .with_context(|| format!("{} is not a buffer", label))?;
+        let count = size as usize / std::mem::size_of::<T>();
+        read_buffer(device, queue, &binding.buffers[index], count).await
+    }
+}
+
+/// size of the contents the buffer was created with
+fn buffer_size(binding: &BindingType) -> Option<u64> {
+    match binding {
+        BindingType::Uniform { contents, .. } | BindingType::Storage { contents, .. } => {
+            Some(contents.len() as u64)
+        }
+        BindingType::SharedUniformBuffer { binding, .. }
+        | BindingType::SharedStorageBuffer { binding, .. } => buffer_size(binding),
+        _ => None,
+    }
+}
+
+/// copies the first `count` elements of `buffer` to a mappable buffer and reads them back.
+/// `buffer` must be `COPY_SRC`
+pub async fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    count: usize,
+) -> Result<Vec<T>> {
+    let size = (count * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    // buffers are padded to the copy alignment on creation
+    let align = wgpu::COPY_BUFFER_ALIGNMENT;
+    let size = (size + align - 1) / align * align;
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("readback"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    mapping.await?;
+    let mut data = bytemuck::cast_slice::<u8, T>(&slice.get_mapped_range()).to_vec();
+    data.truncate(count);
+    staging.unmap();
+    Ok(data)
+}
+
+#[test]
+fn test_compute_local_size() {
+    assert_eq!(
+        local_size(include_bytes!("../../shaders/model_load.comp.spv")),
+        Some([64, 1, 1])
+    );
+    assert_eq!(local_size(&[]), None);
+    assert_eq!(local_size(&[0; 24]), None);
+
+    assert_eq!(workgroup_count([1, 1, 1], [64, 1, 1]), [1, 1, 1]);
+    assert_eq!(workgroup_count([64, 1, 1], [64, 1, 1]), [1, 1, 1]);
+    assert_eq!(workgroup_count([65, 1, 1], [64, 1, 1]), [2, 1, 1]);
+    assert_eq!(workgroup_count([100, 30, 0], [8, 8, 1]), [13, 4, 0]);
+}
@@ -0,0 +1,297 @@
+//! contact sheets and thumbnails of captured sequences
+//!
+//! works on the CPU with frames which are already on disk, e.g. the `frame_00042.png` sequence of
+//! an offline render. frame numbers are drawn with a built-in 3x5 pixel font, so no font file is
+//! needed
+use anyhow::*;
+use image::imageops::{self, FilterType};
+use std::path::{Path, PathBuf};
+
+pub const DIGIT_WIDTH: u32 = 3;
+pub const DIGIT_HEIGHT: u32 = 5;
+
+/// rows of each digit from the top, the highest of the 3 bits is the left pixel
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// frames of the `frame_00042.png` files in `dir`, sorted by frame. other files are ignored
+pub fn list_sequence(dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read the sequence in {}", dir.display()))?;
+    let mut frames = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let frame = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("frame_"))
+            .and_then(|name| name.strip_suffix(".png"))
+            .and_then(|number| number.parse().ok());
+        if let Some(frame) = frame {
+            frames.push((frame, path));
+        }
+    }
+    frames.sort();
+    Ok(frames)
+}
+
+/// up to `count` indices of `len` items spread evenly from the first to the last
+pub fn sample_indices(len: usize, count: usize) -> Vec<usize> {
+    match (len, count) {
+        (0, _) | (_, 0) => Vec::new(),
+        (len, count) if count >= len => (0..len).collect(),
+        (_, 1) => vec![0],
+        (len, count) => (0..count)
+            .map(|i| (i * (len - 1) + (count - 1) / 2) / (count - 1))
+            .collect(),
+    }
+}
+
+/// size of `number` drawn by `draw_number`
+pub fn number_size(number: u32, scale: u32) -> (u32, u32) {
+    let digits = number.to_string().len() as u32;
+    (
+        (digits * (DIGIT_WIDTH + 1) - 1) * scale,
+        DIGIT_HEIGHT * scale,
+    )
+}
+
+/// with the top left at `x`, `y`. pixels outside of `image` are skipped
+pub fn draw_number(
+    image: &mut image::RgbaImage,
+    x: u32,
+    y: u32,
+    number: u32,
+    scale: u32,
+    color: image::Rgba<u8>,
+) {
+    for (i, digit) in number.to_string().bytes().enumerate() {
+        let bitmap = &DIGITS[(digit - b'0') as usize];
+        let left = x + i as u32 * (DIGIT_WIDTH + 1) * scale;
+        for (row, bits) in bitmap.iter().enumerate() {
+            for column in 0..DIGIT_WIDTH {
+                if bits & (1 << (DIGIT_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = left + column * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `width` pixels wide with the aspect ratio of `image`
+pub fn fit_width(image: &image::RgbaImage, width: u32) -> image::RgbaImage {
+    let height = scaled_height(image.dimensions(), width);
+    imageops::resize(image, width, height, FilterType::Triangle)
+}
+
+/// height of an image of `size` scaled to `width`, at least 1
+pub fn scaled_height(size: (u32, u32), width: u32) -> u32 {
+    let (w, h) = size;
+    ((h as u64 * width as u64 + w as u64 / 2) / w.max(1) as u64).max(1) as u32
+}
+
+/// `width` x `height`, scaled to cover it and cropped around the center
+pub fn thumbnail(image: &image::RgbaImage, width: u32, height: u32) -> image::RgbaImage {
+    let (w, h) = image.dimensions();
+    let scale = (width as f64 / w as f64).max(height as f64 / h as f64);
+    let scaled_width = ((w as f64 * scale).round() as u32).max(width);
+    let scaled_height = ((h as f64 * scale).round() as u32).max(height);
+    let scaled = imageops::resize(image, scaled_width, scaled_height, FilterType::Lanczos3);
+    let x = (scaled_width - width) / 2;
+    let y = (scaled_height - height) / 2;
+    imageops::crop_imm(&scaled, x, y, width, height).to_image()
+}
+
+/// grid of frames, each labelled with its number in the bottom left
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ContactSheetBuilder {
+    columns: u32,
+    cell_width: u32,
+    spacing: u32,
+    label_scale: u32,
+    background: image::Rgba<u8>,
+}
+
+impl Default for ContactSheetBuilder {
+    fn default() -> Self {
+        Self {
+            columns: 4,
+            cell_width: 240,
+            spacing: 4,
+            label_scale: 2,
+            background: image::Rgba([24, 24, 24, 255]),
+        }
+    }
+}
+
+impl ContactSheetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn columns(mut self, columns: u32) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// the cell height follows the aspect ratio of the first frame
+    pub fn cell_width(mut self, cell_width: u32) -> Self {
+        self.cell_width = cell_width;
+        self
+    }
+
+    /// between the cells and around them
+    pub fn spacing(mut self, spacing: u32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// pixels per pixel of the label font. no labels with 0
+    pub fn label_scale(mut self, label_scale: u32) -> Self {
+        self.label_scale = label_scale;
+        self
+    }
+
+    pub fn background(mut self, background: image::Rgba<u8>) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// `frames` are `(frame number, image)` in the order of the cells
+    pub fn build(&self, frames: &[(u32, image::RgbaImage)]) -> Result<image::RgbaImage> {
+        if self.columns == 0 || self.cell_width == 0 {
+            bail!("Contact sheet needs at least one column of cells");
+        }
+        let first = match frames.first() {
+            Some((_, image)) => image,
+            None => bail!("Contact sheet needs at least one frame"),
+        };
+        let cell_height = scaled_height(first.dimensions(), self.cell_width);
+        let columns = self.columns.min(frames.len() as u32);
+        let rows = (frames.len() as u32 - 1) / columns + 1;
+        let mut sheet = image::RgbaImage::from_pixel(
+            columns * (self.cell_width + self.spacing) + self.spacing,
+            rows * (cell_height + self.spacing) + self.spacing,
+            self.background,
+        );
+
+        for (i, (number, frame)) in frames.iter().enumerate() {
+            let x = self.spacing + (i as u32 % columns) * (self.cell_width + self.spacing);
+            let y = self.spacing + (i as u32 / columns) * (cell_height + self.spacing);
+            let cell = imageops::resize(frame, self.cell_width, cell_height, FilterType::Triangle);
+            imageops::replace(&mut sheet, &cell, x, y);
+            if self.label_scale > 0 {
+                self.draw_label(&mut sheet, x, y + cell_height, *number);
+            }
+        }
+        Ok(sheet)
+    }
+
+    /// white on a black box in the corner above `bottom`
+    fn draw_label(&self, sheet: &mut image::RgbaImage, x: u32, bottom: u32, number: u32) {
+        let scale = self.label_scale;
+        let (width, height) = number_size(number, scale);
+        let (box_width, box_height) = (width + 2 * scale, height + 2 * scale);
+        let top = bottom.saturating_sub(box_height);
+        let backing =
+            image::RgbaImage::from_pixel(box_width, box_height, image::Rgba([0, 0, 0, 255]));
+        imageops::replace(sheet, &backing, x, top);
+        let white = image::Rgba([255, 255, 255, 255]);
+        draw_number(sheet, x + scale, top + scale, number, scale, white);
+    }
+}
+
+#[test]
+fn test_contact_sheet() {
+    assert_eq!(sample_indices(10, 4), vec![0, 3, 6, 9]);
+    assert_eq!(sample_indices(3, 8), vec![0, 1, 2]);
+    assert_eq!(sample_indices(5, 1), vec![0]);
+    assert!(sample_indices(0, 4).is_empty());
+    assert_eq!(scaled_height((1920, 1080), 240), 135);
+    assert_eq!(number_size(42, 2), (14, 10));
+
+    let mut digits = image::RgbaImage::new(7, 5);
+    let white = image::Rgba([255, 255, 255, 255]);
+    draw_number(&mut digits, 0, 0, 10, 1, white);
+    // the stem of the 1 and the hole of the 0
+    assert_eq!(digits.get_pixel(1, 4), &white);
+    assert_eq!(digits.get_pixel(0, 4), &white);
+    assert_eq!(digits.get_pixel(5, 2)[3], 0);
+    assert_eq!(digits.get_pixel(3, 0)[3], 0);
+
+    let frames = (0..5)
+        .map(|i| {
+            let color = image::Rgba([i as u8 * 50, 100, 200, 255]);
+            (i * 10, image::RgbaImage::from_pixel(64, 36, color))
+        })
+        .collect::<Vec<_>>();
+    let builder = ContactSheetBuilder::new()
+        .columns(3)
+        .cell_width(32)
+        .spacing(2)
+        .label_scale(1);
+    let sheet = builder.build(&frames).unwrap();
+    assert_eq!(sheet.dimensions(), (3 * 34 + 2, 2 * 20 + 2));
+    // the top right of the fifth cell, and the empty sixth cell
+    assert_eq!(
+        sheet.get_pixel(34 + 2 + 31, 22),
+        &image::Rgba([200, 100, 200, 255])
+    );
+    assert_eq!(
+        sheet.get_pixel(2 * 34 + 10, 30),
+        &image::Rgba([24, 24, 24, 255])
+    );
+    // the label box of the first cell
+    assert_eq!(sheet.get_pixel(2, 2 + 17), &image::Rgba([0, 0, 0, 255]));
+    assert!(ContactSheetBuilder::new().build(&[]).is_err());
+
+    let wide = image::RgbaImage::from_fn(200, 100, |x, _| {
+        let red = if (40..160).contains(&x) { 0 } else { 255 };
+        image::Rgba([red, 0, 0, 255])
+    });
+    let thumb = thumbnail(&wide, 50, 50);
+    assert_eq!(thumb.dimensions(), (50, 50));
+    // cropped to the center, without the red sides
+    assert!(thumb.pixels().all(|p| p[0] < 16));
+    assert_eq!(fit_width(&wide, 50).dimensions(), (50, 25));
+
+    let dir = crate::test_util::TempDir::new("contact_sheet");
+    for name in &[
+        "frame_00012.png",
+        "frame_00003.png",
+        "manifest.json",
+        "frame_x.png",
+    ] {
+        std::fs::write(dir.join(name), b"").unwrap();
+    }
+    let sequence = list_sequence(&dir).unwrap();
+    assert_eq!(
+        sequence,
+        vec![
+            (3, dir.join("frame_00003.png")),
+            (12, dir.join("frame_00012.png"))
+        ]
+    );
+    let path = dir.to_path_buf();
+    drop(dir);
+    assert!(list_sequence(&path).is_err());
+}
@@ -0,0 +1,266 @@
+//! GPU frustum culling of instances into indirect draw arguments
+//!
+//! `shaders/cull.comp` tests the bounding sphere of every instance against the camera frustum,
+//! compacts the visible model matrices into a buffer which replaces the instances bind group, and
+//! counts them into one `DrawIndexedIndirect` per mesh. the draw calls then don't depend on the
+//! CPU knowing how many instances are visible
+use anyhow::*;
+use nannou::math::cgmath::{Matrix4, Vector3, Vector4};
+use nannou::prelude::*;
+
+use super::binding::BindingBuilder;
+use super::compute::ComputePass;
+use super::geom::Geom;
+use super::instance::Instances;
+use super::pipeline::PipelineCache;
+
+/// arguments of `draw_indexed_indirect`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DrawIndexedIndirect {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+unsafe impl bytemuck::Zeroable for DrawIndexedIndirect {}
+unsafe impl bytemuck::Pod for DrawIndexedIndirect {}
+
+impl DrawIndexedIndirect {
+    pub const SIZE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
+
+    pub fn new(index_count: u32, instance_count: u32) -> Self {
+        Self {
+            index_count,
+            instance_count,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        }
+    }
+}
+
+/// planes point inwards: (n, d) with dot(n, p) + d >= 0 inside
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// from a view projection matrix with the wgpu depth range of [0, 1]
+    pub fn from_matrix(m: Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let mut planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2];
+        for plane in planes.iter_mut() {
+            *plane /= plane.truncate().magnitude();
+        }
+        Self { planes }
+    }
+
+    /// same as `shaders/cull.comp`
+    pub fn intersects_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+/// center of the bounding box and the distance to the farthest vertex
+pub fn bounding_sphere(positions: impl Iterator<Item = Vector3<f32>>) -> (Vector3<f32>, f32) {
+    let positions = positions.collect::<Vec<_>>();
+    if positions.is_empty() {
+        return (Vector3::zero(), 0.0);
+    }
+    let mut min = positions[0];
+    let mut max = positions[0];
+    for p in &positions {
+        for i in 0..3 {
+            min[i] = min[i].min(p[i]);
+            max[i] = max[i].max(p[i]);
+        }
+    }
+    let center = (min + max) * 0.5;
+    let radius = positions
+        .iter()
+        .map(|p| (p - center).magnitude())
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+/// must be same as `CullParams` in `shaders/cull.comp`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CullRaw {
+    pub planes: [Vector4<f32>; 6],
+    /// xyz: center, w: radius. in model space
+    pub sphere: Vector4<f32>,
+    /// instances, meshes
+    pub counts: [u32; 4],
+}
+unsafe impl bytemuck::Zeroable for CullRaw {}
+unsafe impl bytemuck::Pod for CullRaw {}
+
+/// culls the instances of a whole `Geom` with one bounding sphere, so that every mesh draws the
+/// same visible instances. the instances are copied on creation
+pub struct InstanceCulling {
+    raw: CullRaw,
+    /// instance count 0, reset before every culling
+    initial_args: Vec<DrawIndexedIndirect>,
+    compute: ComputePass,
+    /// the visible instances with the layout of `Instances::binding`
+    bind_group: wgpu::BindGroup,
+}
+
+impl InstanceCulling {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        geom: &Geom,
+        instances: &Instances,
+    ) -> Result<Self> {
+        let (center, radius) = bounding_sphere(
+            geom.meshes
+                .iter()
+                .flat_map(|mesh| mesh.vertices.iter().map(|v| v.position())),
+        );
+        let raw = CullRaw {
+            planes: [Vector4::zero(); 6],
+            sphere: center.extend(radius),
+            counts: [
+                instances.instances.len() as u32,
+                geom.meshes.len() as u32,
+                0,
+                0,
+            ],
+        };
+        let initial_args = geom
+            .meshes
+            .iter()
+            .map(|mesh| DrawIndexedIndirect::new(mesh.indices.len() as u32, 0))
+            .collect::<Vec<_>>();
+
+        let models = instances
+            .instances
+            .iter()
+            .map(|instance| instance.to_raw())
+            .collect::<Vec<_>>();
+        let binding = BindingBuilder::new()
+            .storage_buffer(
+                "instances",
+                &models,
+                wgpu::ShaderStage::COMPUTE,
+                false,
+                true,
+            )
+            .storage_buffer_custom(
+                "visible_instances",
+                &models,
+                wgpu::BufferUsage::STORAGE,
+                wgpu::ShaderStage::COMPUTE,
+                false,
+                false,
+            )
+            .storage_buffer_custom(
+                "draw_args",
+                &initial_args,
+                wgpu::BufferUsage::STORAGE
+                    | wgpu::BufferUsage::INDIRECT
+                    | wgpu::BufferUsage::COPY_DST
+                    | wgpu::BufferUsage::COPY_SRC,
+                wgpu::ShaderStage::COMPUTE,
+                false,
+                false,
+            )
+            .uniform_buffer("cull_params", &[raw], wgpu::ShaderStage::COMPUTE, false)
+            .build(device);
+
+        // same layout as the instances, so it can be bound to the same pipelines
+        let bind_group = wgpu::BindGroupBuilder::new()
+            .binding(wgpu::BindingResource::Buffer(
+                binding.buffers[binding.label_index["visible_instances"]].slice(..),
+            ))
+            .build(device, instances.binding.bind_group_layout());
+
+        let compute = ComputePass::new(
+            device,
+            pipeline_cache,
+            "cull",
+            include_bytes!("../../shaders/cull.comp.spv"),
+            vec![binding],
+        )?;
+
+        Ok(Self {
+            raw,
+            initial_args,
+            compute,
+            bind_group,
+        })
+    }
+
+    /// frustum of the next `encode`
+    pub fn update(&mut self, queue: &wgpu::Queue, view_projection: Matrix4<f32>) {
+        self.raw.planes = Frustum::from_matrix(view_projection).planes;
+        let binding = &mut self.compute.bindings_mut()[0];
+        binding.write_buffer_at_label(queue, "cull_params", 0, &[self.raw]);
+        binding.write_buffer_at_label(queue, "draw_args", 0, &self.initial_args);
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.compute.encode(encoder, [self.raw.counts[0], 1, 1], 1);
+    }
+
+    /// replaces the bind group of `Instances::binding`, e.g. as `BindGroupKind::Instances`
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// `DrawIndexedIndirect` of `geom.meshes[i]` is at `i * DrawIndexedIndirect::SIZE`
+    pub fn draw_args(&self) -> &wgpu::Buffer {
+        let binding = &self.compute.bindings()[0];
+        &binding.buffers[binding.label_index["draw_args"]]
+    }
+}
+
+#[test]
+fn test_frustum_culling() {
+    use super::camera::Projection;
+    use nannou::math::cgmath::{Deg, Point3};
+
+    let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+    let view = Matrix4::look_at(
+        Point3::new(0.0, 0.0, 10.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vector3::unit_y(),
+    );
+    let frustum = Frustum::from_matrix(projection.projection_matrix() * view);
+    assert!(frustum
+        .planes
+        .iter()
+        .all(|p| (p.truncate().magnitude() - 1.0).abs() < 1e-5));
+
+    assert!(frustum.intersects_sphere(Vector3::zero(), 1.0));
+    // behind the camera and beyond the far plane
+    assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, 12.0), 1.0));
+    assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, -95.0), 1.0));
+    // far to the side, unless the sphere is large enough to reach in
+    assert!(!frustum.intersects_sphere(Vector3::new(30.0, 0.0, 0.0), 1.0));
+    assert!(frustum.intersects_sphere(Vector3::new(30.0, 0.0, 0.0), 30.0));
+    // just outside the near plane
+    assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, 9.95), 0.01));
+
+    let (center, radius) = bounding_sphere(
+        vec![
+            Vector3::new(-1.0, -1.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(1.0, -1.0, 0.0),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(center, Vector3::zero());
+    assert!((radius - 3.0f32.sqrt()).abs() < 1e-6);
+    assert_eq!(bounding_sphere(std::iter::empty()).1, 0.0);
+
+    assert_eq!(DrawIndexedIndirect::SIZE, 20);
+}
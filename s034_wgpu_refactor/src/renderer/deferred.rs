@@ -0,0 +1,331 @@
+//! deferred shading
+//!
+//! the G-buffer pass writes the surface attributes of every pixel, and the lighting pass shades
+//! each pixel once with every light of `LightSet`, so the cost of the lights doesn't depend on
+//! the depth complexity of the scene. MSAA and blending aren't supported by this path
+use nannou::math::cgmath::{Vector2, Vector3};
+use nannou::prelude::*;
+use std::sync::Arc;
+
+use super::camera::Camera;
+use super::graph::{GraphResources, TextureDesc, TextureSize};
+use super::pipeline::{PipelineCache, PipelineDesc};
+use super::shadow::ShadowMaps;
+
+pub const ALBEDO: &str = "gbuffer_albedo";
+pub const NORMAL: &str = "gbuffer_normal";
+pub const MATERIAL: &str = "gbuffer_material";
+pub const DEPTH: &str = "gbuffer_depth";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+}
+
+impl Default for RenderPath {
+    fn default() -> Self {
+        RenderPath::Forward
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GBufferPrecision {
+    /// 8 bit albedo and material, 16 bit octahedral normal
+    Low,
+    /// 16 bit albedo and material, 32 bit octahedral normal
+    High,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GBufferFormats {
+    /// rgb: albedo, a: 1 where geometry is drawn
+    pub albedo: wgpu::TextureFormat,
+    /// xy: octahedral encoded view space normal
+    pub normal: wgpu::TextureFormat,
+    /// x: specular strength, y: shininess / 256, z: ambient strength
+    pub material: wgpu::TextureFormat,
+    /// sampled to reconstruct the view space position
+    pub depth: wgpu::TextureFormat,
+}
+
+impl GBufferFormats {
+    pub fn select(precision: GBufferPrecision) -> Self {
+        match precision {
+            GBufferPrecision::Low => Self {
+                albedo: wgpu::TextureFormat::Rgba8UnormSrgb,
+                normal: wgpu::TextureFormat::Rg16Float,
+                material: wgpu::TextureFormat::Rgba8Unorm,
+                depth: wgpu::TextureFormat::Depth32Float,
+            },
+            GBufferPrecision::High => Self {
+                albedo: wgpu::TextureFormat::Rgba16Float,
+                normal: wgpu::TextureFormat::Rg32Float,
+                material: wgpu::TextureFormat::Rgba16Float,
+                depth: wgpu::TextureFormat::Depth32Float,
+            },
+        }
+    }
+
+    /// color attachments in the order of the outputs of `shaders/gbuffer.frag`
+    pub fn colors(&self) -> [wgpu::TextureFormat; 3] {
+        [self.albedo, self.normal, self.material]
+    }
+
+    /// `None` if one of the formats is block compressed
+    pub fn bytes_per_pixel(&self) -> Option<u32> {
+        self.colors()
+            .iter()
+            .chain(std::iter::once(&self.depth))
+            .map(|&format| format_bytes(format))
+            .sum()
+    }
+
+    /// graph textures written by the G-buffer pass
+    pub fn descs(&self) -> Vec<(&'static str, TextureDesc)> {
+        let usage = wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED;
+        let desc = |format| TextureDesc::new(format, TextureSize::Surface, usage);
+        vec![
+            (ALBEDO, desc(self.albedo)),
+            (NORMAL, desc(self.normal)),
+            (MATERIAL, desc(self.material)),
+            (DEPTH, desc(self.depth)),
+        ]
+    }
+}
+
+/// `None` for block compressed formats, which can't be rendered to
+fn format_bytes(format: wgpu::TextureFormat) -> Option<u32> {
+    use wgpu::TextureFormat::*;
+    match format {
+        R8Unorm | R8Snorm | R8Uint | R8Sint => Some(1),
+        R16Uint | R16Sint | R16Float | Rg8Unorm | Rg8Snorm | Rg8Uint | Rg8Sint => Some(2),
+        R32Uint | R32Sint | R32Float | Rg16Uint | Rg16Sint | Rg16Float => Some(4),
+        Rgba8Unorm | Rgba8UnormSrgb | Rgba8Snorm | Rgba8Uint | Rgba8Sint => Some(4),
+        Bgra8Unorm | Bgra8UnormSrgb | Rgb10a2Unorm | Rg11b10Float => Some(4),
+        // the 24 bit depth formats are stored in 32 bits by the backends
+        Depth32Float | Depth24Plus | Depth24PlusStencil8 => Some(4),
+        Rg32Uint | Rg32Sint | Rg32Float | Rgba16Uint | Rgba16Sint | Rgba16Float => Some(8),
+        Rgba32Uint | Rgba32Sint | Rgba32Float => Some(16),
+        Bc1RgbaUnorm | Bc1RgbaUnormSrgb | Bc2RgbaUnorm | Bc2RgbaUnormSrgb | Bc3RgbaUnorm
+        | Bc3RgbaUnormSrgb | Bc4RUnorm | Bc4RSnorm | Bc5RgUnorm | Bc5RgSnorm | Bc6hRgbUfloat
+        | Bc6hRgbSfloat | Bc7RgbaUnorm | Bc7RgbaUnormSrgb => None,
+    }
+}
+
+/// octahedral mapping of a unit vector to [-1, 1]^2. must be same as `shaders/gbuffer.frag`
+pub fn encode_normal(n: Vector3<f32>) -> Vector2<f32> {
+    let n = n / (n.x.abs() + n.y.abs() + n.z.abs());
+    if n.z >= 0.0 {
+        Vector2::new(n.x, n.y)
+    } else {
+        // fold the lower hemisphere over the diagonals
+        Vector2::new(
+            (1.0 - n.y.abs()) * sign_not_zero(n.x),
+            (1.0 - n.x.abs()) * sign_not_zero(n.y),
+        )
+    }
+}
+
+/// must be same as `shaders/deferred_lighting.frag`
+pub fn decode_normal(e: Vector2<f32>) -> Vector3<f32> {
+    let mut n = Vector3::new(e.x, e.y, 1.0 - e.x.abs() - e.y.abs());
+    let t = (-n.z).max(0.0);
+    n.x -= t * sign_not_zero(n.x);
+    n.y -= t * sign_not_zero(n.y);
+    n.normalize()
+}
+
+fn sign_not_zero(x: f32) -> f32 {
+    if x >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// what the lighting pass writes. must be same as `shaders/deferred_lighting.frag`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GBufferChannel {
+    Lit = 0,
+    Albedo = 1,
+    Normal = 2,
+    Material = 3,
+    /// linear view distance divided by the far plane
+    Depth = 4,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct DeferredRaw {
+    pub channel: f32,
+    pub zfar: f32,
+    pub _padding: [f32; 2],
+}
+unsafe impl bytemuck::Zeroable for DeferredRaw {}
+unsafe impl bytemuck::Pod for DeferredRaw {}
+
+/// fullscreen pass which shades the G-buffer
+///
+/// bind groups: 0 camera, 1 G-buffer, 2 lights and shadows (same as the forward path)
+pub struct DeferredLighting {
+    raw: DeferredRaw,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// rebuilt whenever the graph recreates the G-buffer
+    bind_group: Option<wgpu::BindGroup>,
+    pipeline: Arc<wgpu::RenderPipeline>,
+}
+
+impl DeferredLighting {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        camera: &Camera,
+        shadow_maps: &ShadowMaps,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let raw = DeferredRaw {
+            channel: GBufferChannel::Lit as u32 as f32,
+            zfar: camera.projection.zfar,
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("deferred"),
+            contents: bytemuck::cast_slice(&[raw]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        // the G-buffer is read with texelFetch
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("gbuffer"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture = |builder: wgpu::BindGroupLayoutBuilder| {
+            builder.sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureComponentType::Float,
+            )
+        };
+        let builder =
+            wgpu::BindGroupLayoutBuilder::new().uniform_buffer(wgpu::ShaderStage::FRAGMENT, false);
+        let bind_group_layout = texture(texture(texture(texture(builder))))
+            .sampler(wgpu::ShaderStage::FRAGMENT)
+            .build(device);
+
+        let pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new(
+                "deferred_lighting",
+                include_bytes!("../../shaders/fullscreen.vert.spv"),
+            )
+            .fragment_shader(include_bytes!("../../shaders/deferred_lighting.frag.spv"))
+            .color_format(output_format),
+            &[
+                camera.binding.bind_group_layout(),
+                &bind_group_layout,
+                shadow_maps.bind_group_layout(),
+            ],
+        );
+
+        Self {
+            raw,
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            bind_group: None,
+            pipeline,
+        }
+    }
+
+    pub fn set_channel(&mut self, queue: &wgpu::Queue, channel: GBufferChannel) {
+        self.raw.channel = channel as u32 as f32;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.raw]));
+    }
+
+    pub fn channel(&self) -> GBufferChannel {
+        match self.raw.channel as u32 {
+            1 => GBufferChannel::Albedo,
+            2 => GBufferChannel::Normal,
+            3 => GBufferChannel::Material,
+            4 => GBufferChannel::Depth,
+            _ => GBufferChannel::Lit,
+        }
+    }
+
+    pub fn bind(&mut self, device: &wgpu::Device, resources: &GraphResources) {
+        self.bind_group = Some(
+            wgpu::BindGroupBuilder::new()
+                .binding(wgpu::BindingResource::Buffer(self.uniform_buffer.slice(..)))
+                .texture_view(resources.view(ALBEDO))
+                .texture_view(resources.view(NORMAL))
+                .texture_view(resources.view(MATERIAL))
+                .texture_view(resources.view(DEPTH))
+                .sampler(&self.sampler)
+                .build(device, &self.bind_group_layout),
+        );
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureViewHandle,
+        camera_bind_group: &wgpu::BindGroup,
+        shadow_bind_group: &wgpu::BindGroup,
+    ) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) => bind_group,
+            None => return,
+        };
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(target, |color| {
+                color.load_op(wgpu::LoadOp::Clear(wgpu::Color::BLACK))
+            })
+            .begin(encoder);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, bind_group, &[]);
+        render_pass.set_bind_group(2, shadow_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[test]
+fn test_gbuffer_normal_packing() {
+    use super::environment::{f16_to_f32, f32_to_f16};
+
+    let low = GBufferFormats::select(GBufferPrecision::Low);
+    let high = GBufferFormats::select(GBufferPrecision::High);
+    assert_eq!(low.bytes_per_pixel(), Some(16));
+    assert_eq!(high.bytes_per_pixel(), Some(28));
+    assert_eq!(low.colors()[1], wgpu::TextureFormat::Rg16Float);
+    assert_eq!(low.descs().len(), 4);
+    assert!(low
+        .descs()
+        .iter()
+        .all(|(_, desc)| desc.usage.contains(wgpu::TextureUsage::SAMPLED)));
+
+    let normals = [
+        Vector3::unit_x(),
+        -Vector3::unit_y(),
+        Vector3::unit_z(),
+        -Vector3::unit_z(),
+        Vector3::new(1.0, 2.0, -3.0).normalize(),
+        Vector3::new(-0.3, 0.1, 0.9).normalize(),
+        Vector3::new(-0.5, -0.5, -0.01).normalize(),
+    ];
+    for &n in &normals {
+        let e = encode_normal(n);
+        assert!(e.x.abs() <= 1.0 && e.y.abs() <= 1.0, "{:?}", e);
+        assert!((decode_normal(e) - n).magnitude() < 1e-5, "{:?}", n);
+
+        // Rg16Float keeps the error well under a degree
+        let quantized = e.map(|x| f16_to_f32(f32_to_f16(x)));
+        assert!(decode_normal(quantized).dot(n) > 0.9999, "{:?}", n);
+    }
+}
@@ -0,0 +1,786 @@
+//! image based lighting from equirectangular environment maps
+//!
+//! the environment is converted to cube maps and prefiltered on the CPU into
+//! - irradiance: cosine weighted convolution for the diffuse term
+//! - specular: GGX prefiltered mips (mip level = roughness * (mips - 1)) for the split sum
+//! - BRDF LUT: scale and bias to F0 indexed by (n_dot_v, roughness)
+//!
+//! `Environment` is bound as set 0 together with the camera uniform
+use anyhow::*;
+use nannou::math::cgmath::{Vector2, Vector3};
+use nannou::prelude::*;
+use rayon::prelude::*;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use super::camera::Camera;
+
+/// linear radiance in an equirectangular (latitude-longitude) layout. the top row is +y
+pub struct EquirectImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vector3<f32>>,
+}
+
+impl EquirectImage {
+    /// `.hdr` is read as linear radiance, other formats are decoded from sRGB
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .context("File has no extension")?
+            .to_str()
+            .context("Extension cannot be converted to &str")?;
+        match extension {
+            "hdr" => {
+                let decoder =
+                    image::codecs::hdr::HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let meta = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()?
+                    .into_iter()
+                    .map(|p| Vector3::new(p[0], p[1], p[2]))
+                    .collect();
+                Ok(Self {
+                    width: meta.width,
+                    height: meta.height,
+                    pixels,
+                })
+            }
+            _ => {
+                let rgb = image::open(path)?.to_rgb8();
+                let to_linear = |c: u8| srgb_to_linear(c as f32 / 255.0);
+                let pixels = rgb
+                    .pixels()
+                    .map(|p| Vector3::new(to_linear(p[0]), to_linear(p[1]), to_linear(p[2])))
+                    .collect();
+                Ok(Self {
+                    width: rgb.width(),
+                    height: rgb.height(),
+                    pixels,
+                })
+            }
+        }
+    }
+
+    /// synthetic environment from radiance per direction
+    pub fn from_fn<F: Fn(Vector3<f32>) -> Vector3<f32>>(width: u32, height: u32, f: F) -> Self {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let uv = Vector2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                f(equirect_to_direction(uv))
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// bilinear, wraps horizontally
+    pub fn sample(&self, dir: Vector3<f32>) -> Vector3<f32> {
+        let uv = direction_to_equirect(dir);
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (uv.y * self.height as f32 - 0.5)
+            .max(0.0)
+            .min(self.height as f32 - 1.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let w = self.width as i32;
+        let texel = |x: i32, y: i32| {
+            let x = x.rem_euclid(w) as u32;
+            let y = (y as u32).min(self.height - 1);
+            self.pixels[(y * self.width + x) as usize]
+        };
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = texel(x0, y0).lerp(texel(x0 + 1, y0), fx);
+        let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn direction_to_equirect(dir: Vector3<f32>) -> Vector2<f32> {
+    let dir = dir.normalize();
+    Vector2::new(
+        0.5 + dir.z.atan2(dir.x) / (2.0 * PI),
+        dir.y.max(-1.0).min(1.0).acos() / PI,
+    )
+}
+
+pub fn equirect_to_direction(uv: Vector2<f32>) -> Vector3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    Vector3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+/// six square faces in +x, -x, +y, -y, +z, -z order with the same orientation as GPU cube maps
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeMap {
+    pub size: u32,
+    pub faces: Vec<Vec<Vector3<f32>>>,
+}
+
+impl CubeMap {
+    pub fn from_fn<F: Fn(Vector3<f32>) -> Vector3<f32> + Sync>(size: u32, f: F) -> Self {
+        let faces = (0..6)
+            .into_par_iter()
+            .map(|face| {
+                (0..size * size)
+                    .map(|i| f(Self::texel_direction(size, face, i % size, i / size)))
+                    .collect()
+            })
+            .collect();
+        Self { size, faces }
+    }
+
+    pub fn from_equirect(image: &EquirectImage, size: u32) -> Self {
+        Self::from_fn(size, |dir| image.sample(dir))
+    }
+
+    /// direction through `(s, t)` in -1 ~ 1 on `face`
+    pub fn face_direction(face: usize, s: f32, t: f32) -> Vector3<f32> {
+        match face {
+            0 => Vector3::new(1.0, -t, -s),
+            1 => Vector3::new(-1.0, -t, s),
+            2 => Vector3::new(s, 1.0, t),
+            3 => Vector3::new(s, -1.0, -t),
+            4 => Vector3::new(s, -t, 1.0),
+            5 => Vector3::new(-s, -t, -1.0),
+            _ => panic!("cube map has only 6 faces"),
+        }
+        .normalize()
+    }
+
+    pub fn texel_direction(size: u32, face: usize, x: u32, y: u32) -> Vector3<f32> {
+        let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+        Self::face_direction(face, s, t)
+    }
+
+    /// face and `(s, t)` in -1 ~ 1 which `dir` hits
+    pub fn direction_to_face(dir: Vector3<f32>) -> (usize, f32, f32) {
+        let a = Vector3::new(dir.x.abs(), dir.y.abs(), dir.z.abs());
+        let (face, sc, tc, ma) = if a.x >= a.y && a.x >= a.z {
+            if dir.x > 0.0 {
+                (0, -dir.z, -dir.y, a.x)
+            } else {
+                (1, dir.z, -dir.y, a.x)
+            }
+        } else if a.y >= a.z {
+            if dir.y > 0.0 {
+                (2, dir.x, dir.z, a.y)
+            } else {
+                (3, dir.x, -dir.z, a.y)
+            }
+        } else if dir.z > 0.0 {
+            (4, dir.x, -dir.y, a.z)
+        } else {
+            (5, -dir.x, -dir.y, a.z)
+        };
+        (face, sc / ma, tc / ma)
+    }
+
+    /// bilinear within the face, clamped at the edges
+    pub fn sample(&self, dir: Vector3<f32>) -> Vector3<f32> {
+        let (face, s, t) = Self::direction_to_face(dir);
+        let size = self.size as f32;
+        let x = ((s + 1.0) * 0.5 * size - 0.5).max(0.0).min(size - 1.0);
+        let y = ((t + 1.0) * 0.5 * size - 0.5).max(0.0).min(size - 1.0);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+        let texels = &self.faces[face];
+        let texel = |x: u32, y: u32| texels[(y * self.size + x) as usize];
+        let top = texel(x0, y0).lerp(texel(x1, y0), fx);
+        let bottom = texel(x0, y1).lerp(texel(x1, y1), fx);
+        top.lerp(bottom, fy)
+    }
+
+    /// solid angle of a texel
+    pub fn texel_solid_angle(size: u32, x: u32, y: u32) -> f32 {
+        let area = |s: f32, t: f32| (s * t).atan2((s * s + t * t + 1.0).sqrt());
+        let s0 = 2.0 * x as f32 / size as f32 - 1.0;
+        let t0 = 2.0 * y as f32 / size as f32 - 1.0;
+        let s1 = 2.0 * (x + 1) as f32 / size as f32 - 1.0;
+        let t1 = 2.0 * (y + 1) as f32 / size as f32 - 1.0;
+        area(s0, t0) - area(s0, t1) - area(s1, t0) + area(s1, t1)
+    }
+
+    /// 2x2 box filter
+    pub fn downsample(&self) -> Self {
+        let size = (self.size / 2).max(1);
+        let faces = self
+            .faces
+            .iter()
+            .map(|texels| {
+                (0..size * size)
+                    .map(|i| {
+                        let (x, y) = (i % size * 2, i / size * 2);
+                        let texel = |x: u32, y: u32| {
+                            texels
+                                [(y.min(self.size - 1) * self.size + x.min(self.size - 1)) as usize]
+                        };
+                        (texel(x, y) + texel(x + 1, y) + texel(x, y + 1) + texel(x + 1, y + 1))
+                            / 4.0
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { size, faces }
+    }
+
+    /// cosine weighted convolution divided by PI, so that diffuse = albedo * irradiance
+    pub fn irradiance(&self, size: u32) -> Self {
+        // brute force over every source texel, so integrate a small version of the source
+        let mut source = self.clone();
+        while source.size > 32 {
+            source = source.downsample();
+        }
+        let n = source.size;
+        let samples = (0..6)
+            .flat_map(|face| (0..n * n).map(move |i| (face, i % n, i / n)))
+            .map(|(face, x, y)| {
+                (
+                    Self::texel_direction(n, face, x, y),
+                    source.faces[face][(y * n + x) as usize] * Self::texel_solid_angle(n, x, y),
+                )
+            })
+            .collect::<Vec<_>>();
+        Self::from_fn(size, |n| {
+            samples
+                .iter()
+                .map(|(l, radiance)| radiance * n.dot(*l).max(0.0))
+                .fold(Vector3::zero(), |sum, r| sum + r)
+                / PI
+        })
+    }
+
+    /// GGX prefiltered mips assuming n = v = r. mip `i` has roughness `i / (mip_count - 1)`
+    pub fn prefilter_specular(&self, size: u32, mip_count: u32, sample_count: u32) -> Vec<Self> {
+        (0..mip_count)
+            .map(|mip| {
+                let size = (size >> mip).max(1);
+                let roughness = if mip_count > 1 {
+                    mip as f32 / (mip_count - 1) as f32
+                } else {
+                    0.0
+                };
+                if roughness == 0.0 {
+                    return Self::from_fn(size, |dir| self.sample(dir));
+                }
+                Self::from_fn(size, |n| {
+                    let mut sum = Vector3::zero();
+                    let mut weight = 0.0;
+                    for i in 0..sample_count {
+                        let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+                        let l = h * 2.0 * n.dot(h) - n;
+                        let n_dot_l = n.dot(l);
+                        if n_dot_l > 0.0 {
+                            sum += self.sample(l) * n_dot_l;
+                            weight += n_dot_l;
+                        }
+                    }
+                    sum / weight.max(1e-4)
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn hammersley(i: u32, count: u32) -> Vector2<f32> {
+    Vector2::new(
+        i as f32 / count as f32,
+        i.reverse_bits() as f32 * 2.328_306_4e-10,
+    )
+}
+
+/// half vector around `n` distributed by GGX
+pub fn importance_sample_ggx(xi: Vector2<f32>, n: Vector3<f32>, roughness: f32) -> Vector3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let h = Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+    let up = if n.z.abs() < 0.999 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_x()
+    };
+    let tangent = up.cross(n).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent * h.x + bitangent * h.y + n * h.z).normalize()
+}
+
+/// split sum scale (x) and bias (y) to F0. row y is roughness, column x is n_dot_v
+pub struct BrdfLut {
+    pub size: u32,
+    pub texels: Vec<Vector2<f32>>,
+}
+
+impl BrdfLut {
+    pub fn new(size: u32, sample_count: u32) -> Self {
+        let texels = (0..size * size)
+            .into_par_iter()
+            .map(|i| {
+                let n_dot_v = ((i % size) as f32 + 0.5) / size as f32;
+                let roughness = ((i / size) as f32 + 0.5) / size as f32;
+                Self::integrate(n_dot_v, roughness, sample_count)
+            })
+            .collect();
+        Self { size, texels }
+    }
+
+    pub fn integrate(n_dot_v: f32, roughness: f32, sample_count: u32) -> Vector2<f32> {
+        let n = Vector3::unit_z();
+        let v = Vector3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+        let mut scale_bias = Vector2::zero();
+        for i in 0..sample_count {
+            let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+            let l = h * 2.0 * v.dot(h) - v;
+            let n_dot_l = l.z.max(0.0);
+            let n_dot_h = h.z.max(0.0);
+            let v_dot_h = v.dot(h).max(0.0);
+            if n_dot_l > 0.0 {
+                let g = geometry_smith_ibl(n_dot_v, n_dot_l, roughness);
+                let g_vis = g * v_dot_h / (n_dot_h * n_dot_v).max(1e-4);
+                let fc = (1.0 - v_dot_h).powi(5);
+                scale_bias += Vector2::new((1.0 - fc) * g_vis, fc * g_vis);
+            }
+        }
+        scale_bias / sample_count as f32
+    }
+}
+
+/// Schlick-GGX with k for image based lighting
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let g = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+    g(n_dot_v) * g(n_dot_l)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EnvironmentConfig {
+    pub skybox_size: u32,
+    pub irradiance_size: u32,
+    pub specular_size: u32,
+    pub specular_mips: u32,
+    pub specular_samples: u32,
+    pub brdf_lut_size: u32,
+    pub brdf_lut_samples: u32,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            skybox_size: 512,
+            irradiance_size: 32,
+            specular_size: 128,
+            specular_mips: 6,
+            specular_samples: 64,
+            brdf_lut_size: 64,
+            brdf_lut_samples: 256,
+        }
+    }
+}
+
+/// prefiltered environment on the CPU, ready to upload
+pub struct EnvironmentMaps {
+    pub skybox: CubeMap,
+    pub irradiance: CubeMap,
+    pub specular: Vec<CubeMap>,
+    pub brdf_lut: BrdfLut,
+}
+
+impl EnvironmentMaps {
+    pub fn from_equirect(image: &EquirectImage, config: &EnvironmentConfig) -> Self {
+        let skybox = CubeMap::from_equirect(image, config.skybox_size);
+        let mut source = skybox.clone();
+        while source.size > config.specular_size {
+            source = source.downsample();
+        }
+        Self {
+            irradiance: source.irradiance(config.irradiance_size),
+            specular: source.prefilter_specular(
+                config.specular_size,
+                config.specular_mips,
+                config.specular_samples,
+            ),
+            skybox,
+            brdf_lut: BrdfLut::new(config.brdf_lut_size, config.brdf_lut_samples),
+        }
+    }
+
+    /// uniform radiance from every direction
+    pub fn constant(radiance: Vector3<f32>) -> Self {
+        let cube = CubeMap::from_fn(1, |_| radiance);
+        Self {
+            skybox: cube.clone(),
+            irradiance: cube.clone(),
+            specular: vec![cube],
+            brdf_lut: BrdfLut::new(32, 64),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct EnvironmentRaw {
+    pub intensity: f32,
+    pub specular_mips: f32,
+    pub _padding: [f32; 2],
+}
+unsafe impl bytemuck::Zeroable for EnvironmentRaw {}
+unsafe impl bytemuck::Pod for EnvironmentRaw {}
+
+/// camera uniform (binding 0) and image based lighting maps
+pub struct Environment {
+    pub raw: EnvironmentRaw,
+    uniform_buffer: wgpu::Buffer,
+    _textures: Vec<wgpu::Texture>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Environment {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        maps: &EnvironmentMaps,
+    ) -> Self {
+        let raw = EnvironmentRaw {
+            intensity: 1.0,
+            specular_mips: maps.specular.len() as f32,
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("environment"),
+            contents: bytemuck::cast_slice(&[raw]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let skybox = Self::create_cube_texture(device, queue, std::slice::from_ref(&maps.skybox));
+        let irradiance =
+            Self::create_cube_texture(device, queue, std::slice::from_ref(&maps.irradiance));
+        let specular = Self::create_cube_texture(device, queue, &maps.specular);
+        let brdf_lut = Self::create_brdf_lut_texture(device, queue, &maps.brdf_lut);
+        let cube_view = |texture: &wgpu::Texture| {
+            texture
+                .view()
+                .dimension(wgpu::TextureViewDimension::Cube)
+                .build()
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // cube views can't be inferred from the texture, so the layout is built by hand
+        let stage = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+        let cube = |builder: wgpu::BindGroupLayoutBuilder| {
+            builder.sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::Cube,
+                wgpu::TextureComponentType::Float,
+            )
+        };
+        let builder = wgpu::BindGroupLayoutBuilder::new()
+            .uniform_buffer(stage, false)
+            .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false);
+        let bind_group_layout = cube(cube(cube(builder)))
+            .sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureComponentType::Float,
+            )
+            .sampler(wgpu::ShaderStage::FRAGMENT)
+            .build(device);
+
+        let camera_buffer = &camera.binding.buffers[camera.binding.label_index["camera_view_proj"]];
+        let bind_group = wgpu::BindGroupBuilder::new()
+            .binding(wgpu::BindingResource::Buffer(camera_buffer.slice(..)))
+            .binding(wgpu::BindingResource::Buffer(uniform_buffer.slice(..)))
+            .texture_view(&cube_view(&skybox))
+            .texture_view(&cube_view(&irradiance))
+            .texture_view(&cube_view(&specular))
+            .texture_view(&brdf_lut.view().build())
+            .sampler(&sampler)
+            .build(device, &bind_group_layout);
+
+        Self {
+            raw,
+            uniform_buffer,
+            _textures: vec![skybox, irradiance, specular, brdf_lut],
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        path: P,
+        config: &EnvironmentConfig,
+    ) -> Result<Self> {
+        let image = EquirectImage::load(path)?;
+        let maps = EnvironmentMaps::from_equirect(&image, config);
+        Ok(Self::new(device, queue, camera, &maps))
+    }
+
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.raw.intensity = intensity;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.raw]));
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// `mips[i]` is uploaded to mip level `i`
+    fn create_cube_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mips: &[CubeMap],
+    ) -> wgpu::Texture {
+        let size = mips[0].size;
+        let texture = wgpu::TextureBuilder::new()
+            .extent(wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 6,
+            })
+            .mip_level_count(mips.len() as u32)
+            .sample_count(1)
+            .dimension(wgpu::TextureDimension::D2)
+            .format(Self::FORMAT)
+            .usage(wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)
+            .build(device);
+        for (mip_level, cube) in mips.iter().enumerate() {
+            for (face, texels) in cube.faces.iter().enumerate() {
+                let data = texels
+                    .iter()
+                    .flat_map(|c| vec![c.x, c.y, c.z, 1.0])
+                    .map(f32_to_f16)
+                    .collect::<Vec<u16>>();
+                Self::write_layer(
+                    queue,
+                    &texture,
+                    mip_level as u32,
+                    face as u32,
+                    cube.size,
+                    &data,
+                );
+            }
+        }
+        texture
+    }
+
+    fn create_brdf_lut_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lut: &BrdfLut,
+    ) -> wgpu::Texture {
+        let texture = wgpu::TextureBuilder::new()
+            .extent(wgpu::Extent3d {
+                width: lut.size,
+                height: lut.size,
+                depth: 1,
+            })
+            .mip_level_count(1)
+            .sample_count(1)
+            .dimension(wgpu::TextureDimension::D2)
+            .format(Self::FORMAT)
+            .usage(wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)
+            .build(device);
+        let data = lut
+            .texels
+            .iter()
+            .flat_map(|t| vec![t.x, t.y, 0.0, 1.0])
+            .map(f32_to_f16)
+            .collect::<Vec<u16>>();
+        Self::write_layer(queue, &texture, 0, 0, lut.size, &data);
+        texture
+    }
+
+    fn write_layer(
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        mip_level: u32,
+        layer: u32,
+        size: u32,
+        data: &[u16],
+    ) {
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+            },
+            bytemuck::cast_slice(data),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 8 * size, // rgba16
+                rows_per_image: size,
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+        );
+    }
+}
+
+/// IEEE 754 half precision bits, truncating the mantissa
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // inf or nan
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            sign
+        } else {
+            // subnormal
+            sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16
+        }
+    } else {
+        sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+/// inverse of `f32_to_f16`
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+pub trait DrawSkybox<'a, 'b>
+where
+    'b: 'a,
+{
+    fn draw_skybox(&mut self, environment: &'b Environment);
+}
+
+impl<'a, 'b> DrawSkybox<'a, 'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    /// fullscreen triangle at the far plane. draw after opaque geometry
+    fn draw_skybox(&mut self, environment: &'b Environment) {
+        self.set_bind_group(0, environment.bind_group(), &[]);
+        self.draw(0..3, 0..1);
+    }
+}
+
+#[test]
+fn test_environment_prefilter() {
+    // every term of a uniform environment is the environment itself
+    let radiance = Vector3::new(0.5, 1.0, 2.0);
+    let image = EquirectImage::from_fn(64, 32, |_| radiance);
+    let cube = CubeMap::from_equirect(&image, 16);
+    let close = |a: Vector3<f32>, b: Vector3<f32>, eps: f32| (a - b).magnitude() < eps;
+    let irradiance = cube.irradiance(4);
+    let specular = cube.prefilter_specular(8, 4, 32);
+    for dir in [
+        Vector3::unit_x(),
+        -Vector3::unit_y(),
+        Vector3::new(1.0, 1.0, -1.0),
+    ]
+    .iter()
+    {
+        assert!(close(cube.sample(*dir), radiance, 1e-4));
+        assert!(
+            close(irradiance.sample(*dir), radiance, 0.02),
+            "{:?}",
+            irradiance.sample(*dir)
+        );
+        for mip in specular.iter() {
+            assert!(close(mip.sample(*dir), radiance, 1e-3));
+        }
+    }
+    let solid_angle = (0..16 * 16)
+        .map(|i| CubeMap::texel_solid_angle(16, i % 16, i / 16))
+        .sum::<f32>()
+        * 6.0;
+    assert!((solid_angle - 4.0 * PI).abs() < 1e-3);
+
+    // sky above, ground below: the up facing irradiance is the sky's
+    let sky = Vector3::new(1.0, 1.0, 1.0);
+    let image = EquirectImage::from_fn(
+        64,
+        32,
+        |dir| if dir.y > 0.0 { sky } else { Vector3::zero() },
+    );
+    let irradiance = CubeMap::from_equirect(&image, 16).irradiance(4);
+    assert!(irradiance.sample(Vector3::unit_y()).x > 0.95);
+    assert!(irradiance.sample(-Vector3::unit_y()).x < 0.05);
+    assert!((irradiance.sample(Vector3::unit_x()).x - 0.5).abs() < 0.05);
+
+    // the split sum never reflects more than F0 = 1
+    let lut = BrdfLut::new(8, 128);
+    assert!(lut
+        .texels
+        .iter()
+        .all(|t| t.x >= 0.0 && t.y >= 0.0 && t.x + t.y <= 1.01));
+
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(1e6), 0x7c00);
+    assert_eq!(f16_to_f32(0x3c00), 1.0);
+    assert_eq!(f16_to_f32(f32_to_f16(-0.375)), -0.375);
+}
@@ -6,11 +6,10 @@ use image::GenericImageView;
 use nannou::math::cgmath;
 use nannou::prelude::*;
 use rayon::prelude::*;
-use std::ops::Range;
 use std::path::Path;
 
 use super::binding::{self, Binding, BindingBuilder, BindingType};
-use super::material::Material;
+use super::material::{Material, PbrFactors, PbrMaps, Shading};
 use super::mesh::Mesh;
 use super::texture::TextureSet;
 use super::vertex::{Vertex, VertexDescription};
@@ -32,6 +31,10 @@ impl Geom {
         let materials = obj_materials
             .par_iter()
             .map(|mat| {
+                if Self::is_pbr(mat) {
+                    return Self::load_pbr_material(device, queue, containing_folder, mat);
+                }
+
                 // We can also parallelize loading the textures!
                 let mut textures = [
                     containing_folder.join(&mat.diffuse_texture),
@@ -80,6 +83,7 @@ impl Geom {
 
                 Ok(Material {
                     name: mat.name.to_owned(),
+                    shading: Shading::BlinnPhong,
                     binding,
                 })
             })
@@ -141,311 +145,70 @@ impl Geom {
 
         Ok(Geom { meshes, materials })
     }
-}
-
-pub trait DrawGeom<'a, 'b>
-where
-    'b: 'a,
-{
-    fn draw_mesh(&mut self, mesh: &'b Mesh, uniforms: &'b wgpu::BindGroup);
-    fn draw_mesh_with_light(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_mesh_with_light_and_material(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-    );
-
-    fn draw_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-    fn draw_mesh_instanced_with_light(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-    fn draw_mesh_instanced_with_light_and_material(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-
-    fn draw_geom(&mut self, geom: &'b Geom, uniforms: &'b wgpu::BindGroup);
-    fn draw_geom_with_light(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_geom_with_light_and_inner_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_geom_with_light_and_other_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-    );
-
-    fn draw_geom_instanced(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-    fn draw_geom_instanced_with_light(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-    fn draw_geom_instanced_with_light_and_inner_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-    fn draw_geom_instanced_with_light_and_other_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    );
-}
-
-impl<'a, 'b> DrawGeom<'a, 'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh(&mut self, mesh: &'b Mesh, uniforms: &'b wgpu::BindGroup) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
-    }
-
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh_with_light(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.set_bind_group(2, &light, &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
-    }
-
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh_with_light_and_material(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.set_bind_group(2, &light, &[]);
-        self.set_bind_group(3, &material.binding.bind_group(), &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
-    }
-
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.set_bind_group(1, &u_instances, &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, instances);
-    }
-
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh_instanced_with_light(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.set_bind_group(1, &u_instances, &[]);
-        self.set_bind_group(2, &light, &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, instances);
-    }
-
-    // TODO: mesh.binding.buffers[0][1].....
-    fn draw_mesh_instanced_with_light_and_material(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, &uniforms, &[]);
-        self.set_bind_group(1, &u_instances, &[]);
-        self.set_bind_group(2, &light, &[]);
-        self.set_bind_group(3, material.binding.bind_group(), &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, instances);
-    }
-
-    fn draw_geom(&mut self, geom: &'b Geom, uniforms: &'b wgpu::BindGroup) {
-        for mesh in &geom.meshes {
-            self.draw_mesh(mesh, uniforms);
-        }
-    }
-
-    fn draw_geom_with_light(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &geom.meshes {
-            self.draw_mesh_with_light(mesh, uniforms, light);
-        }
-    }
-
-    fn draw_geom_with_light_and_inner_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &geom.meshes {
-            let material = &geom.materials[mesh.material_id];
-            self.draw_mesh_with_light_and_material(mesh, uniforms, light, material);
-        }
-    }
-
-    fn draw_geom_with_light_and_other_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-    ) {
-        for mesh in &geom.meshes {
-            self.draw_mesh_with_light_and_material(mesh, uniforms, light, material);
-        }
-    }
-
-    fn draw_geom_instanced(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        for mesh in &geom.meshes {
-            self.draw_mesh_instanced(mesh, uniforms, u_instances, instances.clone());
-        }
-    }
-
-    fn draw_geom_instanced_with_light(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        for mesh in &geom.meshes {
-            self.draw_mesh_instanced_with_light(
-                mesh,
-                uniforms,
-                light,
-                u_instances,
-                instances.clone(),
-            );
-        }
-    }
 
-    fn draw_geom_instanced_with_light_and_inner_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        for mesh in &geom.meshes {
-            let material = &geom.materials[mesh.material_id];
-            self.draw_mesh_instanced_with_light_and_material(
-                mesh,
-                uniforms,
-                light,
-                material,
-                u_instances,
-                instances.clone(),
-            );
-        }
+    /// MTL files with the PBR extension (`Pr`, `Pm`, `map_Pr`, `map_Pm`) are loaded as PBR
+    fn is_pbr(mat: &tobj::Material) -> bool {
+        ["Pr", "Pm", "map_Pr", "map_Pm"]
+            .iter()
+            .any(|key| mat.unknown_param.contains_key(*key))
     }
 
-    fn draw_geom_instanced_with_light_and_other_material(
-        &mut self,
-        geom: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-        material: &'b Material,
-        u_instances: &'b wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        for mesh in &geom.meshes {
-            self.draw_mesh_instanced_with_light_and_material(
-                mesh,
-                uniforms,
-                light,
-                material,
-                u_instances,
-                instances.clone(),
-            );
-        }
+    fn load_pbr_material(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        containing_folder: &Path,
+        mat: &tobj::Material,
+    ) -> Result<Material> {
+        let param = |key: &str| mat.unknown_param.get(key);
+        let scalar = |key: &str, default: f32| -> Result<f32> {
+            match param(key) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid {} in {}: {}", key, mat.name, value)),
+                None => Ok(default),
+            }
+        };
+        let texture = |path: &str| {
+            if path.is_empty() {
+                None
+            } else {
+                Some(containing_folder.join(path))
+            }
+        };
+
+        let emissive = match param("Ke") {
+            Some(value) => {
+                let rgb = value
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<std::result::Result<Vec<f32>, _>>()
+                    .with_context(|| format!("Invalid Ke in {}: {}", mat.name, value))?;
+                match rgb.as_slice() {
+                    [r, g, b] => cgmath::Vector3::new(*r, *g, *b),
+                    [v] => cgmath::Vector3::new(*v, *v, *v),
+                    _ => bail!("Invalid Ke in {}: {}", mat.name, value),
+                }
+            }
+            None if param("map_Ke").is_some() => cgmath::Vector3::new(1.0, 1.0, 1.0),
+            None => cgmath::Vector3::zero(),
+        };
+        let factors = PbrFactors {
+            base_color: cgmath::Vector3::from(mat.diffuse).extend(mat.dissolve),
+            metallic: scalar("Pm", 0.0)?,
+            roughness: scalar("Pr", 0.5)?,
+            emissive,
+            ..PbrFactors::default()
+        };
+        let maps = PbrMaps {
+            base_color: texture(&mat.diffuse_texture),
+            normal: texture(&mat.normal_texture),
+            metallic: param("map_Pm").and_then(|path| texture(path)),
+            roughness: param("map_Pr").and_then(|path| texture(path)),
+            // there is no standard key for occlusion, but exporters write it as ambient
+            occlusion: texture(&mat.ambient_texture),
+            emissive: param("map_Ke").and_then(|path| texture(path)),
+        };
+        Material::pbr(device, queue, &mat.name, &factors, &maps)
     }
 }
@@ -0,0 +1,492 @@
+//! golden image comparison of rendered frames
+//!
+//! frames are compared against reference PNGs per pixel, by PSNR and by SSIM. a frame fails if
+//! too many pixels differ by more than the channel tolerance or if either metric is below its
+//! threshold, and a diff image is written next to the frame. everything here is CPU only, so
+//! the frames may come from any adapter, e.g. a software rasterizer on CI
+//!
+//! the references of the MSAA test triangle live in `golden/triangle`, blessed once with
+//! `--bless` and checked by the same invocation without it:
+//!
+//! ```sh
+//! cargo run --release -- --offline --scene triangle --frames 0..3 --size 128x96 --msaa 4 \
+//!     --seed 1 --adapter llvmpipe --golden golden/triangle --out target/golden
+//! ```
+use anyhow::*;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::capture::sequence_path;
+
+/// side of the SSIM windows, which move by half of it
+pub const SSIM_WINDOW: u32 = 8;
+
+/// thresholds of a passing frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tolerance {
+    /// difference of a channel which still counts as the same pixel
+    pub channel: u8,
+    /// fraction of the pixels which may differ
+    pub mismatched: f64,
+    /// dB
+    pub min_psnr: f64,
+    pub min_ssim: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            mismatched: 0.001,
+            min_psnr: 40.0,
+            min_ssim: 0.98,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Comparison {
+    pub pixels: usize,
+    /// pixels with a channel differing by more than `Tolerance::channel`
+    pub mismatched: usize,
+    /// largest difference of a channel
+    pub max_difference: u8,
+    /// of the RGB channels, infinite if the images are the same
+    pub psnr: f64,
+    /// mean SSIM of the luma
+    pub ssim: f64,
+}
+
+impl Comparison {
+    pub fn mismatched_fraction(&self) -> f64 {
+        self.mismatched as f64 / self.pixels.max(1) as f64
+    }
+
+    /// the thresholds of `tolerance` which aren't met
+    pub fn failures(&self, tolerance: &Tolerance) -> Vec<String> {
+        let mut failures = Vec::new();
+        if self.mismatched_fraction() > tolerance.mismatched {
+            failures.push(format!(
+                "{:.3}% of the pixels differ (max {:.3}%)",
+                self.mismatched_fraction() * 100.0,
+                tolerance.mismatched * 100.0
+            ));
+        }
+        if self.psnr < tolerance.min_psnr {
+            failures.push(format!(
+                "PSNR {:.2} dB < {:.2} dB",
+                self.psnr, tolerance.min_psnr
+            ));
+        }
+        if self.ssim < tolerance.min_ssim {
+            failures.push(format!("SSIM {:.4} < {:.4}", self.ssim, tolerance.min_ssim));
+        }
+        failures
+    }
+
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.failures(tolerance).is_empty()
+    }
+}
+
+/// the images must have the same size. alpha is only compared per pixel
+pub fn compare(
+    reference: &image::RgbaImage,
+    actual: &image::RgbaImage,
+    tolerance: &Tolerance,
+) -> Result<Comparison> {
+    if reference.dimensions() != actual.dimensions() {
+        bail!(
+            "Reference is {:?} but the frame is {:?}",
+            reference.dimensions(),
+            actual.dimensions()
+        );
+    }
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    let mut squared_error = 0.0;
+    for (r, a) in reference.pixels().zip(actual.pixels()) {
+        let difference = (0..4).map(|i| diff(r[i], a[i])).max().unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance.channel {
+            mismatched += 1;
+        }
+        squared_error += (0..3)
+            .map(|i| (r[i] as f64 - a[i] as f64).powi(2))
+            .sum::<f64>();
+    }
+    let pixels = (reference.width() * reference.height()) as usize;
+    Ok(Comparison {
+        pixels,
+        mismatched,
+        max_difference,
+        psnr: psnr(squared_error / (pixels.max(1) * 3) as f64),
+        ssim: ssim(reference, actual),
+    })
+}
+
+fn diff(a: u8, b: u8) -> u8 {
+    a.max(b) - a.min(b)
+}
+
+fn psnr(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+fn luma(pixel: &image::Rgba<u8>) -> f64 {
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+/// mean SSIM of `SSIM_WINDOW` windows, or a window of the whole image if it is smaller
+pub fn ssim(a: &image::RgbaImage, b: &image::RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = a.dimensions();
+    let window = SSIM_WINDOW.min(width).min(height) as usize;
+    if window == 0 {
+        return 1.0;
+    }
+    let la = a.pixels().map(luma).collect::<Vec<_>>();
+    let lb = b.pixels().map(luma).collect::<Vec<_>>();
+    let (width, height) = (width as usize, height as usize);
+    let step = (window / 2).max(1);
+    let n = (window * window) as f64;
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in (0..=height - window).step_by(step) {
+        for x in (0..=width - window).step_by(step) {
+            let indices = (y..y + window).flat_map(|y| (x..x + window).map(move |x| y * width + x));
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for i in indices {
+                sa += la[i];
+                sb += lb[i];
+                saa += la[i] * la[i];
+                sbb += lb[i] * lb[i];
+                sab += la[i] * lb[i];
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let va = saa / n - ma * ma;
+            let vb = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            count += 1;
+        }
+    }
+    total / count as f64
+}
+
+/// the reference in dimmed gray, with the mismatched pixels in red by their difference
+pub fn diff_image(
+    reference: &image::RgbaImage,
+    actual: &image::RgbaImage,
+    tolerance: &Tolerance,
+) -> image::RgbaImage {
+    image::RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let r = reference.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+        let difference = (0..4).map(|i| diff(r[i], a[i])).max().unwrap_or(0);
+        if difference > tolerance.channel {
+            let red = 128 + difference / 2;
+            image::Rgba([red, 0, 0, 255])
+        } else {
+            let gray = (luma(r) * 0.25) as u8;
+            image::Rgba([gray, gray, gray, 255])
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed(Comparison),
+    Failed(Comparison, Vec<String>),
+    /// the reference doesn't exist or has another size
+    Missing(String),
+    /// the reference was written from the frame
+    Blessed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameResult {
+    pub frame: u32,
+    pub reference: PathBuf,
+    pub outcome: Outcome,
+}
+
+impl FrameResult {
+    pub fn passed(&self) -> bool {
+        match self.outcome {
+            Outcome::Passed(_) | Outcome::Blessed => true,
+            Outcome::Failed(..) | Outcome::Missing(_) => false,
+        }
+    }
+}
+
+/// one line per frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub frames: Vec<FrameResult>,
+}
+
+impl Report {
+    pub fn failed(&self) -> usize {
+        self.frames.iter().filter(|frame| !frame.passed()).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.frames {
+            write!(f, "{:05} ", result.frame)?;
+            match &result.outcome {
+                Outcome::Passed(c) => write!(f, "ok     {}", Metrics(c))?,
+                Outcome::Failed(c, failures) => {
+                    write!(f, "FAILED {}: {}", Metrics(c), failures.join(", "))?
+                }
+                Outcome::Missing(reason) => write!(f, "FAILED {}", reason)?,
+                Outcome::Blessed => write!(f, "blessed {}", result.reference.display())?,
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} of {} frames failed",
+            self.failed(),
+            self.frames.len()
+        )
+    }
+}
+
+struct Metrics<'a>(&'a Comparison);
+
+impl fmt::Display for Metrics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "psnr {:.2} dB, ssim {:.4}, {:.3}% differ, max {}",
+            self.0.psnr,
+            self.0.ssim,
+            self.0.mismatched_fraction() * 100.0,
+            self.0.max_difference
+        )
+    }
+}
+
+/// checks frames against `frame_00042.png` in a directory of references
+pub struct GoldenSet {
+    references: PathBuf,
+    /// diff images and the report
+    output: PathBuf,
+    tolerance: Tolerance,
+    bless: bool,
+    report: Report,
+}
+
+impl GoldenSet {
+    pub fn new(references: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        Self {
+            references: references.into(),
+            output: output.into(),
+            tolerance: Tolerance::default(),
+            bless: false,
+            report: Report::default(),
+        }
+    }
+
+    pub fn tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// writes the frames as the references instead of comparing them
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// `diff_00042.png` in the output directory
+    pub fn diff_path(&self, frame: u32) -> PathBuf {
+        self.output.join(format!("diff_{:05}.png", frame))
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.output.join("golden.txt")
+    }
+
+    /// records whether `image` matches its reference. only fails if a file can't be written.
+    /// the diff of an earlier run is removed first, so only failed frames have one
+    pub fn check(&mut self, frame: u32, image: &image::RgbaImage) -> Result<&FrameResult> {
+        let diff = self.diff_path(frame);
+        if diff.exists() {
+            std::fs::remove_file(&diff)
+                .with_context(|| format!("Failed to remove {}", diff.display()))?;
+        }
+        let reference = sequence_path(&self.references, frame);
+        let outcome = if self.bless {
+            save(image, &reference)?;
+            Outcome::Blessed
+        } else {
+            self.compare_reference(frame, &reference, image)?
+        };
+        self.report.frames.push(FrameResult {
+            frame,
+            reference,
+            outcome,
+        });
+        Ok(self.report.frames.last().unwrap())
+    }
+
+    fn compare_reference(
+        &self,
+        frame: u32,
+        reference: &Path,
+        image: &image::RgbaImage,
+    ) -> Result<Outcome> {
+        let expected = match image::open(reference) {
+            Err(err) => {
+                return Ok(Outcome::Missing(format!(
+                    "{}: {}",
+                    reference.display(),
+                    err
+                )))
+            }
+            opened => opened?.to_rgba8(),
+        };
+        let comparison = match compare(&expected, image, &self.tolerance) {
+            Err(err) => return Ok(Outcome::Missing(err.to_string())),
+            compared => compared?,
+        };
+        let failures = comparison.failures(&self.tolerance);
+        if failures.is_empty() {
+            return Ok(Outcome::Passed(comparison));
+        }
+        let diff = diff_image(&expected, image, &self.tolerance);
+        save(&diff, &self.diff_path(frame))?;
+        Ok(Outcome::Failed(comparison, failures))
+    }
+
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// writes the report and fails if a frame did
+    pub fn finish(self) -> Result<Report> {
+        std::fs::create_dir_all(&self.output)?;
+        std::fs::write(self.report_path(), format!("{}\n", self.report))?;
+        if self.report.failed() > 0 {
+            bail!(
+                "{} of {} frames differ from {}, see {}",
+                self.report.failed(),
+                self.report.frames.len(),
+                self.references.display(),
+                self.report_path().display()
+            );
+        }
+        Ok(self.report)
+    }
+}
+
+fn save(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    image
+        .save(path)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+#[test]
+fn test_golden_compare() {
+    let tolerance = Tolerance::default();
+    let gradient = image::RgbaImage::from_fn(32, 24, |x, y| {
+        image::Rgba([(x * 8) as u8, (y * 10) as u8, 128, 255])
+    });
+
+    let same = compare(&gradient, &gradient, &tolerance).unwrap();
+    assert_eq!((same.mismatched, same.max_difference), (0, 0));
+    assert_eq!(same.psnr, f64::INFINITY);
+    assert!((same.ssim - 1.0).abs() < 1e-9);
+    assert!(same.passes(&tolerance));
+
+    // within the channel tolerance everywhere
+    let mut noisy = gradient.clone();
+    for (i, pixel) in noisy.pixels_mut().enumerate() {
+        pixel[0] = pixel[0].saturating_add((i % 3) as u8);
+    }
+    let comparison = compare(&gradient, &noisy, &tolerance).unwrap();
+    assert_eq!(comparison.mismatched, 0);
+    assert!(comparison.psnr > 45.0 && comparison.ssim > 0.99);
+    assert!(comparison.passes(&tolerance));
+
+    // a block of wrong pixels
+    let mut broken = gradient.clone();
+    for y in 4..12 {
+        for x in 4..12 {
+            broken.put_pixel(x, y, image::Rgba([255, 0, 255, 255]));
+        }
+    }
+    let comparison = compare(&gradient, &broken, &tolerance).unwrap();
+    assert_eq!(comparison.mismatched, 64);
+    assert_eq!(comparison.max_difference, 255 - 4 * 8);
+    assert!(comparison.ssim < 0.98);
+    assert_eq!(comparison.failures(&tolerance).len(), 3);
+    let diff = diff_image(&gradient, &broken, &tolerance);
+    assert!(diff.get_pixel(5, 5)[0] > 128 && diff.get_pixel(5, 5)[1] == 0);
+    assert_eq!(diff.get_pixel(20, 20)[1], diff.get_pixel(20, 20)[0]);
+
+    let loose = Tolerance {
+        mismatched: 0.1,
+        min_psnr: 10.0,
+        min_ssim: 0.5,
+        ..tolerance
+    };
+    assert!(comparison.passes(&loose));
+
+    assert!(compare(&gradient, &image::RgbaImage::new(24, 32), &tolerance).is_err());
+    let tiny = image::RgbaImage::new(2, 1);
+    assert!((ssim(&tiny, &tiny) - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_golden_set() {
+    let dir = crate::test_util::TempDir::new("golden");
+    let references = dir.join("references");
+    let output = dir.join("output");
+    let frame =
+        image::RgbaImage::from_fn(16, 16, |x, y| image::Rgba([x as u8 * 16, y as u8, 0, 255]));
+
+    let mut set = GoldenSet::new(&references, &output).bless(true);
+    set.check(0, &frame).unwrap();
+    set.check(1, &frame).unwrap();
+    set.finish().unwrap();
+    assert!(sequence_path(&references, 1).exists());
+
+    let mut changed = frame.clone();
+    changed.put_pixel(3, 3, image::Rgba([0, 255, 0, 255]));
+    let tolerance = Tolerance {
+        mismatched: 0.0,
+        ..Tolerance::default()
+    };
+    let mut set = GoldenSet::new(&references, &output).tolerance(tolerance);
+    assert!(set.check(0, &frame).unwrap().passed());
+    assert!(!set.check(1, &changed).unwrap().passed());
+    assert!(!set.check(2, &frame).unwrap().passed());
+    assert!(set.diff_path(1).exists());
+    assert!(!set.diff_path(0).exists());
+    let report = set.report().to_string();
+    assert!(report.starts_with("00000 ok"));
+    assert!(report.ends_with("2 of 3 frames failed"));
+    assert!(set.finish().is_err());
+    assert!(output.join("golden.txt").exists());
+
+    // the diff of the failed run goes once the frame passes again
+    let mut set = GoldenSet::new(&references, &output).tolerance(tolerance);
+    assert!(set.check(1, &frame).unwrap().passed());
+    assert!(!set.diff_path(1).exists());
+    set.finish().unwrap();
+}
@@ -0,0 +1,599 @@
+//! declarative render graph
+//!
+//! passes declare the resources they read and write by name. the graph
+//! - orders the passes topologically: every write to a resource happens before any read of it,
+//!   and writes to the same resource keep the order in which the passes were added
+//! - culls passes which don't contribute to an imported or output resource
+//! - rejects transients read before any pass writes them
+//! - allocates transient textures and aliases the ones whose lifetimes don't overlap
+//! - recreates surface sized textures on resize
+//! - records every pass into one encoder
+use anyhow::*;
+use nannou::prelude::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureSize {
+    /// same as the surface (swap chain)
+    Surface,
+    /// surface size multiplied by the scale
+    Scaled(f32),
+    Fixed([u32; 2]),
+}
+
+impl TextureSize {
+    pub fn resolve(&self, surface: [u32; 2]) -> [u32; 2] {
+        let [width, height] = match *self {
+            TextureSize::Surface => surface,
+            TextureSize::Scaled(scale) => [
+                (surface[0] as f32 * scale).round() as u32,
+                (surface[1] as f32 * scale).round() as u32,
+            ],
+            TextureSize::Fixed(size) => size,
+        };
+        [width.max(1), height.max(1)]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureDesc {
+    pub format: wgpu::TextureFormat,
+    pub size: TextureSize,
+    pub usage: wgpu::TextureUsage,
+    pub sample_count: u32,
+}
+
+impl TextureDesc {
+    pub fn new(format: wgpu::TextureFormat, size: TextureSize, usage: wgpu::TextureUsage) -> Self {
+        Self {
+            format,
+            size,
+            usage,
+            sample_count: 1,
+        }
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResourceDesc {
+    /// owned by the graph and may share memory with other transients
+    Transient(TextureDesc),
+    /// owned by the graph, never aliased and readable after `render`
+    Output(TextureDesc),
+    /// provided on `render` (e.g. the swap chain) or owned by the passes (e.g. buffers).
+    /// only used for ordering
+    Imported,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassDesc {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+impl PassDesc {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn read(mut self, resource: &str) -> Self {
+        self.reads.push(resource.to_owned());
+        self
+    }
+
+    pub fn write(mut self, resource: &str) -> Self {
+        self.writes.push(resource.to_owned());
+        self
+    }
+
+    fn uses(&self, resource: &str) -> bool {
+        self.reads
+            .iter()
+            .chain(self.writes.iter())
+            .any(|r| r == resource)
+    }
+}
+
+/// result of `compile`. indices are into the passes given to `compile`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphPlan {
+    /// passes to execute in order. culled passes are not included
+    pub order: Vec<usize>,
+    /// positions in `order` where each graph owned texture is alive
+    pub lifetimes: HashMap<String, Range<usize>>,
+    /// physical textures
+    pub slots: Vec<TextureDesc>,
+    /// graph owned texture -> index of `slots`
+    pub slot_of: HashMap<String, usize>,
+}
+
+pub fn compile(passes: &[PassDesc], resources: &[(String, ResourceDesc)]) -> Result<GraphPlan> {
+    let resource_desc = |name: &str| {
+        resources
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, desc)| *desc)
+    };
+    for pass in passes {
+        for name in pass.reads.iter().chain(pass.writes.iter()) {
+            if resource_desc(name).is_none() {
+                bail!("Pass {} uses undeclared resource {}", pass.name, name);
+            }
+        }
+    }
+
+    // writers -> readers, and writers of the same resource in the order they were added
+    let mut edges = vec![Vec::new(); passes.len()];
+    for (name, _) in resources {
+        let writers = (0..passes.len())
+            .filter(|&i| passes[i].writes.contains(name))
+            .collect::<Vec<_>>();
+        for pair in writers.windows(2) {
+            edges[pair[0]].push(pair[1]);
+        }
+        for (reader, pass) in passes.iter().enumerate() {
+            if pass.reads.contains(name) && !pass.writes.contains(name) {
+                for &writer in &writers {
+                    edges[writer].push(reader);
+                }
+            }
+        }
+    }
+
+    // cull passes which don't reach an imported or output resource
+    let mut alive = passes
+        .iter()
+        .map(|pass| {
+            pass.writes.iter().any(|name| match resource_desc(name) {
+                Some(ResourceDesc::Transient(_)) => false,
+                _ => true,
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pass in 0..passes.len() {
+            if !alive[pass] && edges[pass].iter().any(|&next| alive[next]) {
+                alive[pass] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Kahn's algorithm. ties are broken by the order the passes were added
+    let mut in_degree = vec![0; passes.len()];
+    for pass in (0..passes.len()).filter(|&p| alive[p]) {
+        for &next in edges[pass].iter().filter(|&&next| alive[next]) {
+            in_degree[next] += 1;
+        }
+    }
+    let mut order = Vec::new();
+    let mut ready = (0..passes.len())
+        .filter(|&p| alive[p] && in_degree[p] == 0)
+        .collect::<Vec<_>>();
+    while !ready.is_empty() {
+        let pass = ready.remove(0);
+        order.push(pass);
+        for &next in edges[pass].iter().filter(|&&next| alive[next]) {
+            in_degree[next] -= 1;
+            if in_degree[next] == 0 {
+                let position = ready.iter().position(|&p| p > next).unwrap_or(ready.len());
+                ready.insert(position, next);
+            }
+        }
+    }
+    let num_alive = alive.iter().filter(|&&a| a).count();
+    if order.len() != num_alive {
+        let cycle = (0..passes.len())
+            .filter(|&p| alive[p] && !order.contains(&p))
+            .map(|p| passes[p].name.as_str())
+            .collect::<Vec<_>>();
+        bail!("Render graph has a cycle through {}", cycle.join(", "));
+    }
+    // it would sample whatever last used the memory of its slot
+    for (position, &pass) in order.iter().enumerate() {
+        for name in &passes[pass].reads {
+            let transient = match resource_desc(name) {
+                Some(ResourceDesc::Transient(_)) => true,
+                _ => false,
+            };
+            let written = order[..position]
+                .iter()
+                .any(|&writer| passes[writer].writes.contains(name));
+            if transient && !written {
+                bail!(
+                    "Pass {} reads {}, which no pass writes before it",
+                    passes[pass].name,
+                    name
+                );
+            }
+        }
+    }
+
+    let mut lifetimes = HashMap::new();
+    for (name, desc) in resources {
+        let used = order
+            .iter()
+            .enumerate()
+            .filter(|(_, &pass)| passes[pass].uses(name))
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        let (first, last) = match (used.first(), used.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => continue,
+        };
+        match desc {
+            ResourceDesc::Transient(_) => lifetimes.insert(name.clone(), first..last + 1),
+            ResourceDesc::Output(_) => lifetimes.insert(name.clone(), first..order.len()),
+            ResourceDesc::Imported => None,
+        };
+    }
+
+    // greedy aliasing in the order of the first use
+    let mut owned = resources
+        .iter()
+        .filter_map(|(name, desc)| match desc {
+            ResourceDesc::Transient(desc) | ResourceDesc::Output(desc) => lifetimes
+                .get(name)
+                .map(|lifetime| (name, *desc, lifetime.clone())),
+            ResourceDesc::Imported => None,
+        })
+        .collect::<Vec<_>>();
+    owned.sort_by_key(|(_, _, lifetime)| lifetime.start);
+    let mut slots: Vec<TextureDesc> = Vec::new();
+    let mut slot_ends: Vec<usize> = Vec::new();
+    let mut slot_of = HashMap::new();
+    for (name, desc, lifetime) in owned {
+        let output = match resource_desc(name) {
+            Some(ResourceDesc::Output(_)) => true,
+            _ => false,
+        };
+        let free = (0..slots.len())
+            .find(|&slot| !output && slots[slot] == desc && slot_ends[slot] <= lifetime.start);
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                slots.push(desc);
+                slot_ends.push(0);
+                slots.len() - 1
+            }
+        };
+        // outputs keep their slot until the end
+        slot_ends[slot] = if output { usize::MAX } else { lifetime.end };
+        slot_of.insert(name.clone(), slot);
+    }
+
+    Ok(GraphPlan {
+        order,
+        lifetimes,
+        slots,
+        slot_of,
+    })
+}
+
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub trait GraphPass: AsAny {
+    fn desc(&self) -> PassDesc;
+
+    /// called after the graph owned textures are recreated
+    fn resized(&mut self, _device: &wgpu::Device, _size: [u32; 2]) {}
+
+    /// called after the graph owned textures are created or recreated.
+    /// bind groups which sample graph owned textures should be rebuilt here
+    fn bind(&mut self, _device: &wgpu::Device, _resources: &GraphResources) {}
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources);
+}
+
+struct TextureSlot {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: [u32; 2],
+}
+
+/// textures visible to passes while the graph is rendering
+pub struct GraphResources<'a> {
+    plan: &'a GraphPlan,
+    slots: &'a [TextureSlot],
+    size: [u32; 2],
+    imports: &'a [(&'a str, &'a wgpu::TextureViewHandle)],
+}
+
+impl<'a> GraphResources<'a> {
+    pub fn view(&self, name: &str) -> &'a wgpu::TextureViewHandle {
+        if let Some(&slot) = self.plan.slot_of.get(name) {
+            return &self.slots[slot].view;
+        }
+        self.imports
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, view)| *view)
+            .unwrap_or_else(|| panic!("No texture named {} in the render graph", name))
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&'a wgpu::Texture> {
+        let slots = self.slots;
+        self.plan
+            .slot_of
+            .get(name)
+            .map(|&slot| &slots[slot].texture)
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+}
+
+pub struct RenderGraphBuilder {
+    resources: Vec<(String, ResourceDesc)>,
+    passes: Vec<Box<dyn GraphPass>>,
+}
+
+impl RenderGraphBuilder {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn texture(self, name: &str, desc: TextureDesc) -> Self {
+        self.resource(name, ResourceDesc::Transient(desc))
+    }
+
+    pub fn output(self, name: &str, desc: TextureDesc) -> Self {
+        self.resource(name, ResourceDesc::Output(desc))
+    }
+
+    pub fn import(self, name: &str) -> Self {
+        self.resource(name, ResourceDesc::Imported)
+    }
+
+    pub fn pass<P: GraphPass + 'static>(mut self, pass: P) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    fn resource(mut self, name: &str, desc: ResourceDesc) -> Self {
+        self.resources.retain(|(n, _)| n != name);
+        self.resources.push((name.to_owned(), desc));
+        self
+    }
+
+    pub fn build(self, device: &wgpu::Device, size: [u32; 2]) -> Result<RenderGraph> {
+        let descs = self.passes.iter().map(|p| p.desc()).collect::<Vec<_>>();
+        let plan = compile(&descs, &self.resources)?;
+        let slots = plan
+            .slots
+            .iter()
+            .map(|desc| RenderGraph::create_slot(device, desc, size))
+            .collect();
+        let mut graph = RenderGraph {
+            names: descs.into_iter().map(|d| d.name).collect(),
+            passes: self.passes,
+            plan,
+            slots,
+            size,
+        };
+        graph.bind(device);
+        Ok(graph)
+    }
+}
+
+pub struct RenderGraph {
+    names: Vec<String>,
+    passes: Vec<Box<dyn GraphPass>>,
+    plan: GraphPlan,
+    slots: Vec<TextureSlot>,
+    size: [u32; 2],
+}
+
+impl RenderGraph {
+    /// records every pass into `encoder`. `imports` provides the imported textures
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        imports: &[(&str, &wgpu::TextureViewHandle)],
+    ) {
+        let resources = GraphResources {
+            plan: &self.plan,
+            slots: &self.slots,
+            size: self.size,
+            imports,
+        };
+        for &pass in &self.plan.order {
+            self.passes[pass].render(encoder, &resources);
+        }
+    }
+
+    pub fn resized(&mut self, device: &wgpu::Device, size: [u32; 2]) {
+        self.size = size;
+        for (slot, desc) in self.slots.iter_mut().zip(self.plan.slots.iter()) {
+            if slot.size != desc.size.resolve(size) {
+                *slot = Self::create_slot(device, desc, size);
+            }
+        }
+        for pass in self.passes.iter_mut() {
+            pass.resized(device, size);
+        }
+        self.bind(device);
+    }
+
+    pub fn pass<T: GraphPass + 'static>(&self, name: &str) -> Option<&T> {
+        let index = self.names.iter().position(|n| n == name)?;
+        let pass: &dyn GraphPass = &*self.passes[index];
+        pass.as_any().downcast_ref::<T>()
+    }
+
+    pub fn pass_mut<T: GraphPass + 'static>(&mut self, name: &str) -> Option<&mut T> {
+        let index = self.names.iter().position(|n| n == name)?;
+        let pass: &mut dyn GraphPass = &mut *self.passes[index];
+        pass.as_any_mut().downcast_mut::<T>()
+    }
+
+    /// graph owned texture. transients may be overwritten by later passes
+    pub fn texture(&self, name: &str) -> Option<&wgpu::Texture> {
+        self.plan
+            .slot_of
+            .get(name)
+            .map(|&slot| &self.slots[slot].texture)
+    }
+
+    /// names of the passes in execution order
+    pub fn order(&self) -> Vec<&str> {
+        self.plan
+            .order
+            .iter()
+            .map(|&pass| self.names[pass].as_str())
+            .collect()
+    }
+
+    pub fn plan(&self) -> &GraphPlan {
+        &self.plan
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    fn bind(&mut self, device: &wgpu::Device) {
+        let resources = GraphResources {
+            plan: &self.plan,
+            slots: &self.slots,
+            size: self.size,
+            imports: &[],
+        };
+        for pass in self.passes.iter_mut() {
+            pass.bind(device, &resources);
+        }
+    }
+
+    fn create_slot(device: &wgpu::Device, desc: &TextureDesc, surface: [u32; 2]) -> TextureSlot {
+        let size = desc.size.resolve(surface);
+        let texture = wgpu::TextureBuilder::new()
+            .extent(wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth: 1,
+            })
+            .mip_level_count(1)
+            .sample_count(desc.sample_count)
+            .dimension(wgpu::TextureDimension::D2)
+            .format(desc.format)
+            .usage(desc.usage)
+            .build(device);
+        let view = texture.view().build();
+        TextureSlot {
+            texture,
+            view,
+            size,
+        }
+    }
+}
+
+#[test]
+fn test_render_graph_plan() {
+    let color = TextureDesc::new(
+        wgpu::TextureFormat::Rgba16Float,
+        TextureSize::Surface,
+        wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+    );
+    let half = TextureDesc {
+        size: TextureSize::Scaled(0.5),
+        ..color
+    };
+    let resources = vec![
+        ("swap_chain".to_owned(), ResourceDesc::Imported),
+        ("scene".to_owned(), ResourceDesc::Transient(color)),
+        ("bright".to_owned(), ResourceDesc::Transient(half)),
+        ("blur".to_owned(), ResourceDesc::Transient(half)),
+        ("unused".to_owned(), ResourceDesc::Transient(color)),
+    ];
+    // added out of order
+    let passes = vec![
+        PassDesc::new("composite")
+            .read("scene")
+            .read("blur")
+            .write("swap_chain"),
+        PassDesc::new("blur").read("bright").write("blur"),
+        PassDesc::new("debug").read("scene").write("unused"),
+        PassDesc::new("bright").read("scene").write("bright"),
+        PassDesc::new("scene").write("scene"),
+    ];
+    let plan = compile(&passes, &resources).unwrap();
+    let names = plan
+        .order
+        .iter()
+        .map(|&p| passes[p].name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["scene", "bright", "blur", "composite"]);
+
+    // "blur" reads "bright", so they can't share memory
+    assert_eq!(plan.lifetimes["scene"], 0..4);
+    assert_eq!(plan.lifetimes["bright"], 1..3);
+    assert_eq!(plan.lifetimes["blur"], 2..4);
+    assert_ne!(plan.slot_of["bright"], plan.slot_of["blur"]);
+    assert_eq!(plan.slots.len(), 3);
+
+    // "bright" is dead once "bright_again" is written, so they share memory
+    let passes = vec![
+        PassDesc::new("a").write("scene"),
+        PassDesc::new("b").read("scene").write("bright"),
+        PassDesc::new("c").read("bright").write("blur"),
+        PassDesc::new("d").read("blur").write("bright_again"),
+        PassDesc::new("e").read("bright_again").write("swap_chain"),
+    ];
+    let mut resources = resources;
+    resources.push(("bright_again".to_owned(), ResourceDesc::Transient(half)));
+    let plan = compile(&passes, &resources).unwrap();
+    assert_eq!(plan.slot_of["bright"], plan.slot_of["bright_again"]);
+    assert!(!plan.slot_of.contains_key("unused"));
+
+    let cycle = vec![
+        PassDesc::new("a").read("blur").write("bright"),
+        PassDesc::new("b").read("bright").write("blur"),
+        PassDesc::new("c").read("blur").write("swap_chain"),
+    ];
+    assert!(compile(&cycle, &resources).is_err());
+    let undeclared = vec![PassDesc::new("a").write("nothing")];
+    assert!(compile(&undeclared, &resources).is_err());
+    let unwritten = vec![PassDesc::new("a").read("scene").write("swap_chain")];
+    assert!(compile(&unwritten, &resources).is_err());
+    // unless the reader is culled
+    let culled = vec![
+        PassDesc::new("a").write("swap_chain"),
+        PassDesc::new("b").read("scene").write("blur"),
+    ];
+    assert!(compile(&culled, &resources).is_ok());
+
+    assert_eq!(TextureSize::Surface.resolve([800, 600]), [800, 600]);
+    assert_eq!(TextureSize::Scaled(0.5).resolve([801, 600]), [401, 300]);
+    assert_eq!(TextureSize::Scaled(0.0).resolve([800, 600]), [1, 1]);
+    assert_eq!(TextureSize::Fixed([64, 32]).resolve([800, 600]), [64, 32]);
+}
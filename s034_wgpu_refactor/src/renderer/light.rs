@@ -1,146 +1,373 @@
 // TODO: improve based on ofLight
 use nannou::math::cgmath;
 use nannou::prelude::*;
-use std::ops::Range;
-use std::time::Duration;
+use std::ops::{Deref, DerefMut};
 
-use super::binding::{Binding, BindingBuilder, BindingType};
-use super::geom::Geom;
-use super::mesh::Mesh;
+use super::binding::{Binding, BindingBuilder};
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct LightRaw {
-    pub position: cgmath::Vector3<f32>,
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
-    pub color: cgmath::Vector3<f32>,
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    Directional {
+        direction: cgmath::Vector3<f32>,
+    },
+    Point {
+        position: cgmath::Vector3<f32>,
+        /// light fades out to zero at this distance
+        radius: f32,
+    },
+    Spot {
+        position: cgmath::Vector3<f32>,
+        direction: cgmath::Vector3<f32>,
+        radius: f32,
+        inner_angle: cgmath::Deg<f32>,
+        outer_angle: cgmath::Deg<f32>,
+    },
 }
-unsafe impl bytemuck::Zeroable for LightRaw {}
-unsafe impl bytemuck::Pod for LightRaw {}
 
+impl LightKind {
+    // must be same as shaders
+    pub const DIRECTIONAL: u32 = 0;
+    pub const POINT: u32 = 1;
+    pub const SPOT: u32 = 2;
+
+    pub fn type_id(&self) -> u32 {
+        match self {
+            LightKind::Directional { .. } => Self::DIRECTIONAL,
+            LightKind::Point { .. } => Self::POINT,
+            LightKind::Spot { .. } => Self::SPOT,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Light {
-    pub raw: LightRaw,
-    pub binding: Binding,
+    pub kind: LightKind,
+    pub color: cgmath::Vector3<f32>,
+    pub intensity: f32,
+    /// rendered into `ShadowMaps` if true
+    pub cast_shadows: bool,
 }
 
 impl Light {
-    pub fn new(
-        device: &wgpu::Device,
+    pub fn directional(direction: cgmath::Vector3<f32>, color: cgmath::Vector3<f32>) -> Self {
+        Self {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+            intensity: 1.0,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn point(position: cgmath::Vector3<f32>, radius: f32, color: cgmath::Vector3<f32>) -> Self {
+        Self {
+            kind: LightKind::Point { position, radius },
+            color,
+            intensity: 1.0,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn spot<A: Into<cgmath::Deg<f32>>>(
         position: cgmath::Vector3<f32>,
+        direction: cgmath::Vector3<f32>,
+        radius: f32,
+        inner_angle: A,
+        outer_angle: A,
         color: cgmath::Vector3<f32>,
     ) -> Self {
-        let raw = LightRaw {
-            position,
-            _padding: 0,
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction: direction.normalize(),
+                radius,
+                inner_angle: inner_angle.into(),
+                outer_angle: outer_angle.into(),
+            },
             color,
+            intensity: 1.0,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn cast_shadows(mut self, cast_shadows: bool) -> Self {
+        self.cast_shadows = cast_shadows;
+        self
+    }
+
+    pub fn position(&self) -> Option<&cgmath::Vector3<f32>> {
+        match &self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } => Some(position),
+            LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+
+    pub fn position_as_mut(&mut self) -> Option<&mut cgmath::Vector3<f32>> {
+        match &mut self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } => Some(position),
+            LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+
+    pub fn to_raw(&self) -> LightRaw {
+        let type_id = self.kind.type_id() as f32;
+        let zero = cgmath::Vector3::zero();
+        let (position, direction, radius, cone) = match self.kind {
+            LightKind::Directional { direction } => (zero, direction, 0.0, [1.0, 1.0]),
+            LightKind::Point { position, radius } => (position, zero, radius, [1.0, 1.0]),
+            LightKind::Spot {
+                position,
+                direction,
+                radius,
+                inner_angle,
+                outer_angle,
+            } => (
+                position,
+                direction,
+                radius,
+                [
+                    cgmath::Rad::from(inner_angle).0.cos(),
+                    cgmath::Rad::from(outer_angle).0.cos(),
+                ],
+            ),
         };
+        LightRaw {
+            position: position.extend(type_id),
+            direction: direction.extend(radius),
+            color: self.color.extend(self.intensity),
+            cone: cgmath::Vector4::new(cone[0], cone[1], 0.0, 0.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LightRaw {
+    /// xyz: position, w: light type
+    pub position: cgmath::Vector4<f32>,
+    /// xyz: direction, w: attenuation radius
+    pub direction: cgmath::Vector4<f32>,
+    /// rgb: color, a: intensity
+    pub color: cgmath::Vector4<f32>,
+    /// x: cos(inner angle), y: cos(outer angle)
+    pub cone: cgmath::Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for LightRaw {}
+unsafe impl bytemuck::Pod for LightRaw {}
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LightInfoRaw {
+    pub num_lights: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    pub _padding: [u32; 3],
+}
+unsafe impl bytemuck::Zeroable for LightInfoRaw {}
+unsafe impl bytemuck::Pod for LightInfoRaw {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId(usize);
+
+/// lights by `LightId`. ids of removed lights are reused by the next `add`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lights {
+    slots: Vec<Option<Light>>,
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` if there are already `LightSet::MAX_LIGHTS` lights
+    pub fn add(&mut self, light: Light) -> Option<LightId> {
+        if self.len() >= LightSet::MAX_LIGHTS {
+            return None;
+        }
+        if let Some(index) = self.slots.iter().position(Option::is_none) {
+            self.slots[index] = Some(light);
+            Some(LightId(index))
+        } else {
+            self.slots.push(Some(light));
+            Some(LightId(self.slots.len() - 1))
+        }
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        self.slots.get_mut(id.0).and_then(Option::take)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id.0).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.slots.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| light.as_ref().map(|light| (LightId(i), light)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (LightId, &mut Light)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, light)| light.as_mut().map(|light| (LightId(i), light)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|light| light.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// lights are packed to the front of the array in id order
+    pub fn to_raw(&self) -> Vec<LightRaw> {
+        self.iter().map(|(_, light)| light.to_raw()).collect()
+    }
+}
+
+/// fixed size light array bound as `light_info` (uniform) and `lights` (storage buffer)
+pub struct LightSet {
+    lights: Lights,
+    pub binding: Binding,
+}
+
+impl Deref for LightSet {
+    type Target = Lights;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lights
+    }
+}
+
+impl DerefMut for LightSet {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.lights
+    }
+}
+
+impl LightSet {
+    pub const MAX_LIGHTS: usize = 10;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let info = LightInfoRaw {
+            num_lights: 0,
+            _padding: [0; 3],
+        };
+        let lights = vec![<LightRaw as bytemuck::Zeroable>::zeroed(); Self::MAX_LIGHTS];
         let binding = BindingBuilder::new()
             .uniform_buffer(
-                "light",
-                &[raw],
+                "light_info",
+                &[info],
+                wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                false,
+            )
+            .storage_buffer_custom(
+                "lights",
+                &lights,
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
                 wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                 false,
+                true,
             )
             .build(device);
 
-        Self { raw, binding }
+        Self {
+            lights: Lights::new(),
+            binding,
+        }
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
-        // self.binding.write_buffer_at_index(queue, 0, 0, &[self.raw]);
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let lights = self.to_raw();
+        let info = LightInfoRaw {
+            num_lights: lights.len() as u32,
+            _padding: [0; 3],
+        };
         self.binding
-            .write_buffer_at_label(queue, "light", 0, &[self.raw]);
+            .write_buffer_at_label(queue, "light_info", 0, &[info]);
+        if !lights.is_empty() {
+            self.binding
+                .write_buffer_at_label(queue, "lights", 0, &lights);
+        }
     }
 
-    pub fn position(&mut self) -> &cgmath::Vector3<f32> {
-        &self.raw.position
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        self.binding.bind_group_layout()
     }
 
-    pub fn position_as_mut(&mut self) -> &mut cgmath::Vector3<f32> {
-        &mut self.raw.position
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.binding.bind_group()
     }
 }
 
-pub trait DrawLight<'a, 'b>
-where
-    'b: 'a,
-{
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_light_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) where
-        'b: 'a;
-
-    fn draw_light_model(
-        &mut self,
-        model: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'b Geom,
-        instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-}
+#[test]
+fn test_lights() {
+    let white = cgmath::Vector3::new(1.0, 1.0, 1.0);
+    let spot = Light::spot(
+        (0.0, 4.0, 0.0).into(),
+        (0.0, -2.0, 0.0).into(),
+        10.0,
+        cgmath::Deg(60.0),
+        cgmath::Deg(90.0),
+        white,
+    )
+    .intensity(2.0);
+    let raw = spot.to_raw();
+    assert_eq!(raw.position, cgmath::Vector4::new(0.0, 4.0, 0.0, 2.0));
+    // normalized, with the radius in w
+    assert_eq!(raw.direction, cgmath::Vector4::new(0.0, -1.0, 0.0, 10.0));
+    assert_eq!(raw.color, cgmath::Vector4::new(1.0, 1.0, 1.0, 2.0));
+    assert!((raw.cone.x - 0.5).abs() < 1e-6);
+    assert!(raw.cone.y.abs() < 1e-6);
+    let raw = Light::directional((0.0, 0.0, -3.0).into(), white).to_raw();
+    assert_eq!(raw.position.w, LightKind::DIRECTIONAL as f32);
+    assert_eq!(raw.direction, cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0));
 
-impl<'a, 'b> DrawLight<'a, 'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_mesh_instanced(mesh, 0..1, uniforms, light);
-    }
-
-    fn draw_light_mesh_instanced(
-        &mut self,
-        mesh: &'b Mesh,
-        instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
-        self.set_index_buffer(mesh.binding.buffers[1].slice(..));
-        self.set_bind_group(0, uniforms, &[]);
-        self.set_bind_group(1, light, &[]);
-        self.draw_indexed(0..mesh.indices.len() as u32, 0, instances);
-    }
-
-    fn draw_light_model(
-        &mut self,
-        model: &'b Geom,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, 0..1, uniforms, light);
-    }
-    fn draw_light_model_instanced(
-        &mut self,
-        model: &'b Geom,
-        instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        for mesh in &model.meshes {
-            self.draw_light_mesh_instanced(mesh, instances.clone(), uniforms, light);
-        }
+    let point = |x: f32| Light::point((x, 0.0, 0.0).into(), 5.0, white);
+    let mut lights = Lights::new();
+    let ids = (0..3)
+        .map(|i| lights.add(point(i as f32)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lights.remove(ids[1]), Some(point(1.0)));
+    assert_eq!(lights.remove(ids[1]), None);
+    assert_eq!(lights.get(ids[1]), None);
+    // packed without the removed light
+    let xs = lights
+        .to_raw()
+        .iter()
+        .map(|r| r.position.x)
+        .collect::<Vec<_>>();
+    assert_eq!(xs, vec![0.0, 2.0]);
+    // the slot is reused
+    assert_eq!(lights.add(point(4.0)), Some(ids[1]));
+    let xs = lights
+        .to_raw()
+        .iter()
+        .map(|r| r.position.x)
+        .collect::<Vec<_>>();
+    assert_eq!(xs, vec![0.0, 4.0, 2.0]);
+
+    while lights.len() < LightSet::MAX_LIGHTS {
+        assert!(lights.add(point(0.0)).is_some());
     }
+    assert_eq!(lights.add(point(0.0)), None);
+    assert_eq!(lights.len(), LightSet::MAX_LIGHTS);
+    lights.remove(ids[0]);
+    assert_eq!(lights.add(point(0.0)), Some(ids[0]));
 }
@@ -0,0 +1,213 @@
+//! 3D color lookup tables in the Adobe / Resolve `.cube` format
+//!
+//! only 3D tables are supported. entries are stored like the file: red changes fastest, then green,
+//! then blue, which is also the texel order of a 3D texture with (x, y, z) = (r, g, b)
+use anyhow::*;
+use nannou::math::cgmath::Vector3;
+use nannou::prelude::*;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    pub title: Option<String>,
+    pub size: usize,
+    pub domain_min: Vector3<f32>,
+    pub domain_max: Vector3<f32>,
+    /// `size^3` entries
+    pub data: Vec<Vector3<f32>>,
+}
+
+impl Lut3d {
+    /// maps every color to itself
+    pub fn identity(size: usize) -> Self {
+        let size = size.max(2);
+        let scale = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(Vector3::new(r as f32, g as f32, b as f32) * scale);
+                }
+            }
+        }
+        Self {
+            title: None,
+            size,
+            domain_min: Vector3::new(0.0, 0.0, 0.0),
+            domain_max: Vector3::new(1.0, 1.0, 1.0),
+            data,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = Vector3::new(0.0, 0.0, 0.0);
+        let mut domain_max = Vector3::new(1.0, 1.0, 1.0);
+        let mut data = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap();
+            let args = tokens.collect::<Vec<_>>();
+            match keyword {
+                "TITLE" => {
+                    let rest = line["TITLE".len()..].trim();
+                    title = Some(rest.trim_matches('"').to_owned());
+                }
+                "LUT_3D_SIZE" => {
+                    let n = args
+                        .first()
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .with_context(|| format!("Invalid LUT_3D_SIZE at line {}", number + 1))?;
+                    if n < 2 {
+                        bail!("LUT_3D_SIZE must be 2 or more: {}", n);
+                    }
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => bail!("1D LUTs are not supported"),
+                "DOMAIN_MIN" => domain_min = parse_triple(&args, number)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&args, number)?,
+                // Resolve's shorthand for the same domain on every channel
+                "LUT_3D_INPUT_RANGE" => {
+                    if args.len() != 2 {
+                        bail!("Invalid LUT_3D_INPUT_RANGE at line {}", number + 1);
+                    }
+                    let min = parse_float(args[0], number)?;
+                    let max = parse_float(args[1], number)?;
+                    domain_min = Vector3::new(min, min, min);
+                    domain_max = Vector3::new(max, max, max);
+                }
+                _ => {
+                    let mut values = vec![keyword];
+                    values.extend(args);
+                    data.push(parse_triple(&values, number)?);
+                }
+            }
+        }
+
+        let size = size.context("LUT_3D_SIZE is missing")?;
+        if data.len() != size * size * size {
+            bail!(
+                "LUT_3D_SIZE {} needs {} entries but found {}",
+                size,
+                size * size * size,
+                data.len()
+            );
+        }
+        if (0..3).any(|i| domain_min[i] >= domain_max[i]) {
+            bail!("DOMAIN_MIN must be less than DOMAIN_MAX");
+        }
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+
+    pub fn get(&self, r: usize, g: usize, b: usize) -> Vector3<f32> {
+        self.data[(b * self.size + g) * self.size + r]
+    }
+
+    /// trilinear interpolation. same as the color grading pass
+    pub fn apply(&self, color: Vector3<f32>) -> Vector3<f32> {
+        let max = (self.size - 1) as f32;
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            let t = (color[i] - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i]);
+            let x = t.max(0.0).min(1.0) * max;
+            let floor = x.floor().min(max - 1.0);
+            base[i] = floor as usize;
+            frac[i] = x - floor;
+        }
+        let lerp_r = |g, b| {
+            let c0 = self.get(base[0], g, b);
+            let c1 = self.get(base[0] + 1, g, b);
+            c0.lerp(c1, frac[0])
+        };
+        let lerp_g = |b| lerp_r(base[1], b).lerp(lerp_r(base[1] + 1, b), frac[1]);
+        lerp_g(base[2]).lerp(lerp_g(base[2] + 1), frac[2])
+    }
+}
+
+fn parse_float(token: &str, number: usize) -> Result<f32> {
+    token
+        .parse::<f32>()
+        .with_context(|| format!("Invalid number {} at line {}", token, number + 1))
+}
+
+fn parse_triple(tokens: &[&str], number: usize) -> Result<Vector3<f32>> {
+    if tokens.len() != 3 {
+        bail!("Expected 3 values at line {}", number + 1);
+    }
+    Ok(Vector3::new(
+        parse_float(tokens[0], number)?,
+        parse_float(tokens[1], number)?,
+        parse_float(tokens[2], number)?,
+    ))
+}
+
+#[test]
+fn test_lut_parse() {
+    let text = r#"
+# inverts the red channel
+TITLE "Invert Red"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0.0 0.0 0.0
+DOMAIN_MAX 1.0 1.0 1.0
+
+1.0 0.0 0.0
+0.0 0.0 0.0
+1.0 1.0 0.0
+0.0 1.0 0.0
+1.0 0.0 1.0
+0.0 0.0 1.0
+1.0 1.0 1.0
+0.0 1.0 1.0
+"#;
+    let lut = Lut3d::parse(text).unwrap();
+    assert_eq!(lut.title.as_deref(), Some("Invert Red"));
+    assert_eq!(lut.size, 2);
+    assert_eq!(lut.get(1, 0, 0), Vector3::new(0.0, 0.0, 0.0));
+    assert_eq!(lut.get(0, 1, 1), Vector3::new(1.0, 1.0, 1.0));
+    let mapped = lut.apply(Vector3::new(0.25, 0.5, 0.75));
+    assert!((mapped - Vector3::new(0.75, 0.5, 0.75)).magnitude() < 1e-5);
+
+    let identity = Lut3d::identity(17);
+    for &color in &[
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.3, 0.6, 0.9),
+        Vector3::new(1.0, 1.0, 1.0),
+    ] {
+        assert!((identity.apply(color) - color).magnitude() < 1e-5);
+    }
+    // clamped to the domain
+    assert!(
+        (identity.apply(Vector3::new(2.0, -1.0, 0.5)) - Vector3::new(1.0, 0.0, 0.5)).magnitude()
+            < 1e-5
+    );
+
+    let ranged = Lut3d::parse("LUT_3D_INPUT_RANGE 0.0 2.0\nLUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n").unwrap();
+    assert_eq!(ranged.domain_max, Vector3::new(2.0, 2.0, 2.0));
+    assert!(
+        (ranged.apply(Vector3::new(1.0, 1.0, 1.0)) - Vector3::new(0.5, 0.5, 0.5)).magnitude()
+            < 1e-5
+    );
+
+    assert!(Lut3d::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+    assert!(Lut3d::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+    assert!(Lut3d::parse("0 0 0\n").is_err());
+    assert!(Lut3d::parse("LUT_3D_SIZE 2\n0 0 x\n").is_err());
+}
@@ -0,0 +1,297 @@
+//! records of capture sessions for reproducing a frame later
+//!
+//! a `Manifest` lists the sketch, the frame size, the seed, the command line and the exposed
+//! parameters of a session with the time and camera of every frame, and is saved as JSON next to
+//! the frames. the key fields of a frame can also be embedded into its PNG as tEXt chunks, which
+//! `read_png_text` reads back
+use anyhow::*;
+use nannou::math::cgmath::Deg;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::camera::Camera;
+
+/// longest keyword of a tEXt chunk
+pub const MAX_KEYWORD_LEN: usize = 79;
+
+/// what is needed to place a `Camera` again. the projection tile isn't recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub target: Option<[f32; 3]>,
+    /// yaw and pitch in degrees, if the camera is rotated instead of looking at `target`
+    #[serde(default)]
+    pub rotation: Option<[f32; 2]>,
+    pub up: [f32; 3],
+    /// degrees
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl CameraState {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            target: camera.target.map(Into::into),
+            rotation: camera
+                .rotation
+                .as_ref()
+                .map(|r| [Deg::from(r.yaw).0, Deg::from(r.pitch).0]),
+            up: camera.up.into(),
+            fovy: Deg::from(camera.projection.fovy).0,
+            znear: camera.projection.znear,
+            zfar: camera.projection.zfar,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameRecord {
+    pub frame: u32,
+    /// scene time in seconds
+    pub time: f64,
+    /// milliseconds since the unix epoch when the frame was rendered
+    pub timestamp: u64,
+    /// the image of the frame. `None` if it only went into a video
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub camera: Option<CameraState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub sketch: String,
+    /// milliseconds since the unix epoch when the session started
+    pub started: u64,
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    /// command line of the session, including the program
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, String>,
+    #[serde(default)]
+    pub frames: Vec<FrameRecord>,
+}
+
+impl Manifest {
+    pub fn new(sketch: &str, width: u32, height: u32) -> Self {
+        Self {
+            sketch: sketch.to_string(),
+            started: now(),
+            width,
+            height,
+            seed: 0,
+            args: Vec::new(),
+            parameters: BTreeMap::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = String>>(mut self, args: I) -> Self {
+        self.args = args.into_iter().collect();
+        self
+    }
+
+    /// replaces the value of `name`
+    pub fn parameter(mut self, name: &str, value: impl ToString) -> Self {
+        self.set_parameter(name, value);
+        self
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: impl ToString) {
+        self.parameters.insert(name.to_string(), value.to_string());
+    }
+
+    /// stamped with the current time
+    pub fn push_frame(
+        &mut self,
+        frame: u32,
+        time: f64,
+        path: Option<PathBuf>,
+        camera: Option<CameraState>,
+    ) {
+        self.frames.push(FrameRecord {
+            frame,
+            time,
+            timestamp: now(),
+            path,
+            camera,
+        });
+    }
+
+    /// key fields of `record` for the tEXt chunks of its image
+    pub fn text(&self, record: &FrameRecord) -> Vec<(String, String)> {
+        let mut text = vec![
+            ("Software".to_string(), self.sketch.clone()),
+            ("Frame".to_string(), record.frame.to_string()),
+            ("Time".to_string(), record.time.to_string()),
+            (
+                "Size".to_string(),
+                format!("{}x{}", self.width, self.height),
+            ),
+            ("Seed".to_string(), self.seed.to_string()),
+        ];
+        if let Some(camera) = &record.camera {
+            let camera = serde_json::to_string(camera).unwrap_or_default();
+            text.push(("Camera".to_string(), camera));
+        }
+        if !self.args.is_empty() {
+            text.push(("Command".to_string(), self.args.join(" ")));
+        }
+        text
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let src = read_to_string(path)?;
+        serde_json::from_str(&src).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// data of a tEXt chunk. the text is Latin-1, so other characters are replaced by '?'
+pub fn text_chunk(keyword: &str, text: &str) -> Result<Vec<u8>> {
+    let latin1 = |s: &str| {
+        s.chars()
+            .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+            .collect::<Vec<_>>()
+    };
+    let mut data = latin1(keyword);
+    if data.is_empty() || data.len() > MAX_KEYWORD_LEN || data.contains(&0) {
+        bail!("Invalid tEXt keyword {:?}", keyword);
+    }
+    data.push(0);
+    data.extend(latin1(text).into_iter().filter(|&b| b != 0));
+    Ok(data)
+}
+
+/// RGBA PNG with a tEXt chunk per `(keyword, text)` before the pixels
+pub fn write_png<W: Write>(
+    writer: W,
+    image: &image::RgbaImage,
+    text: &[(String, String)],
+) -> Result<()> {
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    for (keyword, text) in text {
+        writer.write_chunk(*b"tEXt", &text_chunk(keyword, text)?)?;
+    }
+    writer.write_image_data(image.as_raw())?;
+    Ok(())
+}
+
+/// creates the directory of `path`
+pub fn save_png(image: &image::RgbaImage, path: &Path, text: &[(String, String)]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = File::create(path)?;
+    write_png(BufWriter::new(file), image, text)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// `(keyword, text)` of the tEXt chunks of a PNG file
+pub fn read_png_text(data: &[u8]) -> Result<Vec<(String, String)>> {
+    const SIGNATURE: &[u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+    if !data.starts_with(SIGNATURE) {
+        bail!("Not a PNG");
+    }
+    let mut text = Vec::new();
+    let mut rest = &data[SIGNATURE.len()..];
+    // length, type, data and crc
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let chunk_type = &rest[4..8];
+        let chunk = rest.get(8..8 + len).context("Truncated PNG chunk")?;
+        if chunk_type == b"tEXt" {
+            let sep = chunk.iter().position(|&b| b == 0).context("Invalid tEXt")?;
+            let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect::<String>();
+            text.push((latin1(&chunk[..sep]), latin1(&chunk[sep + 1..])));
+        }
+        if chunk_type == b"IEND" {
+            break;
+        }
+        rest = &rest[(12 + len).min(rest.len())..];
+    }
+    Ok(text)
+}
+
+#[test]
+fn test_manifest() {
+    let mut manifest = Manifest::new("sketch", 320, 240)
+        .seed(42)
+        .args(vec!["app".to_string(), "--offline".to_string()])
+        .parameter("fps", 30.0)
+        .parameter("samples", 4);
+    let camera = CameraState {
+        position: [0.0, 1.0, 10.0],
+        target: Some([0.0; 3]),
+        rotation: None,
+        up: [0.0, 1.0, 0.0],
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let path = PathBuf::from("frame_00003.png");
+    manifest.push_frame(3, 0.1, Some(path), Some(camera));
+    let record = manifest.frames[0].clone();
+    assert_eq!(manifest.parameters["fps"], "30");
+    assert!(record.timestamp >= manifest.started);
+
+    let json = serde_json::to_string(&manifest).unwrap();
+    assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), manifest);
+    // fields added later default when older manifests are loaded
+    let old = r#"{"sketch": "s", "started": 0, "width": 1, "height": 1, "seed": 0}"#;
+    assert!(serde_json::from_str::<Manifest>(old)
+        .unwrap()
+        .frames
+        .is_empty());
+
+    let text = manifest.text(&record);
+    assert!(text.contains(&("Frame".to_string(), "3".to_string())));
+    assert!(text.contains(&("Seed".to_string(), "42".to_string())));
+    assert!(text.contains(&("Command".to_string(), "app --offline".to_string())));
+
+    let image = image::RgbaImage::from_fn(4, 3, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+    let mut png = Vec::new();
+    write_png(&mut png, &image, &text).unwrap();
+    assert_eq!(read_png_text(&png).unwrap(), text);
+    let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(decoded, image);
+
+    assert_eq!(text_chunk("Title", "é€").unwrap(), b"Title\0\xe9?");
+    assert!(text_chunk("", "empty").is_err());
+    assert!(text_chunk(&"k".repeat(80), "long").is_err());
+    assert!(read_png_text(b"GIF89a").is_err());
+}
@@ -1,7 +1,194 @@
-use super::binding::Binding;
+use anyhow::*;
+use nannou::math::cgmath;
+use nannou::prelude::*;
+use std::path::PathBuf;
+
+use super::binding::{Binding, BindingBuilder};
+use super::brdf::ToneMapping;
+use super::texture::TextureSet;
+
+/// selects the pipeline which draws meshes with this material
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shading {
+    /// `diffuse_texture`, `normal_texture` (shader.frag)
+    BlinnPhong,
+    /// `pbr_factors` and `PbrMaps` (pbr.frag)
+    Pbr,
+}
 
 pub struct Material {
     pub name: String,
+    pub shading: Shading,
     // TODO: make texture binding to Arc and refer it??
     pub binding: Binding,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PbrFactors {
+    /// multiplied to the base color map (linear)
+    pub base_color: cgmath::Vector4<f32>,
+    /// multiplied to the metallic map
+    pub metallic: f32,
+    /// multiplied to the roughness map
+    pub roughness: f32,
+    /// 0.0: ignore the occlusion map, 1.0: full occlusion
+    pub occlusion_strength: f32,
+    /// multiplied to the emissive map (linear)
+    pub emissive: cgmath::Vector3<f32>,
+    pub exposure: f32,
+    /// `None` when the scene is tone mapped by the post stack
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self {
+            base_color: cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0),
+            metallic: 1.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            emissive: cgmath::Vector3::zero(),
+            exposure: 1.0,
+            tone_mapping: ToneMapping::None,
+        }
+    }
+}
+
+impl PbrFactors {
+    pub fn to_raw(&self) -> PbrFactorsRaw {
+        PbrFactorsRaw {
+            base_color: self.base_color,
+            emissive: self.emissive.extend(self.exposure),
+            params: cgmath::Vector4::new(
+                self.metallic,
+                self.roughness,
+                self.occlusion_strength,
+                self.tone_mapping as u32 as f32,
+            ),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PbrFactorsRaw {
+    pub base_color: cgmath::Vector4<f32>,
+    /// rgb: emissive, a: exposure
+    pub emissive: cgmath::Vector4<f32>,
+    /// x: metallic, y: roughness, z: occlusion strength, w: tone mapping
+    pub params: cgmath::Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for PbrFactorsRaw {}
+unsafe impl bytemuck::Pod for PbrFactorsRaw {}
+
+/// texture paths of a PBR material. missing maps are replaced by 1x1 textures which
+/// leave the factors as they are
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PbrMaps {
+    /// sRGB
+    pub base_color: Option<PathBuf>,
+    /// tangent space, linear
+    pub normal: Option<PathBuf>,
+    /// R channel, linear
+    pub metallic: Option<PathBuf>,
+    /// R channel, linear
+    pub roughness: Option<PathBuf>,
+    /// R channel, linear
+    pub occlusion: Option<PathBuf>,
+    /// sRGB
+    pub emissive: Option<PathBuf>,
+}
+
+impl Material {
+    /// same as the layout of every `Material::pbr`, without creating a material
+    pub fn pbr_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut builder =
+            wgpu::BindGroupLayoutBuilder::new().uniform_buffer(wgpu::ShaderStage::FRAGMENT, false);
+        // base color, normal, metallic, roughness, occlusion and emissive
+        for _ in 0..6 {
+            builder = builder
+                .sampled_texture(
+                    wgpu::ShaderStage::FRAGMENT,
+                    false,
+                    wgpu::TextureViewDimension::D2,
+                    wgpu::TextureComponentType::Float,
+                )
+                .sampler(wgpu::ShaderStage::FRAGMENT);
+        }
+        builder.build(device)
+    }
+
+    pub fn pbr(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        factors: &PbrFactors,
+        maps: &PbrMaps,
+    ) -> Result<Self> {
+        let srgb = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let linear = wgpu::TextureFormat::Rgba8Unorm;
+        let load = |path: &Option<PathBuf>, default: [u8; 4], format| -> Result<TextureSet> {
+            let rgba = match path {
+                Some(path) => image::open(path)
+                    .with_context(|| format!("Failed to load {}", path.display()))?
+                    .to_rgba8(),
+                None => image::RgbaImage::from_pixel(1, 1, image::Rgba(default)),
+            };
+            Ok(TextureSet::from_rgba(device, queue, &rgba, format))
+        };
+        let textures = vec![
+            (
+                "base_color_texture",
+                load(&maps.base_color, [255; 4], srgb)?,
+            ),
+            (
+                "normal_texture",
+                load(&maps.normal, [128, 128, 255, 255], linear)?,
+            ),
+            ("metallic_texture", load(&maps.metallic, [255; 4], linear)?),
+            (
+                "roughness_texture",
+                load(&maps.roughness, [255; 4], linear)?,
+            ),
+            (
+                "occlusion_texture",
+                load(&maps.occlusion, [255; 4], linear)?,
+            ),
+            ("emissive_texture", load(&maps.emissive, [255; 4], srgb)?),
+        ];
+
+        let mut builder = BindingBuilder::new().uniform_buffer(
+            "pbr_factors",
+            &[factors.to_raw()],
+            wgpu::ShaderStage::FRAGMENT,
+            false,
+        );
+        for (label, texture) in textures {
+            builder = builder.texture(
+                label,
+                texture.texture,
+                texture.view,
+                texture.sampler,
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+            );
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            shading: Shading::Pbr,
+            binding: builder.build(device),
+        })
+    }
+
+    pub fn set_pbr_factors(&mut self, queue: &wgpu::Queue, factors: &PbrFactors) {
+        assert_eq!(
+            self.shading,
+            Shading::Pbr,
+            "{} is not a PBR material",
+            self.name
+        );
+        self.binding
+            .write_buffer_at_label(queue, "pbr_factors", 0, &[factors.to_raw()]);
+    }
+}
@@ -0,0 +1,296 @@
+//! draws meshes with the bind groups a pipeline layout expects
+//!
+//! a `DrawLayout` records which kind of bind group is at which set of a pipeline layout. it is
+//! built together with the bind group layouts the pipeline is created with, so the sets of a draw
+//! are looked up instead of being hard coded. `MeshDraw` collects the bind groups by kind and
+//! only binds the ones in the layout, so the same draw works with pipelines which e.g. read the
+//! camera or the environment from set 0
+//!
+//! ```ignore
+//! render_pass
+//!     .draw_geom(&geom)
+//!     .layout(&layout)
+//!     .camera(camera.binding.bind_group())
+//!     .light(shadow_maps.bind_group())
+//!     .instances(instances.binding.bind_group(), 0..num_instances)
+//!     .submit();
+//! ```
+use nannou::prelude::*;
+use std::ops::Range;
+
+use super::culling::DrawIndexedIndirect;
+use super::geom::Geom;
+use super::material::Material;
+use super::mesh::Mesh;
+
+/// bind groups per pipeline in wgpu's default limits
+pub const MAX_BIND_GROUPS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BindGroupKind {
+    /// the view uniforms and whatever is bound with them, e.g. the ambient occlusion
+    Camera,
+    /// the view uniforms with the environment maps
+    Environment,
+    /// the model matrices read with `gl_InstanceIndex`
+    Instances,
+    /// the lights of a `LightSet`, or the lights and shadows of `ShadowMaps`
+    Light,
+    /// the material of each mesh, `Geom::materials[mesh.material_id]` unless overridden
+    Material,
+    Custom(&'static str),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DrawLayout {
+    kinds: [Option<BindGroupKind>; MAX_BIND_GROUPS],
+}
+
+impl DrawLayout {
+    /// the layout of the lit mesh pipelines
+    pub const STANDARD: Self = Self {
+        kinds: [
+            Some(BindGroupKind::Camera),
+            Some(BindGroupKind::Instances),
+            Some(BindGroupKind::Light),
+            Some(BindGroupKind::Material),
+        ],
+    };
+
+    /// `kinds[i]` is at set `i`
+    pub fn new(kinds: &[BindGroupKind]) -> Self {
+        assert!(
+            kinds.len() <= MAX_BIND_GROUPS,
+            "pipelines have up to {} bind groups",
+            MAX_BIND_GROUPS
+        );
+        let mut layout = Self {
+            kinds: [None; MAX_BIND_GROUPS],
+        };
+        for (slot, &kind) in kinds.iter().enumerate() {
+            layout.kinds[slot] = Some(kind);
+        }
+        layout
+    }
+
+    pub fn slot(&self, kind: BindGroupKind) -> Option<u32> {
+        self.kinds
+            .iter()
+            .position(|k| *k == Some(kind))
+            .map(|slot| slot as u32)
+    }
+
+    /// kinds with their sets
+    pub fn slots(&self) -> impl Iterator<Item = (u32, BindGroupKind)> + '_ {
+        self.kinds
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, kind)| kind.map(|kind| (slot as u32, kind)))
+    }
+}
+
+impl Default for DrawLayout {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// bind group layouts of a pipeline with their kinds
+#[derive(Default)]
+pub struct DrawLayoutBuilder<'l> {
+    kinds: Vec<BindGroupKind>,
+    bind_group_layouts: Vec<&'l wgpu::BindGroupLayout>,
+}
+
+impl<'l> DrawLayoutBuilder<'l> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// at the next set
+    pub fn group(mut self, kind: BindGroupKind, layout: &'l wgpu::BindGroupLayout) -> Self {
+        self.kinds.push(kind);
+        self.bind_group_layouts.push(layout);
+        self
+    }
+
+    /// for `PipelineCache::render_pipeline`
+    pub fn bind_group_layouts(&self) -> &[&'l wgpu::BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+
+    pub fn build(&self) -> DrawLayout {
+        DrawLayout::new(&self.kinds)
+    }
+}
+
+pub struct MeshDraw<'p, 'a> {
+    pass: &'p mut wgpu::RenderPass<'a>,
+    layout: DrawLayout,
+    meshes: &'a [Mesh],
+    /// indexed by `Mesh::material_id`
+    materials: &'a [Material],
+    material: Option<&'a Material>,
+    bind_groups: Vec<(BindGroupKind, &'a wgpu::BindGroup)>,
+    instances: Range<u32>,
+    indirect: Option<(&'a wgpu::Buffer, wgpu::BufferAddress)>,
+}
+
+impl<'p, 'a> MeshDraw<'p, 'a> {
+    fn new(
+        pass: &'p mut wgpu::RenderPass<'a>,
+        meshes: &'a [Mesh],
+        materials: &'a [Material],
+    ) -> Self {
+        Self {
+            pass,
+            layout: DrawLayout::STANDARD,
+            meshes,
+            materials,
+            material: None,
+            bind_groups: Vec::new(),
+            instances: 0..1,
+            indirect: None,
+        }
+    }
+
+    /// of the current pipeline. `DrawLayout::STANDARD` by default
+    pub fn layout(mut self, layout: &DrawLayout) -> Self {
+        self.layout = *layout;
+        self
+    }
+
+    /// ignored if the layout doesn't have `kind`
+    pub fn bind_group(mut self, kind: BindGroupKind, bind_group: &'a wgpu::BindGroup) -> Self {
+        self.bind_groups.retain(|(k, _)| *k != kind);
+        self.bind_groups.push((kind, bind_group));
+        self
+    }
+
+    pub fn camera(self, bind_group: &'a wgpu::BindGroup) -> Self {
+        self.bind_group(BindGroupKind::Camera, bind_group)
+    }
+
+    pub fn environment(self, bind_group: &'a wgpu::BindGroup) -> Self {
+        self.bind_group(BindGroupKind::Environment, bind_group)
+    }
+
+    pub fn light(self, bind_group: &'a wgpu::BindGroup) -> Self {
+        self.bind_group(BindGroupKind::Light, bind_group)
+    }
+
+    /// instead of the materials of the geom
+    pub fn material(mut self, material: &'a Material) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn instances(self, bind_group: &'a wgpu::BindGroup, range: Range<u32>) -> Self {
+        self.bind_group(BindGroupKind::Instances, bind_group)
+            .instance_range(range)
+    }
+
+    /// `0..1` by default
+    pub fn instance_range(mut self, range: Range<u32>) -> Self {
+        self.instances = range;
+        self
+    }
+
+    /// `DrawIndexedIndirect` of the i-th mesh at `offset + i * DrawIndexedIndirect::SIZE` instead
+    /// of the instance range
+    pub fn indirect(mut self, buffer: &'a wgpu::Buffer, offset: wgpu::BufferAddress) -> Self {
+        self.indirect = Some((buffer, offset));
+        self
+    }
+
+    /// binds the groups in the layout and draws the meshes. groups which weren't given are left
+    /// as they are, e.g. to bind them once for a batch of draws
+    pub fn submit(self) {
+        let Self {
+            pass,
+            layout,
+            meshes,
+            materials,
+            material,
+            bind_groups,
+            instances,
+            indirect,
+        } = self;
+
+        for (slot, kind) in layout.slots() {
+            let bind_group = match kind {
+                BindGroupKind::Material => material.map(|m| m.binding.bind_group()),
+                _ => bind_groups
+                    .iter()
+                    .find(|(k, _)| *k == kind)
+                    .map(|(_, bind_group)| *bind_group),
+            };
+            if let Some(bind_group) = bind_group {
+                pass.set_bind_group(slot, bind_group, &[]);
+            }
+        }
+
+        let material_slot = layout
+            .slot(BindGroupKind::Material)
+            .filter(|_| material.is_none());
+        for (i, mesh) in meshes.iter().enumerate() {
+            if let Some(slot) = material_slot {
+                if let Some(material) = materials.get(mesh.material_id) {
+                    pass.set_bind_group(slot, material.binding.bind_group(), &[]);
+                }
+            }
+            pass.set_vertex_buffer(0, mesh.binding.buffers[0].slice(..));
+            pass.set_index_buffer(mesh.binding.buffers[1].slice(..));
+            match indirect {
+                Some((buffer, offset)) => pass.draw_indexed_indirect(
+                    buffer,
+                    offset + i as wgpu::BufferAddress * DrawIndexedIndirect::SIZE,
+                ),
+                None => pass.draw_indexed(0..mesh.indices.len() as u32, 0, instances.clone()),
+            }
+        }
+    }
+}
+
+pub trait DrawMesh<'a> {
+    fn draw_mesh<'p>(&'p mut self, mesh: &'a Mesh) -> MeshDraw<'p, 'a>;
+    /// every mesh with its own material
+    fn draw_geom<'p>(&'p mut self, geom: &'a Geom) -> MeshDraw<'p, 'a>;
+    /// only binds the groups on `submit`
+    fn bind_groups<'p>(&'p mut self) -> MeshDraw<'p, 'a>;
+}
+
+impl<'a> DrawMesh<'a> for wgpu::RenderPass<'a> {
+    fn draw_mesh<'p>(&'p mut self, mesh: &'a Mesh) -> MeshDraw<'p, 'a> {
+        MeshDraw::new(self, std::slice::from_ref(mesh), &[])
+    }
+
+    fn draw_geom<'p>(&'p mut self, geom: &'a Geom) -> MeshDraw<'p, 'a> {
+        MeshDraw::new(self, &geom.meshes, &geom.materials)
+    }
+
+    fn bind_groups<'p>(&'p mut self) -> MeshDraw<'p, 'a> {
+        MeshDraw::new(self, &[], &[])
+    }
+}
+
+#[test]
+fn test_draw_layout() {
+    use BindGroupKind::*;
+
+    assert_eq!(DrawLayout::default().slot(Camera), Some(0));
+    assert_eq!(DrawLayout::default().slot(Material), Some(3));
+    assert_eq!(DrawLayout::default().slot(Environment), None);
+
+    let light = DrawLayout::new(&[Camera, Light]);
+    assert_eq!(light.slot(Light), Some(1));
+    assert_eq!(light.slot(Instances), None);
+    assert_eq!(
+        light.slots().collect::<Vec<_>>(),
+        vec![(0, Camera), (1, Light)]
+    );
+
+    let custom = DrawLayout::new(&[Environment, Custom("shadows")]);
+    assert_eq!(custom.slot(Custom("shadows")), Some(1));
+    assert_eq!(custom.slot(Custom("other")), None);
+}
@@ -3,15 +3,36 @@
 
 use nannou::prelude::*;
 
+pub mod accumulation;
+pub mod batch;
 pub mod binding;
+pub mod brdf;
 pub mod camera;
+pub mod camera_path;
+pub mod capture;
+pub mod compute;
+pub mod contact_sheet;
+pub mod culling;
+pub mod deferred;
 pub mod draw;
+pub mod environment;
 pub mod geom;
+pub mod golden;
+pub mod graph;
 pub mod instance;
 pub mod light;
+pub mod lut;
+pub mod manifest;
 pub mod material;
 pub mod mesh;
+pub mod mesh_draw;
+pub mod particle;
+pub mod pipeline;
+pub mod post;
+pub mod shadow;
+pub mod ssao;
 pub mod texture;
+pub mod tiled;
 pub mod vertex;
 
 pub struct PipelineLayoutBuilder<'a> {
@@ -77,6 +98,7 @@ impl<'a> ComputePipelineBuilder<'a> {
     }
 }
 
+/// `texture` may be multisampled. it is resolved by sampling, so it must be `SAMPLED` then
 pub fn capture_frame(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
@@ -84,6 +106,11 @@ pub fn capture_frame(
     texture: &wgpu::Texture,
     path: String,
 ) {
+    assert!(
+        texture.sample_count() == 1 || texture.usage().contains(wgpu::TextureUsage::SAMPLED),
+        "multisampled textures must have TextureUsage::SAMPLED to be captured"
+    );
+
     // Create the texture capturer.
     let texture_capturer = wgpu::TextureCapturer::default();
 
@@ -0,0 +1,445 @@
+//! particle simulation rules shared by `shaders/particle.comp` and the CPU reference `step`
+//!
+//! every particle belongs to the emitter `index % emitters`. a particle is unborn while its age
+//! is negative, alive while the age is less than its lifetime, and respawned by its emitter after
+//! that. the randomness of a respawn only depends on the seed, the frame and the index, so the
+//! CPU and GPU simulations of the same config see the same particles
+use nannou::math::cgmath::{Vector3, Vector4};
+use nannou::prelude::*;
+
+use super::vertex::VertexDescription;
+
+/// must be same as `shaders/particle.comp`
+pub const MAX_EMITTERS: usize = 4;
+pub const MAX_ATTRACTORS: usize = 4;
+/// `local_size_x` of `shaders/particle.comp`
+pub const WORKGROUP_SIZE: u32 = 64;
+
+/// storage buffer element. also the per instance vertex of the billboards
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Particle {
+    /// xyz: position, w: age
+    pub position: Vector4<f32>,
+    /// xyz: velocity, w: lifetime
+    pub velocity: Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for Particle {}
+unsafe impl bytemuck::Pod for Particle {}
+
+impl Particle {
+    pub fn age(&self) -> f32 {
+        self.position.w
+    }
+
+    pub fn lifetime(&self) -> f32 {
+        self.velocity.w
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.age() >= 0.0 && self.age() < self.lifetime()
+    }
+}
+
+impl VertexDescription for Particle {
+    fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                // position and age
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                // velocity and lifetime
+                wgpu::VertexAttributeDescriptor {
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Emitter {
+    pub position: Vector3<f32>,
+    /// particles spawn uniformly in this sphere
+    pub radius: f32,
+    pub velocity: Vector3<f32>,
+    /// radius of the random velocity added to `velocity`
+    pub spread: f32,
+    pub lifetime_min: f32,
+    pub lifetime_max: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zero(),
+            radius: 0.1,
+            velocity: Vector3::new(0.0, 3.0, 0.0),
+            spread: 1.0,
+            lifetime_min: 2.0,
+            lifetime_max: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Attractor {
+    pub position: Vector3<f32>,
+    /// negative repels
+    pub strength: f32,
+    /// softens the force near the center
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forces {
+    pub gravity: Vector3<f32>,
+    /// fraction of the velocity lost per second
+    pub drag: f32,
+    pub curl_strength: f32,
+    /// spatial frequency of the curl noise
+    pub curl_scale: f32,
+    /// up to `MAX_ATTRACTORS`
+    pub attractors: Vec<Attractor>,
+}
+
+impl Default for Forces {
+    fn default() -> Self {
+        Self {
+            gravity: Vector3::new(0.0, -1.0, 0.0),
+            drag: 0.1,
+            curl_strength: 1.0,
+            curl_scale: 0.5,
+            attractors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleConfig {
+    pub num_particles: u32,
+    /// up to `MAX_EMITTERS`. at least one
+    pub emitters: Vec<Emitter>,
+    pub forces: Forces,
+    /// world space size of the billboards
+    pub size: f32,
+    /// HDR color added by a billboard at the start of its life
+    pub color: Vector3<f32>,
+    pub seed: u32,
+}
+
+impl Default for ParticleConfig {
+    fn default() -> Self {
+        Self {
+            num_particles: 16384,
+            emitters: vec![Emitter::default()],
+            forces: Forces::default(),
+            size: 0.05,
+            color: Vector3::new(2.0, 1.2, 0.6),
+            seed: 0,
+        }
+    }
+}
+
+impl ParticleConfig {
+    /// every particle is unborn. the births are spread over the longest lifetime of its emitter
+    /// so that the emitters start with a steady rate instead of a burst
+    pub fn initial_particles(&self) -> Vec<Particle> {
+        let emitters = self.emitters();
+        (0..self.num_particles)
+            .map(|i| {
+                let emitter = &emitters[i as usize % emitters.len()];
+                let delay = i as f32 / self.num_particles as f32 * emitter.lifetime_max;
+                Particle {
+                    position: emitter.position.extend(-delay),
+                    velocity: Vector4::zero(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_raw(&self, time: f32, dt: f32, frame: u32) -> ParticleSimRaw {
+        let emitters = self.emitters();
+        let mut raw: ParticleSimRaw = bytemuck::Zeroable::zeroed();
+        raw.counts = [
+            self.num_particles,
+            emitters.len() as u32,
+            self.forces.attractors.len().min(MAX_ATTRACTORS) as u32,
+            self.seed,
+        ];
+        raw.frame[0] = frame;
+        raw.gravity = self.forces.gravity.extend(self.forces.drag);
+        raw.curl = Vector4::new(self.forces.curl_strength, self.forces.curl_scale, time, dt);
+        for (raw, e) in raw.emitters.iter_mut().zip(emitters.iter()) {
+            *raw = EmitterRaw {
+                position: e.position.extend(e.radius),
+                velocity: e.velocity.extend(e.spread),
+                lifetime: Vector4::new(e.lifetime_min, e.lifetime_max, 0.0, 0.0),
+            };
+        }
+        for (raw, a) in raw.attractors.iter_mut().zip(self.forces.attractors.iter()) {
+            *raw = AttractorRaw {
+                position: a.position.extend(a.strength),
+                params: Vector4::new(a.radius, 0.0, 0.0, 0.0),
+            };
+        }
+        raw
+    }
+
+    pub fn to_render_raw(&self) -> ParticleRenderRaw {
+        ParticleRenderRaw {
+            params: Vector4::new(self.size, self.color.x, self.color.y, self.color.z),
+        }
+    }
+
+    fn emitters(&self) -> Vec<Emitter> {
+        let mut emitters = self.emitters.clone();
+        emitters.truncate(MAX_EMITTERS);
+        if emitters.is_empty() {
+            emitters.push(Emitter::default());
+        }
+        emitters
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct EmitterRaw {
+    /// xyz: position, w: radius
+    pub position: Vector4<f32>,
+    /// xyz: velocity, w: spread
+    pub velocity: Vector4<f32>,
+    /// x: min, y: max
+    pub lifetime: Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for EmitterRaw {}
+unsafe impl bytemuck::Pod for EmitterRaw {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AttractorRaw {
+    /// xyz: position, w: strength
+    pub position: Vector4<f32>,
+    /// x: radius
+    pub params: Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for AttractorRaw {}
+unsafe impl bytemuck::Pod for AttractorRaw {}
+
+/// must be same as `SimParams` in `shaders/particle.comp`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ParticleSimRaw {
+    /// particles, emitters, attractors, seed
+    pub counts: [u32; 4],
+    /// x: frame
+    pub frame: [u32; 4],
+    /// xyz: gravity, w: drag
+    pub gravity: Vector4<f32>,
+    /// x: strength, y: scale, z: time, w: dt
+    pub curl: Vector4<f32>,
+    pub emitters: [EmitterRaw; MAX_EMITTERS],
+    pub attractors: [AttractorRaw; MAX_ATTRACTORS],
+}
+unsafe impl bytemuck::Zeroable for ParticleSimRaw {}
+unsafe impl bytemuck::Pod for ParticleSimRaw {}
+
+/// must be same as `ParticleRenderParams` in `shaders/particle.vert` and `shaders/particle.frag`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ParticleRenderRaw {
+    /// x: size, yzw: color
+    pub params: Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for ParticleRenderRaw {}
+unsafe impl bytemuck::Pod for ParticleRenderRaw {}
+
+/// PCG hash. must be same as `shaders/particle.comp`
+pub fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+/// [0, 1)
+fn random(state: &mut u32) -> f32 {
+    *state = hash(*state);
+    (*state >> 8) as f32 / 16_777_216.0
+}
+
+fn random_in_sphere(state: &mut u32) -> Vector3<f32> {
+    let z = random(state) * 2.0 - 1.0;
+    let phi = random(state) * 2.0 * std::f32::consts::PI;
+    let r = random(state).powf(1.0 / 3.0);
+    let xy = (1.0 - z * z).max(0.0).sqrt();
+    Vector3::new(xy * phi.cos(), xy * phi.sin(), z) * r
+}
+
+/// curl of the potential (sin(sy + t) cos(sz), sin(sz + t) cos(sx), sin(sx + t) cos(sy)),
+/// divided by the scale so that the strength doesn't depend on it. divergence free
+pub fn curl_noise(p: Vector3<f32>, scale: f32, time: f32) -> Vector3<f32> {
+    let (x, y, z) = (p.x * scale, p.y * scale, p.z * scale);
+    let dzdy = -(x + time).sin() * y.sin();
+    let dydz = (z + time).cos() * x.cos();
+    let dxdz = -(y + time).sin() * z.sin();
+    let dzdx = (x + time).cos() * y.cos();
+    let dydx = -(z + time).sin() * x.sin();
+    let dxdy = (y + time).cos() * z.cos();
+    Vector3::new(dzdy - dydz, dxdz - dzdx, dydx - dxdy)
+}
+
+fn respawn(index: u32, raw: &ParticleSimRaw) -> Particle {
+    let mut state = hash(index ^ hash(raw.frame[0] ^ hash(raw.counts[3])));
+    let emitter = &raw.emitters[(index % raw.counts[1].max(1)) as usize];
+    let position = emitter.position.truncate() + random_in_sphere(&mut state) * emitter.position.w;
+    let velocity = emitter.velocity.truncate() + random_in_sphere(&mut state) * emitter.velocity.w;
+    let t = random(&mut state);
+    let lifetime = emitter.lifetime.x + (emitter.lifetime.y - emitter.lifetime.x) * t;
+    Particle {
+        position: position.extend(0.0),
+        velocity: velocity.extend(lifetime),
+    }
+}
+
+/// CPU reference of one dispatch of `shaders/particle.comp`
+pub fn step(particles: &mut [Particle], raw: &ParticleSimRaw) {
+    let dt = raw.curl.w;
+    for (index, particle) in particles.iter_mut().enumerate() {
+        let mut position = particle.position.truncate();
+        let mut velocity = particle.velocity.truncate();
+        let age = particle.age() + dt;
+        let lifetime = particle.lifetime();
+        if age < 0.0 {
+            particle.position.w = age;
+            continue;
+        }
+        if age >= lifetime {
+            *particle = respawn(index as u32, raw);
+            continue;
+        }
+
+        let mut acceleration = raw.gravity.truncate();
+        acceleration += curl_noise(position, raw.curl.y, raw.curl.z) * raw.curl.x;
+        for attractor in &raw.attractors[..raw.counts[2] as usize] {
+            let d = attractor.position.truncate() - position;
+            let r2 = d.magnitude2() + attractor.params.x * attractor.params.x;
+            acceleration += d * (attractor.position.w / (r2 * r2.sqrt()).max(1e-6));
+        }
+        // semi-implicit Euler
+        velocity += acceleration * dt;
+        velocity *= (1.0 - raw.gravity.w * dt).max(0.0);
+        position += velocity * dt;
+
+        particle.position = position.extend(age);
+        particle.velocity = velocity.extend(lifetime);
+    }
+}
+
+#[test]
+fn test_particle_step() {
+    let emitter = Emitter {
+        position: Vector3::new(1.0, 2.0, 3.0),
+        radius: 0.5,
+        velocity: Vector3::new(0.0, 1.0, 0.0),
+        spread: 0.25,
+        lifetime_min: 1.0,
+        lifetime_max: 2.0,
+    };
+    let mut config = ParticleConfig {
+        num_particles: 256,
+        emitters: vec![emitter],
+        forces: Forces {
+            gravity: Vector3::zero(),
+            drag: 0.0,
+            curl_strength: 0.0,
+            curl_scale: 1.0,
+            attractors: Vec::new(),
+        },
+        size: 0.1,
+        color: Vector3::new(1.0, 1.0, 1.0),
+        seed: 3,
+    };
+    let dt = 1.0 / 60.0;
+
+    // unborn particles are born over the longest lifetime, and respawn inside the emitter
+    let mut particles = config.initial_particles();
+    assert!(particles.iter().all(|p| !p.is_alive()));
+    step(&mut particles, &config.to_raw(0.0, dt, 0));
+    let born = particles.iter().filter(|p| p.is_alive()).count();
+    assert!(born >= 1 && born < 8, "{}", born);
+    for frame in 1..120 {
+        step(&mut particles, &config.to_raw(frame as f32 * dt, dt, frame));
+    }
+    for p in particles.iter().filter(|p| p.is_alive()) {
+        assert!(p.lifetime() >= 1.0 && p.lifetime() <= 2.0);
+        // moved by the velocity only
+        let travelled = (p.position.truncate() - emitter.position).magnitude();
+        assert!(travelled <= 0.5 + (1.0 + 0.25) * p.age() + 1e-3);
+    }
+
+    // same seed, same particles
+    let mut again = config.initial_particles();
+    for frame in 0..120 {
+        step(&mut again, &config.to_raw(frame as f32 * dt, dt, frame));
+    }
+    assert_eq!(particles, again);
+    config.seed = 4;
+    let mut other = config.initial_particles();
+    for frame in 0..120 {
+        step(&mut other, &config.to_raw(frame as f32 * dt, dt, frame));
+    }
+    assert_ne!(particles, other);
+
+    // ballistic under gravity
+    config.forces.gravity = Vector3::new(0.0, -9.8, 0.0);
+    let raw = config.to_raw(0.0, dt, 0);
+    let mut p = [Particle {
+        position: Vector4::new(0.0, 0.0, 0.0, 0.0),
+        velocity: Vector4::new(1.0, 5.0, 0.0, 10.0),
+    }];
+    for _ in 0..60 {
+        step(&mut p, &raw);
+    }
+    assert!((p[0].position.x - 1.0).abs() < 1e-3);
+    assert!((p[0].position.y - (5.0 - 4.9)).abs() < 0.1, "{:?}", p[0]);
+    assert!((p[0].age() - 1.0).abs() < 1e-3);
+
+    // pulled towards an attractor
+    config.forces.gravity = Vector3::zero();
+    config.forces.attractors = vec![Attractor {
+        position: Vector3::new(2.0, 0.0, 0.0),
+        strength: 1.0,
+        radius: 0.1,
+    }];
+    let mut p = [Particle {
+        position: Vector4::new(0.0, 0.0, 0.0, 0.0),
+        velocity: Vector4::new(0.0, 0.0, 0.0, 10.0),
+    }];
+    step(&mut p, &config.to_raw(0.0, dt, 0));
+    assert!(p[0].velocity.x > 0.0 && p[0].velocity.y.abs() < 1e-6);
+
+    // curl noise is divergence free
+    let h = 1e-2;
+    for &q in &[
+        Vector3::new(0.3, -1.2, 0.7),
+        Vector3::new(2.0, 0.5, -3.0),
+        Vector3::new(-0.9, 0.1, 1.4),
+    ] {
+        let f = |d: Vector3<f32>| curl_noise(q + d, 0.8, 0.4);
+        let divergence = (f(Vector3::unit_x() * h).x - f(-Vector3::unit_x() * h).x
+            + f(Vector3::unit_y() * h).y
+            - f(-Vector3::unit_y() * h).y
+            + f(Vector3::unit_z() * h).z
+            - f(-Vector3::unit_z() * h).z)
+            / (2.0 * h);
+        assert!(divergence.abs() < 1e-2, "{}", divergence);
+        assert!(f(Vector3::zero()).magnitude() > 0.0);
+    }
+}
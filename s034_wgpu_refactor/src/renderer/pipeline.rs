@@ -0,0 +1,353 @@
+//! hashable pipeline state and a cache which deduplicates pipelines across passes and meshes
+//!
+//! like the `PipelineId` map in `draw::renderer::Renderer`, every property which makes a pipeline
+//! unique is a part of the key. bind group layouts aren't hashable, so pipeline layouts are cached
+//! by `layout` and the addresses of the bind group layouts. a desc with a `layout` which is
+//! already cached gets a new pipeline layout when it is used with other bind group layouts
+use nannou::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::PipelineLayoutBuilder;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub layout: &'static str,
+    /// SPIR-V
+    pub vertex_shader: &'static [u8],
+    /// SPIR-V. depth only if `None`
+    pub fragment_shader: Option<&'static [u8]>,
+    pub vertex_buffers: Vec<wgpu::VertexBufferDescriptor<'static>>,
+    pub index_format: wgpu::IndexFormat,
+    pub topology: wgpu::PrimitiveTopology,
+    pub cull_mode: wgpu::CullMode,
+    /// one for each color attachment. all of them share the blend state
+    pub color_formats: Vec<wgpu::TextureFormat>,
+    pub color_blend: wgpu::BlendDescriptor,
+    pub alpha_blend: wgpu::BlendDescriptor,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    pub clamp_depth: bool,
+    pub sample_count: u32,
+}
+
+impl PipelineDesc {
+    pub fn new(layout: &'static str, vertex_shader: &'static [u8]) -> Self {
+        Self {
+            layout,
+            vertex_shader,
+            fragment_shader: None,
+            vertex_buffers: Vec::new(),
+            index_format: wgpu::IndexFormat::Uint32,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: wgpu::CullMode::None,
+            color_formats: Vec::new(),
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            depth_format: None,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            clamp_depth: false,
+            sample_count: 1,
+        }
+    }
+
+    /// same state with other bind group layouts and shaders
+    pub fn layout(mut self, layout: &'static str) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn vertex_shader(mut self, spirv: &'static [u8]) -> Self {
+        self.vertex_shader = spirv;
+        self
+    }
+
+    pub fn fragment_shader(mut self, spirv: &'static [u8]) -> Self {
+        self.fragment_shader = Some(spirv);
+        self
+    }
+
+    pub fn vertex_buffer(mut self, desc: wgpu::VertexBufferDescriptor<'static>) -> Self {
+        self.vertex_buffers.push(desc);
+        self
+    }
+
+    pub fn index_format(mut self, format: wgpu::IndexFormat) -> Self {
+        self.index_format = format;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: wgpu::CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn color_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.color_formats = vec![format];
+        self
+    }
+
+    /// multiple render targets
+    pub fn color_formats(mut self, formats: &[wgpu::TextureFormat]) -> Self {
+        self.color_formats = formats.to_vec();
+        self
+    }
+
+    pub fn color_blend(mut self, blend: wgpu::BlendDescriptor) -> Self {
+        self.color_blend = blend;
+        self
+    }
+
+    pub fn alpha_blend(mut self, blend: wgpu::BlendDescriptor) -> Self {
+        self.alpha_blend = blend;
+        self
+    }
+
+    pub fn depth_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    pub fn depth_write_enabled(mut self, enabled: bool) -> Self {
+        self.depth_write_enabled = enabled;
+        self
+    }
+
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = compare;
+        self
+    }
+
+    pub fn clamp_depth(mut self, clamp_depth: bool) -> Self {
+        self.clamp_depth = clamp_depth;
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ComputePipelineDesc {
+    pub layout: &'static str,
+    /// SPIR-V
+    pub compute_shader: &'static [u8],
+}
+
+impl ComputePipelineDesc {
+    pub fn new(layout: &'static str, compute_shader: &'static [u8]) -> Self {
+        Self {
+            layout,
+            compute_shader,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: HashMap<&'static [u8], Arc<wgpu::ShaderModule>>,
+    layouts: HashMap<LayoutKey, Arc<wgpu::PipelineLayout>>,
+    render_pipelines: HashMap<PipelineDesc, Arc<wgpu::RenderPipeline>>,
+    compute_pipelines: HashMap<ComputePipelineDesc, Arc<wgpu::ComputePipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn render_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &PipelineDesc,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Arc<wgpu::RenderPipeline> {
+        if let Some(pipeline) = self.render_pipelines.get(desc) {
+            return Arc::clone(pipeline);
+        }
+
+        let layout = self.layout(device, desc.layout, bind_group_layouts);
+        let vs_mod = self.shader(device, desc.vertex_shader);
+        let fs_mod = desc.fragment_shader.map(|fs| self.shader(device, fs));
+        let color_states = desc
+            .color_formats
+            .iter()
+            .map(|&format| wgpu::ColorStateDescriptor {
+                format,
+                color_blend: desc.color_blend.clone(),
+                alpha_blend: desc.alpha_blend.clone(),
+                write_mask: wgpu::ColorWrite::ALL,
+            })
+            .collect::<Vec<_>>();
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(desc.layout),
+            layout: Some(&*layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_mod,
+                entry_point: "main",
+            },
+            fragment_stage: fs_mod
+                .as_ref()
+                .map(|module| wgpu::ProgrammableStageDescriptor {
+                    module,
+                    entry_point: "main",
+                }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: desc.cull_mode,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: desc.clamp_depth,
+            }),
+            primitive_topology: desc.topology,
+            color_states: &color_states,
+            depth_stencil_state: desc.depth_format.map(|format| {
+                wgpu::DepthStencilStateDescriptor {
+                    format,
+                    depth_write_enabled: desc.depth_write_enabled,
+                    depth_compare: desc.depth_compare,
+                    stencil: wgpu::StencilStateDescriptor::default(),
+                }
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: desc.index_format,
+                vertex_buffers: &desc.vertex_buffers,
+            },
+            sample_count: desc.sample_count,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let pipeline = Arc::new(pipeline);
+        self.render_pipelines
+            .insert(desc.clone(), Arc::clone(&pipeline));
+        pipeline
+    }
+
+    pub fn compute_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &ComputePipelineDesc,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Arc<wgpu::ComputePipeline> {
+        if let Some(pipeline) = self.compute_pipelines.get(desc) {
+            return Arc::clone(pipeline);
+        }
+
+        let layout = self.layout(device, desc.layout, bind_group_layouts);
+        let cs_mod = self.shader(device, desc.compute_shader);
+        let pipeline = super::ComputePipelineBuilder::from_layout(&layout, &cs_mod)
+            .label(desc.layout)
+            .build(device);
+
+        let pipeline = Arc::new(pipeline);
+        self.compute_pipelines
+            .insert(desc.clone(), Arc::clone(&pipeline));
+        pipeline
+    }
+
+    /// number of render and compute pipelines
+    pub fn len(&self) -> usize {
+        self.render_pipelines.len() + self.compute_pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// drops pipelines which aren't used by any pass
+    pub fn clear_unused(&mut self) {
+        self.render_pipelines
+            .retain(|_, pipeline| Arc::strong_count(pipeline) > 1);
+        self.compute_pipelines
+            .retain(|_, pipeline| Arc::strong_count(pipeline) > 1);
+    }
+
+    fn shader(&mut self, device: &wgpu::Device, spirv: &'static [u8]) -> Arc<wgpu::ShaderModule> {
+        let module = self
+            .shaders
+            .entry(spirv)
+            .or_insert_with(|| Arc::new(wgpu::shader_from_spirv_bytes(device, spirv)));
+        Arc::clone(module)
+    }
+
+    fn layout(
+        &mut self,
+        device: &wgpu::Device,
+        label: &'static str,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Arc<wgpu::PipelineLayout> {
+        let key = LayoutKey::new(label, bind_group_layouts);
+        let layout = self.layouts.entry(key).or_insert_with(|| {
+            Arc::new(
+                PipelineLayoutBuilder::new()
+                    .label(label)
+                    .bind_group_layouts(bind_group_layouts)
+                    .build(device),
+            )
+        });
+        Arc::clone(layout)
+    }
+}
+
+/// `label` and the addresses of the bind group layouts. a layout dropped and created again at the
+/// same address under the same label is taken as the same, so a label must keep its entries
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LayoutKey {
+    label: &'static str,
+    bind_group_layouts: Vec<usize>,
+}
+
+impl LayoutKey {
+    fn new<T>(label: &'static str, bind_group_layouts: &[&T]) -> Self {
+        Self {
+            label,
+            bind_group_layouts: bind_group_layouts
+                .iter()
+                .map(|&layout| layout as *const T as usize)
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn test_pipeline_cache_keys() {
+    use std::collections::HashSet;
+
+    let desc = PipelineDesc::new("main", &[0, 1, 2, 3])
+        .color_format(wgpu::TextureFormat::Rgba16Float)
+        .depth_format(wgpu::TextureFormat::Depth32Float);
+    let mut descs = HashSet::new();
+    descs.insert(desc.clone());
+    assert!(descs.contains(&desc.clone()));
+    assert!(!descs.contains(&desc.clone().sample_count(4)));
+    assert!(!descs.contains(&desc.clone().layout("pbr")));
+    assert!(!descs.contains(&desc.clone().fragment_shader(&[4, 5])));
+
+    // two layouts with the same entries are still two handles
+    let (a, b) = (0u32, 0u32);
+    assert_eq!(
+        LayoutKey::new("main", &[&a, &b]),
+        LayoutKey::new("main", &[&a, &b])
+    );
+    assert_ne!(LayoutKey::new("main", &[&a]), LayoutKey::new("main", &[&b]));
+    assert_ne!(
+        LayoutKey::new("main", &[&a, &b]),
+        LayoutKey::new("main", &[&b, &a])
+    );
+    assert_ne!(LayoutKey::new("main", &[&a]), LayoutKey::new("pbr", &[&a]));
+    assert_ne!(
+        LayoutKey::new("main", &[&a]),
+        LayoutKey::new::<u32>("main", &[])
+    );
+}
@@ -0,0 +1,654 @@
+//! post-processing effect stack
+//!
+//! the scene is rendered to an HDR texture and the effects are applied in order by fullscreen
+//! passes of the render graph. every effect reads the output of the previous one, and the graph
+//! aliases the intermediate textures, so the stack ping-pongs between a few textures
+//!
+//! effects which depend on the position on the screen get the part of the image being rendered
+//! in `PostParams[3]`, see `PostStack::set_tile`, so tiles of the image are processed like the
+//! whole of it. effects which read the neighbouring pixels can't see past the tile, see
+//! `Effect::reads_neighbours`
+use nannou::math::cgmath::Vector3;
+use nannou::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::brdf::ToneMapping;
+use super::camera::ProjectionTile;
+use super::environment::f32_to_f16;
+use super::graph::{
+    GraphPass, GraphResources, PassDesc, RenderGraph, RenderGraphBuilder, TextureDesc, TextureSize,
+};
+use super::lut::Lut3d;
+use super::pipeline::{PipelineCache, PipelineDesc};
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// must be same as `PostParams` in `shaders/post_*.frag`
+pub type PostParamsRaw = [[f32; 4]; 4];
+
+/// `PostParams[3]` of every pass: x, y, width and height of the rendered part of the image
+pub fn tile_params(tile: Option<ProjectionTile>) -> [f32; 4] {
+    match tile {
+        Some(tile) => [tile.x, tile.y, tile.width, tile.height],
+        None => [0.0, 0.0, 1.0, 1.0],
+    }
+}
+
+pub struct FullscreenPassDesc<'a> {
+    pub name: String,
+    /// shaders with the same layout must have the same inputs
+    pub layout: &'static str,
+    /// SPIR-V
+    pub fragment_shader: &'static [u8],
+    /// sampled from binding 2
+    pub inputs: Vec<String>,
+    /// sampled as a 3D texture after the inputs
+    pub lut: Option<&'a Lut3d>,
+    pub output: String,
+    pub format: wgpu::TextureFormat,
+}
+
+impl<'a> FullscreenPassDesc<'a> {
+    pub fn new(name: &str, layout: &'static str, fragment_shader: &'static [u8]) -> Self {
+        Self {
+            name: name.to_owned(),
+            layout,
+            fragment_shader,
+            inputs: Vec::new(),
+            lut: None,
+            output: String::new(),
+            format: HDR_FORMAT,
+        }
+    }
+
+    pub fn input(mut self, name: &str) -> Self {
+        self.inputs.push(name.to_owned());
+        self
+    }
+
+    pub fn lut(mut self, lut: &'a Lut3d) -> Self {
+        self.lut = Some(lut);
+        self
+    }
+
+    pub fn output(mut self, name: &str, format: wgpu::TextureFormat) -> Self {
+        self.output = name.to_owned();
+        self.format = format;
+        self
+    }
+}
+
+/// draws a fullscreen triangle which samples graph textures with `shaders/fullscreen.vert`
+pub struct FullscreenPass {
+    name: String,
+    inputs: Vec<String>,
+    output: String,
+    params: PostParamsRaw,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    _lut_texture: Option<wgpu::Texture>,
+    lut_view: Option<wgpu::TextureView>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// rebuilt whenever the graph recreates its textures
+    bind_group: Option<wgpu::BindGroup>,
+    pipeline: Arc<wgpu::RenderPipeline>,
+}
+
+impl FullscreenPass {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+        desc: &FullscreenPassDesc,
+    ) -> Self {
+        let params = PostParamsRaw::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("post params"),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut builder = wgpu::BindGroupLayoutBuilder::new()
+            .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false)
+            .sampler(wgpu::ShaderStage::FRAGMENT);
+        for _ in &desc.inputs {
+            builder = builder.sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureComponentType::Float,
+            );
+        }
+        if desc.lut.is_some() {
+            builder = builder.sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::D3,
+                wgpu::TextureComponentType::Float,
+            );
+        }
+        let bind_group_layout = builder.build(device);
+
+        let lut_texture = desc
+            .lut
+            .map(|lut| Self::create_lut_texture(device, queue, lut));
+        let lut_view = lut_texture.as_ref().map(|texture| {
+            texture
+                .view()
+                .dimension(wgpu::TextureViewDimension::D3)
+                .build()
+        });
+
+        let pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new(
+                desc.layout,
+                include_bytes!("../../shaders/fullscreen.vert.spv"),
+            )
+            .fragment_shader(desc.fragment_shader)
+            .color_format(desc.format),
+            &[&bind_group_layout],
+        );
+
+        Self {
+            name: desc.name.clone(),
+            inputs: desc.inputs.clone(),
+            output: desc.output.clone(),
+            params,
+            uniform_buffer,
+            sampler,
+            _lut_texture: lut_texture,
+            lut_view,
+            bind_group_layout,
+            bind_group: None,
+            pipeline,
+        }
+    }
+
+    pub fn set_params(&mut self, queue: &wgpu::Queue, params: PostParamsRaw) {
+        if self.params != params {
+            self.params = params;
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&params));
+        }
+    }
+
+    fn create_lut_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lut: &Lut3d,
+    ) -> wgpu::Texture {
+        let size = lut.size as u32;
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: size,
+        };
+        let texture = wgpu::TextureBuilder::new()
+            .extent(extent)
+            .mip_level_count(1)
+            .sample_count(1)
+            .dimension(wgpu::TextureDimension::D3)
+            .format(HDR_FORMAT)
+            .usage(wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)
+            .build(device);
+        let data = lut
+            .data
+            .iter()
+            .flat_map(|c| vec![c.x, c.y, c.z, 1.0])
+            .map(f32_to_f16)
+            .collect::<Vec<u16>>();
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&data),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 8 * size, // rgba16
+                rows_per_image: size,
+            },
+            extent,
+        );
+        texture
+    }
+}
+
+impl GraphPass for FullscreenPass {
+    fn desc(&self) -> PassDesc {
+        let desc = self
+            .inputs
+            .iter()
+            .fold(PassDesc::new(&self.name), |desc, input| desc.read(input));
+        desc.write(&self.output)
+    }
+
+    fn bind(&mut self, device: &wgpu::Device, resources: &GraphResources) {
+        let mut builder = wgpu::BindGroupBuilder::new()
+            .binding(wgpu::BindingResource::Buffer(self.uniform_buffer.slice(..)))
+            .sampler(&self.sampler);
+        for input in &self.inputs {
+            builder = builder.texture_view(resources.view(input));
+        }
+        if let Some(lut_view) = &self.lut_view {
+            builder = builder.texture_view(lut_view);
+        }
+        self.bind_group = Some(builder.build(device, &self.bind_group_layout));
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, resources: &GraphResources) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) => bind_group,
+            None => return,
+        };
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(resources.view(&self.output), |color| {
+                color.load_op(wgpu::LoadOp::Clear(wgpu::Color::BLACK))
+            })
+            .begin(encoder);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomParams {
+    /// brightness where the bloom starts
+    pub threshold: f32,
+    /// width of the soft transition around the threshold
+    pub knee: f32,
+    pub intensity: f32,
+    /// blur radius in texels of the half resolution texture
+    pub radius: f32,
+}
+
+impl Default for BloomParams {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.8,
+            radius: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FxaaParams {
+    /// max length of the blur in texels
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for FxaaParams {
+    fn default() -> Self {
+        Self {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VignetteParams {
+    pub intensity: f32,
+    pub smoothness: f32,
+    pub roundness: f32,
+    /// circle instead of the aspect ratio of the screen
+    pub rounded: bool,
+    pub color: Vector3<f32>,
+}
+
+impl Default for VignetteParams {
+    fn default() -> Self {
+        Self {
+            intensity: 0.45,
+            smoothness: 0.2,
+            roundness: 1.0,
+            rounded: false,
+            color: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChromaticAberrationParams {
+    pub intensity: f32,
+}
+
+impl Default for ChromaticAberrationParams {
+    fn default() -> Self {
+        Self { intensity: 0.5 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FilmGrainParams {
+    pub intensity: f32,
+    /// 0.0: same grain everywhere, 1.0: no grain in the highlights
+    pub response: f32,
+}
+
+impl Default for FilmGrainParams {
+    fn default() -> Self {
+        Self {
+            intensity: 0.05,
+            response: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGradingParams {
+    /// uploaded once by `PostStack::add_to_graph`
+    pub lut: Lut3d,
+    /// 0.0: original, 1.0: graded
+    pub strength: f32,
+}
+
+impl ColorGradingParams {
+    pub fn new(lut: Lut3d) -> Self {
+        Self { lut, strength: 1.0 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMappingParams {
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+}
+
+impl Default for ToneMappingParams {
+    fn default() -> Self {
+        Self {
+            tone_mapping: ToneMapping::Aces,
+            exposure: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    Bloom(BloomParams),
+    Fxaa(FxaaParams),
+    Vignette(VignetteParams),
+    ChromaticAberration(ChromaticAberrationParams),
+    FilmGrain(FilmGrainParams),
+    ColorGrading(ColorGradingParams),
+    ToneMapping(ToneMappingParams),
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Bloom(_) => "bloom",
+            Effect::Fxaa(_) => "fxaa",
+            Effect::Vignette(_) => "vignette",
+            Effect::ChromaticAberration(_) => "chromatic_aberration",
+            Effect::FilmGrain(_) => "film_grain",
+            Effect::ColorGrading(_) => "color_grading",
+            Effect::ToneMapping(_) => "tone_mapping",
+        }
+    }
+
+    /// whether the effect samples around each pixel, so that it shows seams between tiles
+    pub fn reads_neighbours(&self) -> bool {
+        match self {
+            Effect::Bloom(_) | Effect::Fxaa(_) | Effect::ChromaticAberration(_) => true,
+            Effect::Vignette(_)
+            | Effect::FilmGrain(_)
+            | Effect::ColorGrading(_)
+            | Effect::ToneMapping(_) => false,
+        }
+    }
+
+    /// one for each pass of the effect
+    pub fn to_raw(&self, time: f32) -> Vec<PostParamsRaw> {
+        let mut raw = PostParamsRaw::default();
+        match self {
+            Effect::Bloom(p) => {
+                // prefilter, horizontal blur, vertical blur, composite
+                let params = [p.threshold, p.knee, p.intensity, p.radius];
+                let mut horizontal = raw;
+                horizontal[0] = [p.radius, 0.0, 0.0, 0.0];
+                let mut vertical = raw;
+                vertical[0] = [0.0, p.radius, 0.0, 0.0];
+                raw[0] = params;
+                return vec![raw, horizontal, vertical, raw];
+            }
+            Effect::Fxaa(p) => raw[0] = [p.span_max, p.reduce_mul, p.reduce_min, 0.0],
+            Effect::Vignette(p) => {
+                let rounded = if p.rounded { 1.0 } else { 0.0 };
+                raw[0] = [p.intensity, p.smoothness, p.roundness, rounded];
+                raw[1] = p.color.extend(1.0).into();
+            }
+            Effect::ChromaticAberration(p) => raw[0][0] = p.intensity,
+            Effect::FilmGrain(p) => raw[0] = [p.intensity, time, p.response, 0.0],
+            Effect::ColorGrading(p) => {
+                raw[0] = [p.strength, p.lut.size as f32, 0.0, 0.0];
+                raw[1] = p.lut.domain_min.extend(0.0).into();
+                raw[2] = p.lut.domain_max.extend(0.0).into();
+            }
+            Effect::ToneMapping(p) => raw[0] = [p.exposure, p.tone_mapping as u32 as f32, 0.0, 0.0],
+        }
+        vec![raw]
+    }
+}
+
+/// ordered list of effects
+///
+/// ```ignore
+/// let mut post = PostStack::new()
+///     .effect(Effect::Bloom(BloomParams::default()))
+///     .effect(Effect::ToneMapping(ToneMappingParams::default()));
+/// let graph = post.add_to_graph(device, queue, &mut pipeline_cache, graph, "hdr", "swap_chain", format);
+/// ```
+#[derive(Debug, Default)]
+pub struct PostStack {
+    effects: Vec<Effect>,
+    /// left out by `without_neighbourhood`
+    dropped: Vec<Effect>,
+    /// names of the graph passes of each effect
+    passes: Vec<Vec<String>>,
+    time: f32,
+    tile: Option<ProjectionTile>,
+}
+
+impl PostStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    /// only the effects which don't read the neighbouring pixels, for rendering in tiles
+    pub fn without_neighbourhood(mut self) -> Self {
+        let (dropped, effects) = self
+            .effects
+            .into_iter()
+            .partition::<Vec<_>, _>(Effect::reads_neighbours);
+        self.effects = effects;
+        self.dropped.extend(dropped);
+        self
+    }
+
+    /// the effects left out by `without_neighbourhood`, for the caller to report
+    pub fn dropped(&self) -> &[Effect] {
+        &self.dropped
+    }
+
+    /// parameters are applied on `update`. the kind of the effect and the LUT of color grading
+    /// can't be changed after `add_to_graph`
+    pub fn effect_mut(&mut self, index: usize) -> Option<&mut Effect> {
+        self.effects.get_mut(index)
+    }
+
+    /// adds the passes which read `input` and write `output` in the end.
+    /// an empty stack gets a tone mapping pass without a curve, which only copies `input`
+    pub fn add_to_graph(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+        mut graph: RenderGraphBuilder,
+        input: &str,
+        output: &str,
+        output_format: wgpu::TextureFormat,
+    ) -> RenderGraphBuilder {
+        if self.effects.is_empty() {
+            self.effects.push(Effect::ToneMapping(ToneMappingParams {
+                tone_mapping: ToneMapping::None,
+                exposure: 1.0,
+            }));
+        }
+
+        let usage = wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED;
+        let full = TextureDesc::new(HDR_FORMAT, TextureSize::Surface, usage);
+        let half = TextureDesc::new(HDR_FORMAT, TextureSize::Scaled(0.5), usage);
+
+        self.passes.clear();
+        let mut source = input.to_owned();
+        for (i, effect) in self.effects.iter().enumerate() {
+            let (target, format) = if i + 1 == self.effects.len() {
+                (output.to_owned(), output_format)
+            } else {
+                let target = format!("post{}", i);
+                graph = graph.texture(&target, full);
+                (target, HDR_FORMAT)
+            };
+
+            let name = format!("post{}_{}", i, effect.name());
+            let descs = match effect {
+                Effect::Bloom(_) => {
+                    let bright = format!("{}_bright", name);
+                    let blur_h = format!("{}_blur_h", name);
+                    let blur_v = format!("{}_blur_v", name);
+                    graph = graph
+                        .texture(&bright, half)
+                        .texture(&blur_h, half)
+                        .texture(&blur_v, half);
+                    let blur = include_bytes!("../../shaders/post_blur.frag.spv");
+                    vec![
+                        FullscreenPassDesc::new(
+                            &format!("{}_prefilter", name),
+                            "post_bloom_prefilter",
+                            include_bytes!("../../shaders/post_bloom_prefilter.frag.spv"),
+                        )
+                        .input(&source)
+                        .output(&bright, HDR_FORMAT),
+                        FullscreenPassDesc::new(&blur_h, "post_blur", blur)
+                            .input(&bright)
+                            .output(&blur_h, HDR_FORMAT),
+                        FullscreenPassDesc::new(&blur_v, "post_blur", blur)
+                            .input(&blur_h)
+                            .output(&blur_v, HDR_FORMAT),
+                        FullscreenPassDesc::new(
+                            &format!("{}_composite", name),
+                            "post_bloom_composite",
+                            include_bytes!("../../shaders/post_bloom_composite.frag.spv"),
+                        )
+                        .input(&source)
+                        .input(&blur_v)
+                        .output(&target, format),
+                    ]
+                }
+                Effect::ColorGrading(params) => vec![FullscreenPassDesc::new(
+                    &name,
+                    "post_color_grading",
+                    include_bytes!("../../shaders/post_color_grading.frag.spv"),
+                )
+                .input(&source)
+                .lut(&params.lut)
+                .output(&target, format)],
+                _ => {
+                    let (layout, spirv): (&'static str, &'static [u8]) = match effect {
+                        Effect::Fxaa(_) => (
+                            "post_fxaa",
+                            include_bytes!("../../shaders/post_fxaa.frag.spv"),
+                        ),
+                        Effect::Vignette(_) => (
+                            "post_vignette",
+                            include_bytes!("../../shaders/post_vignette.frag.spv"),
+                        ),
+                        Effect::ChromaticAberration(_) => (
+                            "post_chromatic_aberration",
+                            include_bytes!("../../shaders/post_chromatic_aberration.frag.spv"),
+                        ),
+                        Effect::FilmGrain(_) => (
+                            "post_film_grain",
+                            include_bytes!("../../shaders/post_film_grain.frag.spv"),
+                        ),
+                        _ => (
+                            "post_tone_mapping",
+                            include_bytes!("../../shaders/post_tone_mapping.frag.spv"),
+                        ),
+                    };
+                    vec![FullscreenPassDesc::new(&name, layout, spirv)
+                        .input(&source)
+                        .output(&target, format)]
+                }
+            };
+
+            let mut names = Vec::new();
+            for (desc, mut raw) in descs.iter().zip(effect.to_raw(self.time)) {
+                let mut pass = FullscreenPass::new(device, queue, pipeline_cache, desc);
+                raw[3] = tile_params(self.tile);
+                pass.set_params(queue, raw);
+                names.push(desc.name.clone());
+                graph = graph.pass(pass);
+            }
+            self.passes.push(names);
+            source = target;
+        }
+        graph
+    }
+
+    /// uploads the parameters of every effect and advances the time of the film grain
+    pub fn update(&mut self, graph: &mut RenderGraph, queue: &wgpu::Queue, dt: Duration) {
+        self.time += dt.as_secs_f32();
+        self.upload(graph, queue);
+    }
+
+    /// the part of the image the graph renders, see `ProjectionTile`. `None` is all of it
+    pub fn set_tile(
+        &mut self,
+        graph: &mut RenderGraph,
+        queue: &wgpu::Queue,
+        tile: Option<ProjectionTile>,
+    ) {
+        self.tile = tile;
+        self.upload(graph, queue);
+    }
+
+    fn upload(&self, graph: &mut RenderGraph, queue: &wgpu::Queue) {
+        for (effect, names) in self.effects.iter().zip(self.passes.iter()) {
+            for (name, mut raw) in names.iter().zip(effect.to_raw(self.time)) {
+                if let Some(pass) = graph.pass_mut::<FullscreenPass>(name) {
+                    raw[3] = tile_params(self.tile);
+                    pass.set_params(queue, raw);
+                }
+            }
+        }
+    }
+}
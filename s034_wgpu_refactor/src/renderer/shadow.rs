@@ -0,0 +1,562 @@
+//! shadow maps for `LightSet`
+//!
+//! every shadow casting light gets a range of layers in one depth array texture
+//! (directional: one layer per cascade, point: six cube faces, spot: one layer).
+//! the layer matrices are recomputed every frame by `update()`, and `render()` bakes the
+//! depth of a `Geom` into each layer with `DrawMesh`
+use nannou::math::cgmath::{self, Matrix4, Point3, Vector3};
+use nannou::prelude::*;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use super::binding::{Binding, BindingBuilder};
+use super::camera::{Camera, Projection};
+use super::geom::Geom;
+use super::instance::Instances;
+use super::light::{Light, LightKind, LightSet};
+use super::mesh_draw::{BindGroupKind, DrawLayout, DrawLayoutBuilder, DrawMesh};
+use super::pipeline::{PipelineCache, PipelineDesc};
+use super::vertex::{Vertex, VertexDescription};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowConfig {
+    /// width and height of each layer
+    pub resolution: u32,
+    /// total number of layers shared by all lights
+    pub max_layers: u32,
+    /// number of cascades for directional lights (1 ~ MAX_CASCADES)
+    pub cascades: usize,
+    /// 0.0: uniform splits, 1.0: logarithmic splits
+    pub split_lambda: f32,
+    /// directional shadows end at this view distance
+    pub max_distance: f32,
+    /// directional shadow casters behind the cascade bounds within this distance are captured
+    pub caster_margin: f32,
+    /// kernel is (2 * pcf_radius + 1)^2 texels. 0 is a single hardware filtered lookup
+    pub pcf_radius: u32,
+    /// constant depth bias
+    pub bias: f32,
+    /// depth bias for surfaces at grazing angles to the light
+    pub slope_bias: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            max_layers: 16,
+            cascades: 3,
+            split_lambda: 0.75,
+            max_distance: 50.0,
+            caster_margin: 20.0,
+            pcf_radius: 1,
+            bias: 0.0005,
+            slope_bias: 0.005,
+        }
+    }
+}
+
+impl ShadowConfig {
+    pub fn resolution(mut self, resolution: u32) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn max_layers(mut self, max_layers: u32) -> Self {
+        self.max_layers = max_layers;
+        self
+    }
+
+    pub fn cascades(mut self, cascades: usize) -> Self {
+        self.cascades = cascades;
+        self
+    }
+
+    pub fn split_lambda(mut self, split_lambda: f32) -> Self {
+        self.split_lambda = split_lambda;
+        self
+    }
+
+    pub fn max_distance(mut self, max_distance: f32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn pcf_radius(mut self, pcf_radius: u32) -> Self {
+        self.pcf_radius = pcf_radius;
+        self
+    }
+
+    pub fn bias(mut self, bias: f32, slope_bias: f32) -> Self {
+        self.bias = bias;
+        self.slope_bias = slope_bias;
+        self
+    }
+}
+
+/// per light shadow parameters. indexed same as the packed `LightSet` array
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowRaw {
+    /// x: first layer, y: layer count (0: no shadow), z: pcf radius
+    pub layers: [u32; 4],
+    /// x: bias, y: slope bias, z: texel size
+    pub params: cgmath::Vector4<f32>,
+    /// far view distance of each cascade
+    pub splits: cgmath::Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for ShadowRaw {}
+unsafe impl bytemuck::Pod for ShadowRaw {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowLayerRaw {
+    pub view_proj: cgmath::Matrix4<f32>,
+}
+unsafe impl bytemuck::Zeroable for ShadowLayerRaw {}
+unsafe impl bytemuck::Pod for ShadowLayerRaw {}
+
+pub struct ShadowMaps {
+    config: ShadowConfig,
+    num_layers: usize,
+    layer_views: Vec<wgpu::TextureView>,
+    /// `light_info`, `lights`, `shadows`, `shadow_layers`, `shadow_map`
+    binding: Binding,
+    /// view projection of the layer currently being rendered
+    pass_binding: Binding,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    draw_layout: DrawLayout,
+}
+
+impl ShadowMaps {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const MAX_CASCADES: usize = 4;
+    const NEAR: f32 = 0.05;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        lights: &LightSet,
+        instances: &Instances,
+        config: ShadowConfig,
+    ) -> Self {
+        assert!(
+            config.cascades >= 1 && config.cascades <= Self::MAX_CASCADES,
+            "The number of cascades must be in 1 ~ {}",
+            Self::MAX_CASCADES
+        );
+        // keep at least two layers so that the view is always inferred as D2Array
+        let max_layers = config.max_layers.max(2);
+
+        let texture = wgpu::TextureBuilder::new()
+            .extent(wgpu::Extent3d {
+                width: config.resolution,
+                height: config.resolution,
+                depth: max_layers,
+            })
+            .mip_level_count(1)
+            .sample_count(1)
+            .dimension(wgpu::TextureDimension::D2)
+            .format(Self::FORMAT)
+            .usage(wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
+            .build(device);
+        let layer_views = (0..max_layers)
+            .map(|i| {
+                texture
+                    .view()
+                    .label("shadow")
+                    .dimension(wgpu::TextureViewDimension::D2)
+                    .aspect(wgpu::TextureAspect::All)
+                    .level_count(None)
+                    .base_array_layer(i)
+                    .array_layer_count(NonZeroU32::new(1))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let view = texture
+            .view()
+            .dimension(wgpu::TextureViewDimension::D2Array)
+            .build();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // share the light buffers so that lights and their shadows are bound as one group
+        let light_info = lights.binding.label_index["light_info"];
+        let light_array = lights.binding.label_index["lights"];
+        let shadows = vec![<ShadowRaw as bytemuck::Zeroable>::zeroed(); LightSet::MAX_LIGHTS];
+        let layers = vec![<ShadowLayerRaw as bytemuck::Zeroable>::zeroed(); max_layers as usize];
+        let binding = BindingBuilder::new()
+            .assign_uniform_buffer(
+                "light_info",
+                Arc::clone(&lights.binding.bindings[light_info]),
+                Arc::clone(&lights.binding.buffers[light_info]),
+            )
+            .assign_storage_buffer(
+                "lights",
+                Arc::clone(&lights.binding.bindings[light_array]),
+                Arc::clone(&lights.binding.buffers[light_array]),
+            )
+            .storage_buffer_custom(
+                "shadows",
+                &shadows,
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                true,
+            )
+            .storage_buffer_custom(
+                "shadow_layers",
+                &layers,
+                wgpu::BufferUsage::STORAGE
+                    | wgpu::BufferUsage::COPY_DST
+                    | wgpu::BufferUsage::COPY_SRC,
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                true,
+            )
+            .texture(
+                "shadow_map",
+                texture,
+                view,
+                sampler,
+                wgpu::ShaderStage::FRAGMENT,
+                true,
+            )
+            .build(device);
+
+        let pass_binding = BindingBuilder::new()
+            .uniform_buffer(
+                "light_view_proj",
+                &[layers[0]],
+                wgpu::ShaderStage::VERTEX,
+                false,
+            )
+            .build(device);
+
+        // depth only. biased in the lighting shader by `ShadowConfig`
+        let desc = PipelineDesc::new("shadow", include_bytes!("../../shaders/shadow.vert.spv"))
+            .vertex_buffer(Vertex::desc())
+            .cull_mode(wgpu::CullMode::Back)
+            .depth_format(Self::FORMAT)
+            .clamp_depth(device.features().contains(wgpu::Features::DEPTH_CLAMPING));
+        // the light's view projection replaces the camera
+        let layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Camera, pass_binding.bind_group_layout())
+            .group(
+                BindGroupKind::Instances,
+                instances.binding.bind_group_layout(),
+            );
+        let pipeline = pipeline_cache.render_pipeline(device, &desc, layout.bind_group_layouts());
+        let draw_layout = layout.build();
+
+        Self {
+            config,
+            num_layers: 0,
+            layer_views,
+            binding,
+            pass_binding,
+            pipeline,
+            draw_layout,
+        }
+    }
+
+    pub fn config(&self) -> &ShadowConfig {
+        &self.config
+    }
+
+    /// number of layers used in the last `update()`
+    pub fn num_layers(&self) -> usize {
+        self.num_layers
+    }
+
+    /// reallocates layers for shadow casting lights and recomputes their matrices.
+    /// lights which don't fit in the remaining layers are rendered unshadowed
+    pub fn update(&mut self, queue: &wgpu::Queue, lights: &LightSet, camera: &Camera) {
+        let mut shadows = vec![<ShadowRaw as bytemuck::Zeroable>::zeroed(); LightSet::MAX_LIGHTS];
+        let mut layers = vec![];
+        for (i, (_, light)) in lights.iter().enumerate() {
+            if !light.cast_shadows {
+                continue;
+            }
+            let (matrices, splits) = self.light_matrices(light, camera);
+            if layers.len() + matrices.len() > self.layer_views.len() {
+                continue;
+            }
+            let mut far = [0.0; Self::MAX_CASCADES];
+            for (far, split) in far.iter_mut().zip(splits.iter().skip(1)) {
+                *far = *split;
+            }
+            shadows[i] = ShadowRaw {
+                layers: [
+                    layers.len() as u32,
+                    matrices.len() as u32,
+                    self.config.pcf_radius,
+                    0,
+                ],
+                params: cgmath::Vector4::new(
+                    self.config.bias,
+                    self.config.slope_bias,
+                    1.0 / self.config.resolution as f32,
+                    0.0,
+                ),
+                splits: far.into(),
+            };
+            layers.extend(
+                matrices
+                    .into_iter()
+                    .map(|view_proj| ShadowLayerRaw { view_proj }),
+            );
+        }
+        self.num_layers = layers.len();
+
+        self.binding
+            .write_buffer_at_label(queue, "shadows", 0, &shadows);
+        if !layers.is_empty() {
+            self.binding
+                .write_buffer_at_label(queue, "shadow_layers", 0, &layers);
+        }
+    }
+
+    /// light space matrices of each layer, and cascade split distances for directional lights
+    fn light_matrices(&self, light: &Light, camera: &Camera) -> (Vec<Matrix4<f32>>, Vec<f32>) {
+        let config = &self.config;
+        match light.kind {
+            LightKind::Directional { direction } => {
+                let projection = &camera.projection;
+                let far = projection.zfar.min(config.max_distance);
+                let splits =
+                    cascade_splits(projection.znear, far, config.cascades, config.split_lambda);
+                let view = camera.view_matrix();
+                let matrices = splits
+                    .windows(2)
+                    .map(|split| {
+                        let proj = Projection::OPENGL_TO_WGPU_MATRIX
+                            * cgmath::perspective(
+                                projection.fovy,
+                                projection.aspect,
+                                split[0],
+                                split[1],
+                            );
+                        let corners = frustum_corners(proj * view);
+                        directional_light_matrix(
+                            direction,
+                            &corners,
+                            config.resolution,
+                            config.caster_margin,
+                        )
+                    })
+                    .collect();
+                (matrices, splits)
+            }
+            LightKind::Point { position, radius } => {
+                let far = if radius > 0.0 {
+                    radius
+                } else {
+                    config.max_distance
+                };
+                (point_light_matrices(position, far).to_vec(), vec![])
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                radius,
+                outer_angle,
+                ..
+            } => {
+                let far = if radius > 0.0 {
+                    radius
+                } else {
+                    config.max_distance
+                };
+                (
+                    vec![spot_light_matrix(position, direction, outer_angle, far)],
+                    vec![],
+                )
+            }
+        }
+    }
+
+    /// bakes the depth of `geom` into every layer allocated in the last `update()`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, geom: &Geom, instances: &Instances) {
+        let layer_size = std::mem::size_of::<ShadowLayerRaw>() as wgpu::BufferAddress;
+        let layers = &self.binding.buffers[self.binding.label_index["shadow_layers"]];
+        let uniform = &self.pass_binding.buffers[0];
+        for (i, view) in self.layer_views.iter().take(self.num_layers).enumerate() {
+            // every pass reads the same uniform, so copy the matrix in just before the pass
+            encoder.copy_buffer_to_buffer(layers, i as u64 * layer_size, uniform, 0, layer_size);
+
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .depth_stencil_attachment(view, |depth| {
+                    depth
+                        .depth_load_op(wgpu::LoadOp::Clear(1.0))
+                        .depth_store_op(true)
+                })
+                .begin(encoder);
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass
+                .draw_geom(geom)
+                .layout(&self.draw_layout)
+                .camera(self.pass_binding.bind_group())
+                .instances(
+                    instances.binding.bind_group(),
+                    0..instances.instances.len() as u32,
+                )
+                .submit();
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        self.binding.bind_group_layout()
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.binding.bind_group()
+    }
+}
+
+/// cascade boundaries from `near` to `far` (`count + 1` values) blended between uniform and
+/// logarithmic distribution by `lambda`
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (0..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            lambda * log + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// world space corners of the frustum of `view_proj` (wgpu clip space, z in 0 ~ 1)
+pub fn frustum_corners(view_proj: Matrix4<f32>) -> [Point3<f32>; 8] {
+    let inv = view_proj
+        .invert()
+        .expect("view projection matrix must be invertible");
+    let mut corners = [Point3::origin(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+        let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+        let p = inv * cgmath::Vector4::new(x, y, z, 1.0);
+        *corner = Point3::from_homogeneous(p);
+    }
+    corners
+}
+
+/// orthographic light space matrix which covers the bounding sphere of `corners`.
+/// the sphere keeps the size constant while the camera rotates, and the center is snapped to
+/// the texel grid so that shadow edges don't shimmer while the camera moves
+pub fn directional_light_matrix(
+    direction: Vector3<f32>,
+    corners: &[Point3<f32>; 8],
+    resolution: u32,
+    caster_margin: f32,
+) -> Matrix4<f32> {
+    let center = Point3::centroid(corners);
+    let radius = corners
+        .iter()
+        .map(|corner| (corner - center).magnitude())
+        .fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+    // pad one texel to keep the corners inside after snapping
+    let texel = 2.0 * radius / (resolution as f32 - 2.0);
+    let extent = radius + texel;
+
+    let direction = direction.normalize();
+    let view = Matrix4::look_at_dir(Point3::origin(), direction, up_for(direction));
+    let center = view.transform_point(center);
+    let x = (center.x / texel).round() * texel;
+    let y = (center.y / texel).round() * texel;
+    // view space looks at -z
+    let depth = -center.z;
+    let proj = cgmath::ortho(
+        x - extent,
+        x + extent,
+        y - extent,
+        y + extent,
+        depth - radius - caster_margin,
+        depth + radius,
+    );
+    Projection::OPENGL_TO_WGPU_MATRIX * proj * view
+}
+
+pub fn spot_light_matrix<A: Into<cgmath::Rad<f32>>>(
+    position: Vector3<f32>,
+    direction: Vector3<f32>,
+    outer_angle: A,
+    far: f32,
+) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    let fovy = cgmath::Rad((outer_angle.into().0 * 2.0).min(179.0f32.to_radians()));
+    let view = Matrix4::look_at_dir(Point3::from_vec(position), direction, up_for(direction));
+    let proj = cgmath::perspective(fovy, 1.0, ShadowMaps::NEAR, far);
+    Projection::OPENGL_TO_WGPU_MATRIX * proj * view
+}
+
+/// cube faces in +x, -x, +y, -y, +z, -z order (must be same as shaders)
+pub fn point_light_matrices(position: Vector3<f32>, far: f32) -> [Matrix4<f32>; 6] {
+    let eye = Point3::from_vec(position);
+    let proj = Projection::OPENGL_TO_WGPU_MATRIX
+        * cgmath::perspective(cgmath::Deg(90.0), 1.0, ShadowMaps::NEAR, far);
+    let face = |dir: Vector3<f32>, up: Vector3<f32>| proj * Matrix4::look_at_dir(eye, dir, up);
+    [
+        face(Vector3::unit_x(), -Vector3::unit_y()),
+        face(-Vector3::unit_x(), -Vector3::unit_y()),
+        face(Vector3::unit_y(), Vector3::unit_z()),
+        face(-Vector3::unit_y(), -Vector3::unit_z()),
+        face(Vector3::unit_z(), -Vector3::unit_y()),
+        face(-Vector3::unit_z(), -Vector3::unit_y()),
+    ]
+}
+
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+#[test]
+fn test_cascade_splits() {
+    let splits = cascade_splits(0.1, 100.0, 4, 0.5);
+    assert_eq!(splits.len(), 5);
+    assert!((splits[0] - 0.1).abs() < 1e-5);
+    assert!((splits[4] - 100.0).abs() < 1e-3);
+    assert!(splits.windows(2).all(|s| s[0] < s[1]));
+
+    let uniform = cascade_splits(1.0, 9.0, 2, 0.0);
+    assert!((uniform[1] - 5.0).abs() < 1e-5);
+    let log = cascade_splits(1.0, 9.0, 2, 1.0);
+    assert!((log[1] - 3.0).abs() < 1e-5);
+}
+
+#[test]
+fn test_directional_light_matrix_covers_cascade() {
+    let view = Matrix4::look_at(
+        Point3::new(0.0, 5.0, 10.0),
+        Point3::origin(),
+        Vector3::unit_y(),
+    );
+    let splits = cascade_splits(0.1, 50.0, 3, 0.75);
+    for split in splits.windows(2) {
+        let proj = Projection::OPENGL_TO_WGPU_MATRIX
+            * cgmath::perspective(cgmath::Deg(45.0), 4.0 / 3.0, split[0], split[1]);
+        let corners = frustum_corners(proj * view);
+        let light = directional_light_matrix(Vector3::new(-1.0, -1.0, 0.5), &corners, 1024, 0.0);
+        for corner in corners.iter() {
+            let p = light.transform_point(*corner);
+            assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0, "{:?}", p);
+            assert!(p.z >= -1e-4 && p.z <= 1.0 + 1e-4, "{:?}", p);
+        }
+    }
+}
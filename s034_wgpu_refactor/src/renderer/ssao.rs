@@ -0,0 +1,502 @@
+//! screen-space ambient occlusion
+//!
+//! a depth prepass of the scene is rendered to "ssao_depth", the occlusion of a hemisphere around
+//! each pixel is estimated from the depth with a random kernel, and the noisy result is blurred
+//! without crossing depth edges. the forward Blinn-Phong shader multiplies its ambient term by it.
+//! kernel and noise come from a seeded generator so that offline renders are reproducible
+use nannou::math::cgmath::{Vector2, Vector3, Vector4};
+use nannou::prelude::*;
+use std::sync::Arc;
+
+use super::camera::Camera;
+use super::environment::f32_to_f16;
+use super::geom::Geom;
+use super::graph::{GraphResources, TextureDesc, TextureSize};
+use super::instance::Instances;
+use super::mesh_draw::{BindGroupKind, DrawLayout, DrawLayoutBuilder, DrawMesh};
+use super::pipeline::{PipelineCache, PipelineDesc};
+use super::vertex::{Vertex, VertexDescription};
+
+pub const DEPTH: &str = "ssao_depth";
+pub const OCCLUSION: &str = "ssao";
+pub const BLURRED: &str = "ssao_blur";
+
+/// must be same as `shaders/ssao.frag`
+pub const MAX_KERNEL_SIZE: usize = 64;
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+const NOISE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// SplitMix64. small and stable across platforms and crate versions
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// samples in the unit hemisphere around +z, denser near the center
+pub fn generate_kernel(size: usize, rng: &mut SplitMix64) -> Vec<Vector3<f32>> {
+    (0..size)
+        .map(|i| {
+            let mut sample = Vector3::zero();
+            while sample.magnitude2() < 1e-6 {
+                sample = Vector3::new(
+                    rng.next_f32() * 2.0 - 1.0,
+                    rng.next_f32() * 2.0 - 1.0,
+                    rng.next_f32(),
+                );
+            }
+            let scale = i as f32 / size as f32;
+            sample.normalize() * rng.next_f32() * (0.1 + 0.9 * scale * scale)
+        })
+        .collect()
+}
+
+/// unit vectors in the xy plane which rotate the kernel per pixel. tiled over the screen
+pub fn generate_noise(size: u32, rng: &mut SplitMix64) -> Vec<Vector2<f32>> {
+    (0..size * size)
+        .map(|_| {
+            let angle = rng.next_f32() * 2.0 * std::f32::consts::PI;
+            Vector2::new(angle.cos(), angle.sin())
+        })
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsaoConfig {
+    /// up to `MAX_KERNEL_SIZE`
+    pub kernel_size: usize,
+    /// view space radius of the hemisphere
+    pub radius: f32,
+    /// avoids self occlusion on flat surfaces
+    pub bias: f32,
+    /// exponent of the visibility
+    pub intensity: f32,
+    /// width of the tiled noise texture. only applied on `Ssao::new`
+    pub noise_size: u32,
+    /// the blur is (2 * radius + 1)^2 taps
+    pub blur_radius: u32,
+    /// view space depth difference where the blur weight falls off
+    pub blur_depth_sigma: f32,
+    pub seed: u64,
+}
+
+impl Default for SsaoConfig {
+    fn default() -> Self {
+        Self {
+            kernel_size: 32,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+            noise_size: 4,
+            blur_radius: 2,
+            blur_depth_sigma: 0.1,
+            seed: 0,
+        }
+    }
+}
+
+impl SsaoConfig {
+    pub fn to_raw(&self) -> SsaoRaw {
+        let kernel_size = self.kernel_size.min(MAX_KERNEL_SIZE);
+        let mut kernel = [Vector4::zero(); MAX_KERNEL_SIZE];
+        let mut rng = SplitMix64::new(self.seed);
+        for (k, sample) in kernel
+            .iter_mut()
+            .zip(generate_kernel(kernel_size, &mut rng))
+        {
+            *k = sample.extend(0.0);
+        }
+        SsaoRaw {
+            kernel,
+            params: Vector4::new(self.radius, self.bias, self.intensity, kernel_size as f32),
+            blur: Vector4::new(
+                self.noise_size as f32,
+                self.blur_radius as f32,
+                self.blur_depth_sigma,
+                0.0,
+            ),
+        }
+    }
+
+    /// graph textures written by `Ssao::render`
+    pub fn descs() -> Vec<(&'static str, TextureDesc)> {
+        let usage = wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED;
+        vec![
+            (
+                DEPTH,
+                TextureDesc::new(DEPTH_FORMAT, TextureSize::Surface, usage),
+            ),
+            (
+                OCCLUSION,
+                TextureDesc::new(OCCLUSION_FORMAT, TextureSize::Surface, usage),
+            ),
+            (
+                BLURRED,
+                TextureDesc::new(OCCLUSION_FORMAT, TextureSize::Surface, usage),
+            ),
+        ]
+    }
+}
+
+/// must be same as `SsaoParams` in `shaders/ssao.frag` and `shaders/ssao_blur.frag`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SsaoRaw {
+    pub kernel: [Vector4<f32>; MAX_KERNEL_SIZE],
+    /// x: radius, y: bias, z: intensity, w: kernel size
+    pub params: Vector4<f32>,
+    /// x: noise size, y: blur radius, z: blur depth sigma
+    pub blur: Vector4<f32>,
+}
+unsafe impl bytemuck::Zeroable for SsaoRaw {}
+unsafe impl bytemuck::Pod for SsaoRaw {}
+
+pub struct Ssao {
+    config: SsaoConfig,
+    enabled: bool,
+    uniform_buffer: wgpu::Buffer,
+    _noise_texture: wgpu::Texture,
+    noise_view: wgpu::TextureView,
+    /// white. used while disabled
+    _fallback_texture: wgpu::Texture,
+    fallback_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    /// shared by the occlusion and the blur passes
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// camera and the blurred occlusion. replaces the camera bind group of the forward shader
+    frame_bind_group_layout: wgpu::BindGroupLayout,
+    occlusion_bind_group: Option<wgpu::BindGroup>,
+    blur_bind_group: Option<wgpu::BindGroup>,
+    frame_bind_group: wgpu::BindGroup,
+    prepass_pipeline: Arc<wgpu::RenderPipeline>,
+    prepass_layout: DrawLayout,
+    occlusion_pipeline: Arc<wgpu::RenderPipeline>,
+    blur_pipeline: Arc<wgpu::RenderPipeline>,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut PipelineCache,
+        camera: &Camera,
+        instances: &Instances,
+        config: SsaoConfig,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ssao"),
+            contents: bytemuck::cast_slice(&[config.to_raw()]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        // a stream of its own, derived from `seed` like the kernel's
+        let mut rng = SplitMix64::new(config.seed ^ 0x55a0);
+        let noise = generate_noise(config.noise_size, &mut rng)
+            .iter()
+            .flat_map(|n| vec![n.x, n.y, 0.0, 0.0])
+            .collect::<Vec<_>>();
+        let noise_texture = Self::create_texture(device, queue, config.noise_size, &noise);
+        let fallback_texture = Self::create_texture(device, queue, 1, &[1.0, 1.0, 1.0, 1.0]);
+        let noise_view = noise_texture.view().build();
+        let fallback_view = fallback_texture.view().build();
+        // everything is read with texelFetch
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ssao"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture = |builder: wgpu::BindGroupLayoutBuilder| {
+            builder.sampled_texture(
+                wgpu::ShaderStage::FRAGMENT,
+                false,
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureComponentType::Float,
+            )
+        };
+        let stage = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+        let builder = wgpu::BindGroupLayoutBuilder::new()
+            .uniform_buffer(stage, false)
+            .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false);
+        let bind_group_layout = texture(texture(builder))
+            .sampler(wgpu::ShaderStage::FRAGMENT)
+            .build(device);
+        let frame_bind_group_layout =
+            texture(wgpu::BindGroupLayoutBuilder::new().uniform_buffer(stage, false))
+                .sampler(wgpu::ShaderStage::FRAGMENT)
+                .build(device);
+
+        let prepass_layout = DrawLayoutBuilder::new()
+            .group(BindGroupKind::Camera, camera.binding.bind_group_layout())
+            .group(
+                BindGroupKind::Instances,
+                instances.binding.bind_group_layout(),
+            );
+        let prepass_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &PipelineDesc::new(
+                "ssao_depth",
+                include_bytes!("../../shaders/shader.vert.spv"),
+            )
+            .vertex_buffer(Vertex::desc())
+            .cull_mode(wgpu::CullMode::Back)
+            .depth_format(DEPTH_FORMAT),
+            prepass_layout.bind_group_layouts(),
+        );
+        let fullscreen =
+            PipelineDesc::new("ssao", include_bytes!("../../shaders/fullscreen.vert.spv"))
+                .color_format(OCCLUSION_FORMAT);
+        let occlusion_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &fullscreen
+                .clone()
+                .fragment_shader(include_bytes!("../../shaders/ssao.frag.spv")),
+            &[&bind_group_layout],
+        );
+        let blur_pipeline = pipeline_cache.render_pipeline(
+            device,
+            &fullscreen.fragment_shader(include_bytes!("../../shaders/ssao_blur.frag.spv")),
+            &[&bind_group_layout],
+        );
+
+        let frame_bind_group = wgpu::BindGroupBuilder::new()
+            .binding(wgpu::BindingResource::Buffer(
+                Self::camera_buffer(camera).slice(..),
+            ))
+            .texture_view(&fallback_view)
+            .sampler(&sampler)
+            .build(device, &frame_bind_group_layout);
+
+        Self {
+            config,
+            enabled: false,
+            uniform_buffer,
+            _noise_texture: noise_texture,
+            noise_view,
+            _fallback_texture: fallback_texture,
+            fallback_view,
+            sampler,
+            bind_group_layout,
+            frame_bind_group_layout,
+            occlusion_bind_group: None,
+            blur_bind_group: None,
+            frame_bind_group,
+            prepass_pipeline,
+            prepass_layout: prepass_layout.build(),
+            occlusion_pipeline,
+            blur_pipeline,
+        }
+    }
+
+    pub fn config(&self) -> &SsaoConfig {
+        &self.config
+    }
+
+    /// `noise_size` is only applied on `new`
+    /// the noise texture keeps the `seed` and `noise_size` given to `new`
+    pub fn set_config(&mut self, queue: &wgpu::Queue, config: SsaoConfig) {
+        self.config = config;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[config.to_raw()]),
+        );
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// the graph has to be built after this, since the textures written by the pass change
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn frame_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.frame_bind_group_layout
+    }
+
+    /// set 0 of the forward Blinn-Phong pipeline
+    pub fn frame_bind_group(&self) -> &wgpu::BindGroup {
+        &self.frame_bind_group
+    }
+
+    pub fn bind(&mut self, device: &wgpu::Device, resources: &GraphResources, camera: &Camera) {
+        let camera_buffer = Self::camera_buffer(camera);
+        let occlusion: &wgpu::TextureViewHandle = if self.enabled {
+            let uniform_buffer = &self.uniform_buffer;
+            let sampler = &self.sampler;
+            let layout = &self.bind_group_layout;
+            let builder = |texture: &wgpu::TextureViewHandle| {
+                wgpu::BindGroupBuilder::new()
+                    .binding(wgpu::BindingResource::Buffer(camera_buffer.slice(..)))
+                    .binding(wgpu::BindingResource::Buffer(uniform_buffer.slice(..)))
+                    .texture_view(resources.view(DEPTH))
+                    .texture_view(texture)
+                    .sampler(sampler)
+                    .build(device, layout)
+            };
+            self.occlusion_bind_group = Some(builder(&self.noise_view));
+            self.blur_bind_group = Some(builder(resources.view(OCCLUSION)));
+            resources.view(BLURRED)
+        } else {
+            self.occlusion_bind_group = None;
+            self.blur_bind_group = None;
+            &self.fallback_view
+        };
+        self.frame_bind_group = wgpu::BindGroupBuilder::new()
+            .binding(wgpu::BindingResource::Buffer(camera_buffer.slice(..)))
+            .texture_view(occlusion)
+            .sampler(&self.sampler)
+            .build(device, &self.frame_bind_group_layout);
+    }
+
+    /// depth prepass, occlusion and blur. nothing is rendered while disabled
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &GraphResources,
+        geom: &Geom,
+        instances: &Instances,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let (occlusion_bind_group, blur_bind_group) =
+            match (&self.occlusion_bind_group, &self.blur_bind_group) {
+                (Some(occlusion), Some(blur)) => (occlusion, blur),
+                _ => return,
+            };
+
+        let mut prepass = wgpu::RenderPassBuilder::new()
+            .depth_stencil_attachment(resources.view(DEPTH), |depth| {
+                depth
+                    .depth_load_op(wgpu::LoadOp::Clear(1.0))
+                    .depth_store_op(true)
+            })
+            .begin(encoder);
+        prepass.set_pipeline(&self.prepass_pipeline);
+        prepass
+            .draw_geom(geom)
+            .layout(&self.prepass_layout)
+            .camera(camera_bind_group)
+            .instances(
+                instances.binding.bind_group(),
+                0..instances.instances.len() as u32,
+            )
+            .submit();
+        drop(prepass);
+
+        for (pipeline, bind_group, target) in &[
+            (&self.occlusion_pipeline, occlusion_bind_group, OCCLUSION),
+            (&self.blur_pipeline, blur_bind_group, BLURRED),
+        ] {
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .color_attachment(resources.view(target), |color| {
+                    color.load_op(wgpu::LoadOp::Clear(wgpu::Color::WHITE))
+                })
+                .begin(encoder);
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn camera_buffer(camera: &Camera) -> &wgpu::Buffer {
+        &camera.binding.buffers[camera.binding.label_index["camera_view_proj"]]
+    }
+
+    /// rgba
+    fn create_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        data: &[f32],
+    ) -> wgpu::Texture {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: 1,
+        };
+        let texture = wgpu::TextureBuilder::new()
+            .extent(extent)
+            .mip_level_count(1)
+            .sample_count(1)
+            .dimension(wgpu::TextureDimension::D2)
+            .format(NOISE_FORMAT)
+            .usage(wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)
+            .build(device);
+        let data = data.iter().cloned().map(f32_to_f16).collect::<Vec<u16>>();
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&data),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 8 * size, // rgba16
+                rows_per_image: size,
+            },
+            extent,
+        );
+        texture
+    }
+}
+
+#[test]
+fn test_ssao_kernel() {
+    let kernel = generate_kernel(64, &mut SplitMix64::new(7));
+    assert_eq!(kernel.len(), 64);
+    assert!(kernel.iter().all(|s| s.z >= 0.0 && s.magnitude() <= 1.0));
+    // denser near the center
+    let inner = kernel[..32].iter().map(|s| s.magnitude()).sum::<f32>();
+    let outer = kernel[32..].iter().map(|s| s.magnitude()).sum::<f32>();
+    assert!(inner < outer);
+
+    // reproducible with the same seed only
+    assert_eq!(kernel, generate_kernel(64, &mut SplitMix64::new(7)));
+    assert_ne!(kernel, generate_kernel(64, &mut SplitMix64::new(8)));
+    assert_eq!(
+        SsaoConfig::default().to_raw().kernel[..],
+        SsaoConfig::default().to_raw().kernel[..]
+    );
+
+    let noise = generate_noise(4, &mut SplitMix64::new(7));
+    assert_eq!(noise.len(), 16);
+    assert!(noise.iter().all(|n| (n.magnitude() - 1.0).abs() < 1e-5));
+
+    // reference values of SplitMix64 with seed 0
+    let mut rng = SplitMix64::new(0);
+    assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+    assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+    assert!((0..1000)
+        .map(|_| rng.next_f32())
+        .all(|x| x >= 0.0 && x < 1.0));
+
+    let config = SsaoConfig {
+        kernel_size: 100,
+        ..Default::default()
+    };
+    assert_eq!(config.to_raw().params.w, MAX_KERNEL_SIZE as f32);
+}
@@ -0,0 +1,299 @@
+//! renders images larger than the maximum texture size in tiles
+//!
+//! the image is split into a grid of tiles of the same size. each is rendered through its part of
+//! the camera frustum, see `ProjectionTile`, and stitched back together. tiles at the right and
+//! bottom edges reach past the image and are cropped. the post effects which depend on the
+//! position on the screen are given the tile, see `PostStack::set_tile`, and the ones which read
+//! the neighbouring pixels are left out, see `Effect::reads_neighbours`. SSAO still only sees
+//! its own tile, so it may show seams
+use anyhow::*;
+use std::io::Write;
+
+use super::camera::ProjectionTile;
+use super::manifest;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TileGrid {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+impl TileGrid {
+    /// the fewest tiles of at most `max_tile` a side, split evenly
+    pub fn new(width: u32, height: u32, max_tile: u32) -> Self {
+        assert!(width > 0 && height > 0 && max_tile > 0, "empty tile grid");
+        let split = |size: u32| {
+            let count = (size + max_tile - 1) / max_tile;
+            (size + count - 1) / count
+        };
+        Self {
+            width,
+            height,
+            tile_width: split(width),
+            tile_height: split(height),
+        }
+    }
+
+    pub fn columns(&self) -> u32 {
+        (self.width + self.tile_width - 1) / self.tile_width
+    }
+
+    pub fn rows(&self) -> u32 {
+        (self.height + self.tile_height - 1) / self.tile_height
+    }
+
+    pub fn tile(&self, column: u32, row: u32) -> Tile {
+        let x = column * self.tile_width;
+        let y = row * self.tile_height;
+        Tile {
+            column,
+            row,
+            x,
+            y,
+            width: self.tile_width.min(self.width - x),
+            height: self.tile_height.min(self.height - y),
+        }
+    }
+
+    /// row by row from the top left
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        (0..self.rows()).flat_map(move |row| (0..self.columns()).map(move |c| self.tile(c, row)))
+    }
+}
+
+/// `width` and `height` are the part inside the image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tile {
+    pub column: u32,
+    pub row: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// of the whole tile, so that every tile is rendered with the same size
+    pub fn projection(&self, grid: &TileGrid) -> ProjectionTile {
+        ProjectionTile {
+            x: self.x as f32 / grid.width as f32,
+            y: self.y as f32 / grid.height as f32,
+            width: grid.tile_width as f32 / grid.width as f32,
+            height: grid.tile_height as f32 / grid.height as f32,
+        }
+    }
+}
+
+/// copies the part of `pixels` inside the image to `image`, whose top row is at `top` of the
+/// whole image
+pub fn stitch(image: &mut image::RgbaImage, top: u32, tile: &Tile, pixels: &image::RgbaImage) {
+    let image_width = image.width() as usize;
+    let pixels_width = pixels.width() as usize;
+    let row_bytes = tile.width as usize * BYTES_PER_PIXEL;
+    let (image, pixels) = (&mut **image, &**pixels);
+    for y in 0..tile.height as usize {
+        let src = y * pixels_width * BYTES_PER_PIXEL;
+        let dst = ((tile.y - top) as usize + y) * image_width + tile.x as usize;
+        let dst = dst * BYTES_PER_PIXEL;
+        image[dst..dst + row_bytes].copy_from_slice(&pixels[src..src + row_bytes]);
+    }
+}
+
+/// stitches the tiles of one row at a time and streams them to a PNG, so that only one row of
+/// tiles is kept in memory
+pub struct StripWriter<W: Write + 'static> {
+    grid: TileGrid,
+    writer: png::StreamWriter<'static, W>,
+    strip: image::RgbaImage,
+    /// the next tile to be written
+    next: (u32, u32),
+}
+
+impl<W: Write + 'static> StripWriter<W> {
+    /// `text` is written as tEXt chunks before the pixels, see `manifest::write_png`
+    pub fn new(writer: W, grid: TileGrid, text: &[(String, String)]) -> Result<Self> {
+        let mut encoder = png::Encoder::new(writer, grid.width, grid.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        for (keyword, text) in text {
+            writer.write_chunk(*b"tEXt", &manifest::text_chunk(keyword, text)?)?;
+        }
+        let writer = writer.into_stream_writer();
+        Ok(Self {
+            grid,
+            writer,
+            strip: image::RgbaImage::new(grid.width, grid.tile_height),
+            next: (0, 0),
+        })
+    }
+
+    /// `pixels` of `tile_width` x `tile_height`. tiles must be written in the order of
+    /// `TileGrid::tiles`
+    pub fn write_tile(&mut self, tile: &Tile, pixels: &image::RgbaImage) -> Result<()> {
+        if (tile.column, tile.row) != self.next {
+            bail!(
+                "Expected tile {:?} but got {:?}",
+                self.next,
+                (tile.column, tile.row)
+            );
+        }
+        if pixels.dimensions() != (self.grid.tile_width, self.grid.tile_height) {
+            bail!("Unexpected tile size {:?}", pixels.dimensions());
+        }
+        stitch(&mut self.strip, tile.y, tile, pixels);
+
+        self.next = (tile.column + 1, tile.row);
+        if self.next.0 == self.grid.columns() {
+            let bytes = (self.grid.width * tile.height) as usize * BYTES_PER_PIXEL;
+            self.writer.write_all(&self.strip.as_raw()[..bytes])?;
+            self.next = (0, tile.row + 1);
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if self.next.1 != self.grid.rows() {
+            bail!("{} of {} tile rows written", self.next.1, self.grid.rows());
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tiled_projection() {
+    use super::camera::Projection;
+    use nannou::math::cgmath::{Deg, InnerSpace, Vector4};
+
+    let grid = TileGrid::new(1000, 500, 384);
+    assert_eq!((grid.columns(), grid.rows()), (3, 2));
+    assert_eq!((grid.tile_width, grid.tile_height), (334, 250));
+    let tiles = grid.tiles().collect::<Vec<_>>();
+    assert_eq!(tiles.len(), 6);
+    assert_eq!(tiles[2].x, 668);
+    assert_eq!(tiles[2].width, 332);
+    assert_eq!(
+        tiles.iter().map(|t| t.width * t.height).sum::<u32>(),
+        1000 * 500
+    );
+    assert_eq!(TileGrid::new(64, 64, 64).tiles().count(), 1);
+
+    let full = Projection::new(grid.width, grid.height, Deg(60.0), 0.1, 100.0);
+    let mut whole = Projection::new(grid.width, grid.height, Deg(60.0), 0.1, 100.0);
+    whole.tile = Some(ProjectionTile {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    });
+    let (a, b) = (full.projection_matrix(), whole.projection_matrix());
+    for i in 0..4 {
+        assert!((a[i] - b[i]).magnitude() < 1e-5);
+    }
+
+    // a point lands on the same pixel of the image whether it's rendered whole or in tiles
+    let pixel = |projection: &Projection, width: u32, height: u32, point: Vector4<f32>| {
+        let clip = projection.projection_matrix() * point;
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        (
+            (x + 1.0) * 0.5 * width as f32,
+            (1.0 - y) * 0.5 * height as f32,
+        )
+    };
+    let point = Vector4::new(2.0, -1.0, -5.0, 1.0);
+    let (x, y) = pixel(&full, grid.width, grid.height, point);
+    let tile = grid.tile(x as u32 / grid.tile_width, y as u32 / grid.tile_height);
+    let mut tiled = Projection::new(grid.width, grid.height, Deg(60.0), 0.1, 100.0);
+    tiled.tile = Some(tile.projection(&grid));
+    let (tx, ty) = pixel(&tiled, grid.tile_width, grid.tile_height, point);
+    assert!((tile.x as f32 + tx - x).abs() < 1e-2, "{} {}", tx, x);
+    assert!((tile.y as f32 + ty - y).abs() < 1e-2, "{} {}", ty, y);
+}
+
+#[test]
+fn test_tiled_strip_writer() {
+    let grid = TileGrid::new(7, 5, 3);
+    let expected = image::RgbaImage::from_fn(7, 5, |x, y| image::Rgba([x as u8, y as u8, 9, 255]));
+    // tiles rendered past the image are filled with garbage
+    let render = |tile: &Tile| {
+        image::RgbaImage::from_fn(grid.tile_width, grid.tile_height, |x, y| {
+            let (x, y) = (tile.x + x, tile.y + y);
+            if x < grid.width && y < grid.height {
+                *expected.get_pixel(x, y)
+            } else {
+                image::Rgba([255, 0, 255, 255])
+            }
+        })
+    };
+
+    let mut stitched = image::RgbaImage::new(7, 5);
+    for tile in grid.tiles() {
+        stitch(&mut stitched, 0, &tile, &render(&tile));
+    }
+    assert_eq!(stitched, expected);
+
+    let dir = crate::test_util::TempDir::new("tiled");
+    let path = dir.join("tiled.png");
+    let text = vec![("Size".to_string(), "7x5".to_string())];
+    let file = std::fs::File::create(&path).unwrap();
+    let mut writer = StripWriter::new(file, grid, &text).unwrap();
+    let tiles = grid.tiles().collect::<Vec<_>>();
+    assert!(writer.write_tile(&tiles[1], &render(&tiles[1])).is_err());
+    for tile in &tiles {
+        writer.write_tile(tile, &render(tile)).unwrap();
+    }
+    writer.finish().unwrap();
+    let decoded = image::open(&path).unwrap().to_rgba8();
+    assert_eq!(decoded, expected);
+    let png = std::fs::read(&path).unwrap();
+    assert_eq!(manifest::read_png_text(&png).unwrap(), text);
+
+    let writer = StripWriter::new(Vec::new(), grid, &[]).unwrap();
+    assert!(writer.finish().is_err());
+}
+
+#[test]
+fn test_tiled_post_effects() {
+    use super::post;
+
+    // the positions of shaders/post_vignette.frag and shaders/post_film_grain.frag
+    let shade = |params: [f32; 4], size: [u32; 2], x: u32, y: u32| {
+        let (width, height) = (size[0] as f32, size[1] as f32);
+        let (u, v) = ((x as f32 + 0.5) / width, (y as f32 + 0.5) / height);
+        let screen = (params[0] + u * params[2], params[1] + v * params[3]);
+        let d = ((screen.0 - 0.5).abs(), (screen.1 - 0.5).abs());
+        let vignette = 1.0 - (d.0 * d.0 + d.1 * d.1);
+        let pixel = (
+            x as f32 + (params[0] * width / params[2]).round(),
+            y as f32 + (params[1] * height / params[3]).round(),
+        );
+        let noise = ((pixel.0 * 12.9898 + pixel.1 * 78.233).sin() * 43758.5453).abs();
+        let channel = |value: f32| (value * 255.0).round() as u8;
+        image::Rgba([channel(vignette), channel(noise.fract()), 0, 255])
+    };
+    let render = |grid: TileGrid| {
+        let mut image = image::RgbaImage::new(grid.width, grid.height);
+        for tile in grid.tiles() {
+            let params = post::tile_params(Some(tile.projection(&grid)));
+            let size = [grid.tile_width, grid.tile_height];
+            let pixels =
+                image::RgbaImage::from_fn(size[0], size[1], |x, y| shade(params, size, x, y));
+            stitch(&mut image, 0, &tile, &pixels);
+        }
+        image
+    };
+
+    let whole = TileGrid::new(7, 5, 7);
+    assert_eq!((whole.columns(), whole.rows()), (1, 1));
+    let projection = whole.tile(0, 0).projection(&whole);
+    assert_eq!(post::tile_params(Some(projection)), post::tile_params(None));
+    // the tiles at the right and bottom edges are cropped
+    let tiled = TileGrid::new(7, 5, 4);
+    assert_eq!((tiled.columns(), tiled.rows()), (2, 2));
+    assert_eq!(render(whole), render(tiled));
+}
@@ -36,6 +36,10 @@ impl Vertex {
             bitangent: bitangent.into(),
         }
     }
+
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        self.position
+    }
 }
 
 impl VertexDescription for Vertex {
@@ -0,0 +1,50 @@
+//! helpers shared by the tests
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// empty directory in the temp dir, removed with its contents on drop, so a failing test
+/// doesn't leave it behind. the name is unique per test and process
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let n = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let dir = format!("{}_{}_{}", name, std::process::id(), n);
+        let path = std::env::temp_dir().join(dir);
+        // left by an earlier process with the same id
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[test]
+fn test_temp_dir() {
+    let a = TempDir::new("test_util");
+    let b = TempDir::new("test_util");
+    assert_ne!(&*a, &*b);
+    std::fs::create_dir_all(a.join("nested")).unwrap();
+    std::fs::write(a.join("nested/file"), b"").unwrap();
+    let path = a.to_path_buf();
+    drop(a);
+    assert!(!path.exists() && b.exists());
+}